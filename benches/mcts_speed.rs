@@ -4,10 +4,12 @@ use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use rand::Rng;
 use xoxo::core::Player;
 use xoxo::game::{connect_four::C4Board, run_blitz_game};
-use xoxo::player::{MctsAi, RandomAi};
+use xoxo::player::{MctsAi, MctsEngine, RandomAi};
 
-fn mcts_move() {
-    let mut ai: MctsAi<C4Board> = MctsAi::new(123, 2.0, None);
+fn mcts_move(engine: MctsEngine, rave: Option<f64>) {
+    let mut ai: MctsAi<C4Board> = MctsAi::new(123, 2.0);
+    ai.set_engine(engine);
+    ai.set_rave(rave);
     ai.set_play_steps(2000);
     let board = C4Board::default();
     ai.play(&board);
@@ -21,7 +23,8 @@ fn mcts_io() {
     let mut rng = rand::thread_rng();
 
     for _k in 0..5 {
-        let ai1: MctsAi<C4Board> = MctsAi::new(rng.gen(), 2.0, Some(FILENAME1.into()));
+        let mut ai1: MctsAi<C4Board> = MctsAi::new(rng.gen(), 2.0);
+        ai1.load_memory(FILENAME1).unwrap();
         let ai2 = RandomAi::new(rng.gen());
         run_blitz_game::<C4Board>(
             Box::new(ai1),
@@ -44,7 +47,15 @@ fn criterion_benchmark(c: &mut Criterion) {
     }));
     group.sample_size(300);
     group.bench_function("mcts-c4-play", |b| b.iter(|| {
-        mcts_move();
+        mcts_move(MctsEngine::QMap, None);
+        black_box(())
+    }));
+    group.bench_function("mcts-c4-play-rave", |b| b.iter(|| {
+        mcts_move(MctsEngine::QMap, Some(300.0));
+        black_box(())
+    }));
+    group.bench_function("mcts-c4-play-tree", |b| b.iter(|| {
+        mcts_move(MctsEngine::Tree, None);
         black_box(())
     }));
     group.finish();
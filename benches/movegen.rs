@@ -0,0 +1,49 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use xoxo::core::Board;
+use xoxo::game::{connect_four::C4Board, tictactoe::TTTBoard, ultimate_ttt::UTTTBoard};
+
+/// Play a random game, allocating a fresh move list every turn
+fn playout_alloc<B: Board>(rng: &mut StdRng) -> B {
+    let mut board = B::default();
+    while !board.game_is_over() {
+        let moves = board.valid_moves();
+        let m = moves[rng.gen_range(0..moves.len())];
+        board.place_mark(m, board.current_player());
+    }
+    board
+}
+
+/// Play a random game, reusing one move buffer for the whole game
+fn playout_buffer<B: Board>(rng: &mut StdRng, moves: &mut Vec<B::Coordinate>) -> B {
+    let mut board = B::default();
+    while !board.game_is_over() {
+        board.valid_moves_into(moves);
+        let m = moves[rng.gen_range(0..moves.len())];
+        board.place_mark(m, board.current_player());
+    }
+    board
+}
+
+fn bench_game<B: Board>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(format!("movegen-{}", name));
+    let mut rng = StdRng::seed_from_u64(123);
+    group.bench_function("playout-alloc", |b| {
+        b.iter(|| black_box(playout_alloc::<B>(&mut rng)))
+    });
+    let mut rng = StdRng::seed_from_u64(123);
+    let mut moves = Vec::new();
+    group.bench_function("playout-buffer", |b| {
+        b.iter(|| black_box(playout_buffer::<B>(&mut rng, &mut moves)))
+    });
+    group.finish();
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    bench_game::<TTTBoard>(c, "ttt");
+    bench_game::<C4Board>(c, "c4");
+    bench_game::<UTTTBoard>(c, "uttt");
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use xoxo::core::Board;
+use xoxo::game::connect_four::C4Board;
+use xoxo::player::{c4_heuristic, c4_move_prior, ABAi};
+
+/// A depth 7 search of a Connect Four opening, on 1, 2, 4 and 8 threads
+fn criterion_benchmark(c: &mut Criterion) {
+    let mut board = C4Board::default();
+    for col in [3, 3, 2, 4] {
+        board.place_mark(col, board.current_player());
+    }
+    let mut group = c.benchmark_group("parallel-ab-c4");
+    group.sample_size(10);
+    for threads in [1, 2, 4, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            b.iter(|| {
+                let mut ai = ABAi::<C4Board>::new(c4_heuristic, 7);
+                ai.set_move_prior(Some(c4_move_prior));
+                ai.set_killer_moves(true);
+                ai.set_threads(threads);
+                black_box(ai.search(&board))
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -1,7 +1,7 @@
 set -e
 cargo build --release --bin arena
 rm -f score.csv
-rm -f *.data
+rm -f *.data *.data.idx *.data.lock
 # players=("random" "ab4" "ab6" "minimax4" "mcts1" "mcts2" "mcts3");
 players=("ab6" "mcts1" "mcts2" "mcts3");
 game=c4
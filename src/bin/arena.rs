@@ -7,13 +7,18 @@ use enum_iterator::{all, cardinality, Sequence};
 use rand::rngs::ThreadRng;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::io::Seek;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use xoxo::{
-    core::{BlitzPlayer, GameEndStatus, GameType, PlayerMark},
+    core::{BlitzPlayer, Board, GameEndReason, GameEndStatus, GameType, HeuristicFn, MovePriorFn, PlayerMark},
     game::{connect_four::C4Board, run_blitz_game, tictactoe::TTTBoard, ultimate_ttt::UTTTBoard},
-    player::{c4_heuristic, ttt_heuristic, uttt_heuristic, ABAi, MctsAi, MinMaxAi, RandomAi},
+    player::{
+        c4_heuristic, c4_move_prior, ttt_heuristic, ttt_move_prior, uttt_heuristic, uttt_move_prior,
+        mcts::{Mdp, StateSummary}, merge_memory_files, read_memory_file, ABAi, Eviction, MctsAi, MemoryLimit, MinMaxAi, Parallelism, RandomAi,
+    },
 };
 
 #[derive(Parser, Debug)]
@@ -45,11 +50,75 @@ enum Commands {
         /// what kind of player is player? See alternatives for p1
         #[arg(short = 'q', long)]
         player2: PlayerSpec,
+        /// How many threads each AI searches on
+        #[arg(long, default_value = "1")]
+        threads: usize,
+        /// How the MCTS AIs search on more than one thread
+        #[arg(long, default_value = "root")]
+        parallelism: Parallelism,
+        /// Turn on RAVE for the MCTS AIs, with this equivalence parameter
+        #[arg(long)]
+        rave: Option<f64>,
+        /// Keep at most this many states in the memory of each MCTS AI
+        #[arg(long, conflicts_with = "max_bytes")]
+        max_states: Option<usize>,
+        /// Keep at most about this many bytes in the memory of each MCTS AI
+        #[arg(long)]
+        max_bytes: Option<usize>,
+        /// Which states the MCTS AIs evict when their memory is full
+        #[arg(long, default_value = "lru")]
+        eviction: EvictionSpec,
+        /// For `--eviction deep`: evict the states more than this many plies below the position being searched, and those it hasn't reached
+        #[arg(long, default_value = "8")]
+        eviction_depth: usize,
+        /// Where the MCTS AIs keep their memory files. Games that run at the same time need a directory each.
+        #[arg(long, default_value = ".")]
+        memory_dir: PathBuf,
+    },
+    /// Add the memory files of MCTS AIs to another one, summing their statistics
+    Merge {
+        /// The memory file to add to. It is made if it isn't there.
+        #[arg(long)]
+        into: PathBuf,
+        /// The memory files to add. They are left as they are.
+        #[arg(required = true)]
+        from: Vec<PathBuf>,
+    },
+    /// Print what the memory file of an MCTS AI holds: the states by ply, and the most visited ones
+    Inspect {
+        /// The memory file
+        file: PathBuf,
+        /// How many of the most visited states to show
+        #[arg(long, default_value = "5")]
+        top: usize,
+        /// Write all the states to standard output in this format, instead of the summary
+        #[arg(long)]
+        export: Option<ExportFormat>,
     },
     /// Report on the results of the games in the terminal
     Report {},
 }
 
+/// The formats that `inspect` exports to
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// An array of the states, each with its actions
+    Json,
+    /// A row for every action of every state, and for the states without actions
+    Csv,
+}
+
+/// The eviction policies of `Eviction`
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum EvictionSpec {
+    /// The least recently used states
+    Lru,
+    /// The states with the fewest visits
+    LowestVisits,
+    /// The states deeper than `--eviction-depth`, then the least recently used
+    Deep,
+}
+
 #[derive(Debug, Clone, Copy, ValueEnum, Serialize, Deserialize, Sequence)]
 enum PlayerSpec {
     Random,
@@ -78,6 +147,8 @@ struct GameRecord {
     time1: u128,
     /// Time remaining for player 2 when game ended, in microseconds
     time2: u128,
+    /// Why the game ended. Older score files lack this column, see `read_records`.
+    reason: GameEndReason,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -91,13 +162,23 @@ fn main() -> anyhow::Result<()> {
     simple_logger::init_with_level(log_level).unwrap();
     match args.command {
         Commands::Run {
-            player1, player2, ..
+            player1, player2, threads, parallelism, rave, max_states, max_bytes, eviction, eviction_depth, memory_dir,
         } => {
-            let (result, time1, time2)  = match game {
-                GameType::C4 => run_c4(player1, player2),
-                GameType::Ttt => run_ttt(player1, player2),
-                GameType::Uttt => run_uttt(player1, player2),
+            let memory_limit = max_states.map(MemoryLimit::States).or(max_bytes.map(MemoryLimit::Bytes));
+            let eviction = match eviction {
+                EvictionSpec::Lru => Eviction::LeastRecentlyUsed,
+                EvictionSpec::LowestVisits => Eviction::LowestVisits,
+                EvictionSpec::Deep => Eviction::DeeperThan(eviction_depth),
             };
+            if rave.is_some() && parallelism == Parallelism::SharedTree {
+                anyhow::bail!("RAVE needs the QMap engine, but the shared tree searches with the tree engine");
+            }
+            let options = AiOptions { threads, parallelism, rave, memory_limit, eviction, memory_dir };
+            let (result, reason, time1, time2)  = match game {
+                GameType::C4 => run_c4(player1, player2, &options),
+                GameType::Ttt => run_ttt(player1, player2, &options),
+                GameType::Uttt => run_uttt(player1, player2, &options),
+            }?;
             let record = GameRecord {
                 game,
                 player1,
@@ -106,33 +187,194 @@ fn main() -> anyhow::Result<()> {
                 played_at: chrono::Local::now(),
                 time1: time1.as_micros(),
                 time2: time2.as_micros(),
+                reason,
             };
             record_result(&args.outfile, record)
         }
         Commands::Report {} => print_out_report(&args.outfile, game),
+        Commands::Merge { into, from } => {
+            if let Some(missing) = from.iter().find(|f| !f.exists()) {
+                anyhow::bail!("There is no memory file at {}", missing.display());
+            }
+            let n_states = match game {
+                GameType::C4 => merge_memory_files::<C4Board>(&into, &from),
+                GameType::Ttt => merge_memory_files::<TTTBoard>(&into, &from),
+                GameType::Uttt => merge_memory_files::<UTTTBoard>(&into, &from),
+            }
+            .map_err(|e| anyhow::anyhow!("Can't merge the memory files into {}: {e}", into.display()))?;
+            log::info!("Added {n_states} states to {}", into.display());
+            Ok(())
+        }
+        Commands::Inspect { file, top, export } => match game {
+            GameType::C4 => inspect::<C4Board>(&file, top, export),
+            GameType::Ttt => inspect::<TTTBoard>(&file, top, export),
+            GameType::Uttt => inspect::<UTTTBoard>(&file, top, export),
+        },
     }
 }
 
-fn print_out_report(outfile: &PathBuf, game_to_report: GameType) -> anyhow::Result<()> {
+/// A state of a memory file, as `inspect` exports it. The moves and actions are written as the game prints them.
+#[derive(Serialize)]
+struct ExportedState {
+    /// The moves from the start of the game, separated by spaces, if the state can be reached through the file
+    moves: Option<String>,
+    ply: Option<usize>,
+    /// The board, with its lines separated by '/'
+    board: String,
+    visits: f64,
+    proven: Option<f64>,
+    actions: Vec<ExportedAction>,
+}
+
+#[derive(Serialize)]
+struct ExportedAction {
+    action: String,
+    visits: f64,
+    mean: f64,
+    proven: Option<f64>,
+}
+
+/// A row of the CSV that `inspect` exports
+#[derive(Serialize)]
+struct ExportedRow<'a> {
+    moves: Option<&'a str>,
+    ply: Option<usize>,
+    board: &'a str,
+    state_visits: f64,
+    state_proven: Option<f64>,
+    action: Option<&'a str>,
+    visits: Option<f64>,
+    mean: Option<f64>,
+    proven: Option<f64>,
+}
+
+impl<'a> ExportedRow<'a> {
+    fn new(state: &'a ExportedState, action: Option<&'a ExportedAction>) -> Self {
+        ExportedRow {
+            moves: state.moves.as_deref(),
+            ply: state.ply,
+            board: &state.board,
+            state_visits: state.visits,
+            state_proven: state.proven,
+            action: action.map(|a| a.action.as_str()),
+            visits: action.map(|a| a.visits),
+            mean: action.map(|a| a.mean),
+            proven: action.and_then(|a| a.proven),
+        }
+    }
+}
+
+/// Print what the MCTS memory file at `path` holds, or export all of it
+fn inspect<B>(path: &Path, top: usize, export: Option<ExportFormat>) -> anyhow::Result<()>
+where
+    B: Board + Default + Display + Mdp<State = B, Action = <B as Board>::Coordinate>,
+{
+    let states = read_memory_file::<B>(path, &B::default())
+        .map_err(|e| anyhow::anyhow!("Can't read the memory file {}: {e}", path.display()))?;
+    let exported = || {
+        states.iter().map(|s| ExportedState {
+            moves: s.moves.as_ref().map(|m| m.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(" ")),
+            ply: s.ply(),
+            board: s.state.to_string().trim_end().replace('\n', "/"),
+            visits: s.visits,
+            proven: s.proven,
+            actions: s
+                .actions
+                .iter()
+                .map(|a| ExportedAction {
+                    action: a.action.to_string(),
+                    visits: a.visits,
+                    mean: a.mean,
+                    proven: a.proven,
+                })
+                .collect(),
+        })
+    };
+    match export {
+        Some(ExportFormat::Json) => {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &exported().collect::<Vec<_>>())?;
+            println!();
+        }
+        Some(ExportFormat::Csv) => {
+            let mut wtr = csv::Writer::from_writer(std::io::stdout().lock());
+            for state in exported() {
+                if state.actions.is_empty() {
+                    wtr.serialize(ExportedRow::new(&state, None))?;
+                }
+                for action in &state.actions {
+                    wtr.serialize(ExportedRow::new(&state, Some(action)))?;
+                }
+            }
+            wtr.flush()?;
+        }
+        None => print_memory_summary(path, &states, top),
+    }
+    Ok(())
+}
+
+/// Print the number of states and visits at each ply, and the `top` most visited states with their actions
+fn print_memory_summary<B>(path: &Path, states: &[StateSummary<B, B::Coordinate>], top: usize)
+where
+    B: Board + Display,
+{
+    let total_visits: f64 = states.iter().map(|s| s.visits).sum();
+    println!("{}: {} states, {} visits", path.display(), states.len(), total_visits);
+    let mut by_ply: BTreeMap<Option<usize>, (usize, f64)> = BTreeMap::new();
+    for s in states {
+        let (n, visits) = by_ply.entry(s.ply()).or_default();
+        *n += 1;
+        *visits += s.visits;
+    }
+    println!("{:>5} {:>10} {:>12}", "ply", "states", "visits");
+    // the states that can't be reached from the start last
+    let unreachable = by_ply.remove(&None);
+    for (ply, (n, visits)) in by_ply {
+        println!("{:>5} {:>10} {:>12}", ply.expect("Removed above"), n, visits);
+    }
+    if let Some((n, visits)) = unreachable {
+        println!("{:>5} {:>10} {:>12}  (not reachable from the start through the file)", "?", n, visits);
+    }
+
+    for (i, s) in states.iter().take(top).enumerate() {
+        let moves = match &s.moves {
+            Some(m) if m.is_empty() => "the start".to_string(),
+            Some(m) => m.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(" "),
+            None => "unknown moves".to_string(),
+        };
+        let proven = s.proven.map_or(String::new(), |v| format!(", proven value {v:.2}"));
+        println!();
+        println!("#{}: {} visits, after {}{}", i + 1, s.visits, moves, proven);
+        print!("{}", s.state);
+        println!("{:>8} {:>10} {:>8} {:>8}", "action", "visits", "mean", "proven");
+        for a in &s.actions {
+            let proven = a.proven.map_or("-".to_string(), |v| format!("{v:.2}"));
+            println!("{:>8} {:>10} {:>8.3} {:>8}", a.action.to_string(), a.visits, a.mean, proven);
+        }
+    }
+}
+
+fn print_out_report(outfile: &Path, game_to_report: GameType) -> anyhow::Result<()> {
     let mut n_wins = [[0.0; cardinality::<PlayerSpec>()]; cardinality::<PlayerSpec>()];
     let mut n_draws = [[0.0; cardinality::<PlayerSpec>()]; cardinality::<PlayerSpec>()];
     let mut n_losses = [[0.0; cardinality::<PlayerSpec>()]; cardinality::<PlayerSpec>()];
+    let mut n_forfeits = 0;
 
     // Iterate the lines in the file, and for each line, update the n_wins and n_games
-    let file = std::fs::File::open(outfile).map_err(|e| anyhow::anyhow!(
-        format!("Failed to open the file {:?} for reading scores. {}", outfile, e)))?;
-    let mut rdr = csv::Reader::from_reader(file);
-    for line in rdr.deserialize() {
+    for record in read_records(outfile)? {
         let GameRecord {
             game,
             player1,
             player2,
             result,
+            reason,
             ..
-        } = line?;
+        } = record;
         if game_to_report != game {
             continue;
         }
+        if reason != GameEndReason::Normal {
+            n_forfeits += 1;
+        }
         let p1num = player1 as usize;
         let p2num = player2 as usize;
         match result {
@@ -148,6 +390,9 @@ fn print_out_report(outfile: &PathBuf, game_to_report: GameType) -> anyhow::Resu
         }
     }
     print_result_matrix(n_wins, n_draws, n_losses);
+    if n_forfeits > 0 {
+        println!("{} games ended by illegal move, timeout or crash", n_forfeits);
+    }
     Ok(())
 }
 
@@ -189,13 +434,47 @@ fn print_result_matrix<const N: usize>(
     }
 }
 
+/// All the games in a score file.
+/// Score files started before the `reason` column was added have a header without it.
+/// The rows from then have no reason either, and those games all ended normally.
+/// Rows that were appended to such a file later do have the reason, after the columns of the header.
+fn read_records(outfile: &Path) -> anyhow::Result<Vec<GameRecord>> {
+    let file = std::fs::File::open(outfile).map_err(|e| anyhow::anyhow!(
+        format!("Failed to open the file {:?} for reading scores. {}", outfile, e)))?;
+    let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(file);
+    let mut headers = rdr.headers()?.clone();
+    let old_header = !headers.iter().any(|h| h == "reason");
+    if old_header {
+        headers.push_field("reason");
+    }
+    // serde writes a variant like `GameEndReason::Normal` by its name, as Debug does
+    let normal = format!("{:?}", GameEndReason::Normal);
+    let mut records = vec![];
+    for row in rdr.records() {
+        let mut row = row?;
+        if old_header && row.len() + 1 == headers.len() {
+            row.push_field(&normal);
+        }
+        records.push(row.deserialize(Some(&headers))?);
+    }
+    Ok(records)
+}
+
+/// Append a game to the score file. A file with the header from before the `reason` column is written again
+/// with the new header first, so that the reasons of the new rows are read back.
 fn record_result(outfile: &PathBuf, record: GameRecord) -> anyhow::Result<()> {
     let mut file = std::fs::OpenOptions::new()
-        .write(true)
         .create(true)
         .append(true)
         .open(outfile)?;
     let needs_headers = file.seek(std::io::SeekFrom::End(0))? == 0;
+    if !needs_headers {
+        let header = csv::Reader::from_path(outfile)?.headers()?.clone();
+        if !header.iter().any(|h| h == "reason") {
+            drop(file);
+            return rewrite_records(outfile, record);
+        }
+    }
     let mut wtr = csv::WriterBuilder::new()
         .has_headers(needs_headers)
         .from_writer(file);
@@ -205,72 +484,137 @@ fn record_result(outfile: &PathBuf, record: GameRecord) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Write all the games in the score file and `record` to a new file with the current header, and put it in place of the old one
+fn rewrite_records(outfile: &Path, record: GameRecord) -> anyhow::Result<()> {
+    let mut records = read_records(outfile)?;
+    records.push(record);
+    let mut tmp = outfile.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let mut wtr = csv::Writer::from_path(&tmp)?;
+    for record in records {
+        wtr.serialize(record)?;
+    }
+    wtr.flush()?;
+    drop(wtr);
+    std::fs::rename(&tmp, outfile)?;
+    log::info!("Added the reason column to the score file {}", outfile.display());
+    Ok(())
+}
+
 
 
 fn make_player_c4(
     p: PlayerSpec,
     mark: PlayerMark,
     rng: &mut ThreadRng,
-) -> Box<dyn BlitzPlayer<C4Board>> {
-    match p {
+    options: &AiOptions,
+) -> anyhow::Result<Box<dyn BlitzPlayer<C4Board>>> {
+    Ok(match p {
         PlayerSpec::Random => Box::new(RandomAi::new(rng.gen())),
-        PlayerSpec::Minimax4 => Box::new(MinMaxAi::new(mark, c4_heuristic, 4)),
-        PlayerSpec::AB4 => Box::new(ABAi::new(mark, c4_heuristic, 4)),
-        PlayerSpec::AB6 => Box::new(ABAi::new(mark, c4_heuristic, 6)),
-        PlayerSpec::MCTS1 => Box::new(MctsAi::<C4Board>::new(rng.gen(), 1.0, Some(format!("mcts1.{}.c4.data",mark)))),
-        PlayerSpec::MCTS2 => Box::new(MctsAi::<C4Board>::new(rng.gen(), 2.0, Some(format!("mcts2.{}.c4.data",mark)))),
-        PlayerSpec::MCTS3 => Box::new(MctsAi::<C4Board>::new(rng.gen(), 0.5, Some(format!("mcts3.{}.c4.data",mark)))),
-    }
+        PlayerSpec::Minimax4 => Box::new(MinMaxAi::new(c4_heuristic, 4)),
+        PlayerSpec::AB4 => Box::new(make_ab(c4_heuristic, c4_move_prior, 4, options)),
+        PlayerSpec::AB6 => Box::new(make_ab(c4_heuristic, c4_move_prior, 6, options)),
+        PlayerSpec::MCTS1 => Box::new(make_mcts::<C4Board>(rng.gen(), 1.0, format!("mcts1.{}.c4.data",mark), options)?),
+        PlayerSpec::MCTS2 => Box::new(make_mcts::<C4Board>(rng.gen(), 2.0, format!("mcts2.{}.c4.data",mark), options)?),
+        PlayerSpec::MCTS3 => Box::new(make_mcts::<C4Board>(rng.gen(), 0.5, format!("mcts3.{}.c4.data",mark), options)?),
+    })
 }
 
 static T0: Duration = Duration::from_secs(1);
 
+/// Number of positions in the transposition table of the alpha-beta players
+const TT_SIZE: usize = 1 << 18;
+
+/// The settings from the command line that apply to all AIs
+#[derive(Debug, Clone)]
+struct AiOptions {
+    threads: usize,
+    parallelism: Parallelism,
+    rave: Option<f64>,
+    memory_limit: Option<MemoryLimit>,
+    eviction: Eviction,
+    memory_dir: PathBuf,
+}
+
+fn make_ab<B>(heuristic: HeuristicFn<B>, prior: MovePriorFn<B>, depth: usize, options: &AiOptions) -> ABAi<B>
+where
+    B: Board + Clone + Send + Sync,
+    B::Coordinate: Send + Sync,
+{
+    let mut ai = ABAi::new(heuristic, depth);
+    ai.set_transposition_table_size(TT_SIZE);
+    ai.set_move_prior(Some(prior));
+    ai.set_killer_moves(true);
+    ai.set_history_heuristic(true);
+    ai.set_threads(options.threads);
+    ai
+}
+
+fn make_mcts<B: Mdp>(seed: u64, c: f64, mem_file: String, options: &AiOptions) -> anyhow::Result<MctsAi<B>> {
+    let mut ai = MctsAi::new(seed, c);
+    let mem_path = options.memory_dir.join(mem_file);
+    // the shared tree never reads or writes the memory, so it is not opened, and not locked either
+    if options.parallelism == Parallelism::SharedTree {
+        log::info!("The shared tree doesn't use the memory of the MCTS AI, so {} is not loaded", mem_path.display());
+    } else {
+        ai.load_memory(&mem_path)
+            .map_err(|e| anyhow::anyhow!("Can't load the memory of the MCTS AI from {}: {e}", mem_path.display()))?;
+    }
+    ai.set_threads(options.threads);
+    ai.set_parallelism(options.parallelism);
+    ai.set_rave(options.rave);
+    ai.set_memory_limit(options.memory_limit, options.eviction);
+    Ok(ai)
+}
+
 fn make_player_ttt(
     p: PlayerSpec,
     mark: PlayerMark,
     rng: &mut ThreadRng,
-) -> Box<dyn BlitzPlayer<TTTBoard>> {
-    match p {
+    options: &AiOptions,
+) -> anyhow::Result<Box<dyn BlitzPlayer<TTTBoard>>> {
+    Ok(match p {
         PlayerSpec::Random => Box::new(RandomAi::new(rng.gen())),
-        PlayerSpec::Minimax4 => Box::new(MinMaxAi::new(mark, ttt_heuristic, 4)),
-        PlayerSpec::AB4 => Box::new(ABAi::new(mark, ttt_heuristic, 4)),
-        PlayerSpec::AB6 => Box::new(ABAi::new(mark, ttt_heuristic, 6)),
-        PlayerSpec::MCTS1 => Box::new(MctsAi::<TTTBoard>::new(rng.gen(), 1.0, Some(format!("mcts1.{}.ttt.data",mark)))),
-        PlayerSpec::MCTS2 => Box::new(MctsAi::<TTTBoard>::new(rng.gen(), 2.0, Some(format!("mcts2.{}.ttt.data",mark)))),
-        PlayerSpec::MCTS3 => Box::new(MctsAi::<TTTBoard>::new(rng.gen(), 0.5, Some(format!("mcts3.{}.ttt.data",mark)))),
-    }
+        PlayerSpec::Minimax4 => Box::new(MinMaxAi::new(ttt_heuristic, 4)),
+        PlayerSpec::AB4 => Box::new(make_ab(ttt_heuristic, ttt_move_prior, 4, options)),
+        PlayerSpec::AB6 => Box::new(make_ab(ttt_heuristic, ttt_move_prior, 6, options)),
+        PlayerSpec::MCTS1 => Box::new(make_mcts::<TTTBoard>(rng.gen(), 1.0, format!("mcts1.{}.ttt.data",mark), options)?),
+        PlayerSpec::MCTS2 => Box::new(make_mcts::<TTTBoard>(rng.gen(), 2.0, format!("mcts2.{}.ttt.data",mark), options)?),
+        PlayerSpec::MCTS3 => Box::new(make_mcts::<TTTBoard>(rng.gen(), 0.5, format!("mcts3.{}.ttt.data",mark), options)?),
+    })
 }
 fn make_player_uttt(
     p: PlayerSpec,
     mark: PlayerMark,
     rng: &mut ThreadRng,
-) -> Box<dyn BlitzPlayer<UTTTBoard>> {
-    match p {
+    options: &AiOptions,
+) -> anyhow::Result<Box<dyn BlitzPlayer<UTTTBoard>>> {
+    Ok(match p {
         PlayerSpec::Random => Box::new(RandomAi::new(rng.gen())),
-        PlayerSpec::Minimax4 => Box::new(MinMaxAi::new(mark, uttt_heuristic, 4)),
-        PlayerSpec::AB4 => Box::new(ABAi::new(mark, uttt_heuristic, 4)),
-        PlayerSpec::AB6 => Box::new(ABAi::new(mark, uttt_heuristic, 6)),
-        PlayerSpec::MCTS1 => Box::new(MctsAi::<UTTTBoard>::new(rng.gen(), 1.0, Some(format!("mcts1.{}.uttt.data",mark)))),
-        PlayerSpec::MCTS2 => Box::new(MctsAi::<UTTTBoard>::new(rng.gen(), 2.0, Some(format!("mcts2.{}.uttt.data",mark)))),
-        PlayerSpec::MCTS3 => Box::new(MctsAi::<UTTTBoard>::new(rng.gen(), 0.5, Some(format!("mcts3.{}.uttt.data",mark)))),
-    }
+        PlayerSpec::Minimax4 => Box::new(MinMaxAi::new(uttt_heuristic, 4)),
+        PlayerSpec::AB4 => Box::new(make_ab(uttt_heuristic, uttt_move_prior, 4, options)),
+        PlayerSpec::AB6 => Box::new(make_ab(uttt_heuristic, uttt_move_prior, 6, options)),
+        PlayerSpec::MCTS1 => Box::new(make_mcts::<UTTTBoard>(rng.gen(), 1.0, format!("mcts1.{}.uttt.data",mark), options)?),
+        PlayerSpec::MCTS2 => Box::new(make_mcts::<UTTTBoard>(rng.gen(), 2.0, format!("mcts2.{}.uttt.data",mark), options)?),
+        PlayerSpec::MCTS3 => Box::new(make_mcts::<UTTTBoard>(rng.gen(), 0.5, format!("mcts3.{}.uttt.data",mark), options)?),
+    })
 }
 
-fn run_c4(player1: PlayerSpec, player2: PlayerSpec) -> (GameEndStatus, Duration, Duration) {
+fn run_c4(player1: PlayerSpec, player2: PlayerSpec, options: &AiOptions) -> anyhow::Result<(GameEndStatus, GameEndReason, Duration, Duration)> {
     let mut rng = rand::thread_rng();
-    let p1 = make_player_c4(player1, PlayerMark::Naught, &mut rng);
-    let p2 = make_player_c4(player2, PlayerMark::Cross, &mut rng);
-    run_blitz_game::<C4Board>(p1, p2,T0)
+    let p1 = make_player_c4(player1, PlayerMark::Naught, &mut rng, options)?;
+    let p2 = make_player_c4(player2, PlayerMark::Cross, &mut rng, options)?;
+    Ok(run_blitz_game::<C4Board>(p1, p2,T0))
 }
-fn run_ttt(player1: PlayerSpec, player2: PlayerSpec) -> (GameEndStatus, Duration, Duration) {
+fn run_ttt(player1: PlayerSpec, player2: PlayerSpec, options: &AiOptions) -> anyhow::Result<(GameEndStatus, GameEndReason, Duration, Duration)> {
     let mut rng = rand::thread_rng();
-    let p1 = make_player_ttt(player1, PlayerMark::Naught, &mut rng);
-    let p2 = make_player_ttt(player2, PlayerMark::Cross, &mut rng);
-    run_blitz_game::<TTTBoard>(p1, p2,T0)
+    let p1 = make_player_ttt(player1, PlayerMark::Naught, &mut rng, options)?;
+    let p2 = make_player_ttt(player2, PlayerMark::Cross, &mut rng, options)?;
+    Ok(run_blitz_game::<TTTBoard>(p1, p2,T0))
 }
-fn run_uttt(player1: PlayerSpec, player2: PlayerSpec) -> (GameEndStatus, Duration, Duration) {
+fn run_uttt(player1: PlayerSpec, player2: PlayerSpec, options: &AiOptions) -> anyhow::Result<(GameEndStatus, GameEndReason, Duration, Duration)> {
     let mut rng = rand::thread_rng();
-    let p1 = make_player_uttt(player1, PlayerMark::Naught, &mut rng);
-    let p2 = make_player_uttt(player2, PlayerMark::Cross, &mut rng);
-    run_blitz_game::<UTTTBoard>(p1, p2,T0)
+    let p1 = make_player_uttt(player1, PlayerMark::Naught, &mut rng, options)?;
+    let p2 = make_player_uttt(player2, PlayerMark::Cross, &mut rng, options)?;
+    Ok(run_blitz_game::<UTTTBoard>(p1, p2,T0))
 }
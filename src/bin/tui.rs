@@ -4,15 +4,17 @@ use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::hash::Hash;
 use xoxo::{
-    core::{run_game, Board, GameType, HeuristicFn, Player, PlayerMark},
+    core::{run_game, Board, GameEndReason, GameType, HeuristicFn, MovePriorFn, Player, PlayerMark},
     player::{
         ABAi,
-        c4_heuristic,
+        c4_heuristic, c4_move_prior,
         ConsolePlayer,
         MctsAi,
         MinMaxAi,
+        Parallelism,
         RandomAi,
         ttt_heuristic, uttt_heuristic,
+        ttt_move_prior, uttt_move_prior,
     },
 };
 
@@ -51,6 +53,20 @@ struct Args {
     #[arg(long, default_value = "6")]
     ab_depth: usize,
 
+    /// The number of threads the alpha-beta and MCTS algorithms search on
+    #[arg(long, default_value = "1")]
+    threads: usize,
+
+    /// How the MCTS algorithm searches on more than one thread
+    /// Only used for MCTS ai, if used
+    #[arg(long, default_value = "root")]
+    parallelism: Parallelism,
+
+    /// Turn on RAVE for the MCTS algorithm, with this equivalence parameter
+    /// Only used for MCTS ai, if used
+    #[arg(long)]
+    rave: Option<f64>,
+
     /// The seed for the random number generator (when used)
     #[arg(long)]
     seed: Option<u64>,
@@ -66,23 +82,37 @@ fn make_player<T>(
     player_type: PlayerType,
     marker: PlayerMark,
     rng: &mut StdRng,
-    mm_depth: usize,
-    ab_depth: usize,
+    args: &Args,
     c: f64,
     heuristic: HeuristicFn<T>,
+    prior: MovePriorFn<T>,
 ) -> Box<dyn Player<T>>
 where
-    T: Board + Clone + Hash + Eq + Debug + 'static + Serialize + for <'de> Deserialize<'de>,
+    T: Board + Clone + Hash + Eq + Debug + Send + Sync + 'static + Serialize + for <'de> Deserialize<'de>,
     ConsolePlayer: Player<T>,
-    <T as Board>::Coordinate: Ord + Hash + Debug,
+    <T as Board>::Coordinate: Ord + Hash + Debug + Send + Sync,
     for<'de> <T as Board>::Coordinate: Deserialize<'de> + Serialize
 {
     match player_type {
         PlayerType::Console => Box::new(ConsolePlayer::new(marker)),
         PlayerType::Random => Box::new(RandomAi::new(rng.gen())),
-        PlayerType::Minimax => Box::new(MinMaxAi::<T>::new(marker, heuristic, mm_depth)),
-        PlayerType::AlphaBeta => Box::new(ABAi::<T>::new(marker, heuristic, ab_depth)),
-        PlayerType::Mcts => Box::new(MctsAi::<T>::new(rng.gen(), c, None)),
+        PlayerType::Minimax => Box::new(MinMaxAi::<T>::new(heuristic, args.mm_depth)),
+        PlayerType::AlphaBeta => {
+            let mut ai = ABAi::<T>::new(heuristic, args.ab_depth);
+            ai.set_transposition_table_size(1 << 20);
+            ai.set_move_prior(Some(prior));
+            ai.set_killer_moves(true);
+            ai.set_history_heuristic(true);
+            ai.set_threads(args.threads);
+            Box::new(ai)
+        }
+        PlayerType::Mcts => {
+            let mut ai = MctsAi::<T>::new(rng.gen(), c);
+            ai.set_threads(args.threads);
+            ai.set_parallelism(args.parallelism);
+            ai.set_rave(args.rave);
+            Box::new(ai)
+        }
     }
 }
 
@@ -99,25 +129,25 @@ fn main() {
             GameType::C4 => 0.5,
         }
     };
-    match args.game {
+    let (result, reason) = match args.game {
         GameType::Ttt => {
             let p1 = make_player(
                 args.p1,
                 PlayerMark::Naught,
                 &mut rng,
-                args.mm_depth,
-                args.ab_depth,
+                &args,
                 c,
                 ttt_heuristic,
+                ttt_move_prior,
             );
             let p2 = make_player(
                 args.p2,
                 PlayerMark::Cross,
                 &mut rng,
-                args.mm_depth,
-                args.ab_depth,
+                &args,
                 c,
                 ttt_heuristic,
+                ttt_move_prior,
             );
             run_game(p1, p2)
         }
@@ -126,19 +156,19 @@ fn main() {
                 args.p1,
                 PlayerMark::Naught,
                 &mut rng,
-                args.mm_depth,
-                args.ab_depth,
+                &args,
                 c,
                 uttt_heuristic,
+                uttt_move_prior,
             );
             let p2 = make_player(
                 args.p2,
                 PlayerMark::Cross,
                 &mut rng,
-                args.mm_depth,
-                args.ab_depth,
+                &args,
                 c,
                 uttt_heuristic,
+                uttt_move_prior,
             );
             run_game(p1, p2)
         }
@@ -147,21 +177,24 @@ fn main() {
                 args.p1,
                 PlayerMark::Naught,
                 &mut rng,
-                args.mm_depth,
-                args.ab_depth,
+                &args,
                 c,
                 c4_heuristic,
+                c4_move_prior,
             );
             let p2 = make_player(
                 args.p2,
                 PlayerMark::Cross,
                 &mut rng,
-                args.mm_depth,
-                args.ab_depth,
+                &args,
                 c,
                 c4_heuristic,
+                c4_move_prior,
             );
             run_game(p1, p2)
         }
     };
+    if reason != GameEndReason::Normal {
+        println!("{} by {}", result, reason);
+    }
 }
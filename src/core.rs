@@ -2,7 +2,7 @@
 //!
 
 use serde::{Deserialize, Serialize};
-use std::{fmt::Display, hash::Hash};
+use std::{fmt::Display, hash::Hash, time::Duration};
 
 use clap::ValueEnum;
 
@@ -36,6 +36,11 @@ pub trait Player<B: Board> {
     /// The play function is the main mechanic for the AIs
     /// You observe the whole board through a reference, and can do whatever you like, and then you return an action representing where to play
     fn play(&mut self, b: &B) -> B::Coordinate;
+
+    /// What the player found out when it chose its last move. None for players that don't search.
+    fn last_search(&self) -> Option<&SearchInfo<B::Coordinate>> {
+        None
+    }
 }
 
 /// The BlitzPlayer trait is a trait for players that are able to blitz the game, i.e. play games with time limits.
@@ -44,17 +49,150 @@ pub trait Player<B: Board> {
 /// It is up to the player to decide how to budget their time over the course of the game.
 pub trait BlitzPlayer<B: Board> {
     fn blitz(&mut self, b: &B, time_remaining: std::time::Duration) -> B::Coordinate;
+
+    /// What the player found out when it chose its last move. None for players that don't search.
+    fn last_search(&self) -> Option<&SearchInfo<B::Coordinate>> {
+        None
+    }
+}
+
+/// The result of a search, to show why a player played what it did.
+/// Scores are from the point of view of the player to move at the root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchInfo<C> {
+    pub best_move: C,
+    pub score: f64,
+    /// The line of play that both players are expected to follow, starting with the best move.
+    /// It can be shorter than the depth, when the search found the rest of it in a transposition table.
+    pub principal_variation: Vec<C>,
+    /// Number of plies searched, counting the move at the root
+    pub depth: usize,
+    /// Number of positions visited in the whole search, including an unfinished last iteration
+    pub nodes: usize,
+    pub elapsed: Duration,
+    /// Every move at the root that was searched, with its score, best first.
+    /// A search that prunes at the root may only know that the other moves are no better than the best one,
+    /// and then gives upper bounds for their scores, which don't rank those moves among themselves. See `ScoreBound`.
+    pub root_moves: Vec<RootMove<C>>,
+    /// How the game ends from here with best play from both sides, if the search proved it
+    pub outcome: Option<Outcome>,
+    /// The moves at the root whose outcome was proven, for the player making them
+    pub proven_moves: Vec<(C, Outcome)>,
+}
+
+impl<C: Display + PartialEq> Display for SearchInfo<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} with score {:.2}, depth {}, {} nodes in {:.3?}",
+            self.best_move, self.score, self.depth, self.nodes, self.elapsed
+        )?;
+        match self.outcome {
+            Some(outcome) => writeln!(f, ", proven {outcome}")?,
+            None => writeln!(f)?,
+        }
+        write!(f, "pv:")?;
+        for m in &self.principal_variation {
+            write!(f, " {m}")?;
+        }
+        writeln!(f)?;
+        write!(f, "moves:")?;
+        for RootMove { coordinate: m, score, bound } in &self.root_moves {
+            match bound {
+                ScoreBound::Exact => write!(f, " {m}: {score:.2}")?,
+                ScoreBound::Upper => write!(f, " {m}: <={score:.2}")?,
+            }
+            if let Some((_, outcome)) = self.proven_moves.iter().find(|(p, _)| p == m) {
+                write!(f, " ({outcome})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A move at the root of a search, with its score from the point of view of the player making it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RootMove<C> {
+    pub coordinate: C,
+    pub score: f64,
+    pub bound: ScoreBound,
+}
+
+/// What the score of a move at the root tells about the move
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ScoreBound {
+    /// The score is what the search found for the move: its value to the depth searched, or for MCTS its mean return
+    Exact,
+    /// The search only showed that the move is no better than the best one, so its value is at most the score
+    Upper,
+}
+
+/// The end of a game, as seen by one of the players
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Win => write!(f, "win"),
+            Self::Draw => write!(f, "draw"),
+            Self::Loss => write!(f, "loss"),
+        }
+    }
 }
 
+/// How good a position is for the given player. The searches are negamax,
+/// so it must be zero-sum: the score for one player is the negation of the score for the other.
 pub type HeuristicFn<B> = fn(PlayerMark, &B) -> f64;
 
+/// A static guess of how good a move is, before searching it. Searches try the moves with high priors first.
+pub type MovePriorFn<B> = fn(&B, <B as Board>::Coordinate) -> i32;
+
 pub trait Board: Display + Default + Hash + Eq {
     type Coordinate: Display + Copy + Hash + Eq;
+    /// A short name of the game, e.g. to tell which game a file of learned data belongs to
+    const NAME: &'static str;
     /// The coordinates where you are allowed to place your marker in this turn.
-    fn valid_moves(&self) -> Vec<Self::Coordinate>;
+    fn valid_moves(&self) -> Vec<Self::Coordinate> {
+        let mut moves = Vec::new();
+        self.valid_moves_into(&mut moves);
+        moves
+    }
+    /// Like `valid_moves`, but the moves are written into a buffer owned by the caller.
+    /// The buffer is cleared first. Reuse it between calls, and there is no allocation in the hot loops of the AIs.
+    fn valid_moves_into(&self, moves: &mut Vec<Self::Coordinate>);
+    /// Place a marker without checking that the move is legal. Use `try_place_mark` if the move comes from an untrusted source.
     fn place_mark(&mut self, a: Self::Coordinate, marker: PlayerMark);
+    /// Check if `marker` may be placed at `a` right now, and if not, why not.
+    fn check_move(&self, a: Self::Coordinate, marker: PlayerMark) -> Result<(), MoveError>;
+    /// Place a marker, if the move is legal. The board is left untouched if it is not.
+    fn try_place_mark(&mut self, a: Self::Coordinate, marker: PlayerMark) -> Result<(), MoveError> {
+        self.check_move(a, marker)?;
+        self.place_mark(a, marker);
+        Ok(())
+    }
+    /// Everything needed to take back a move. See `make_move` and `unmake_move`.
+    type Undo: Copy;
+    /// Place a mark just like `place_mark`, but return a token that lets you take the move back.
+    /// This is what the searchers use, so they don't need to clone the board for every node.
+    fn make_move(&mut self, a: Self::Coordinate, marker: PlayerMark) -> Self::Undo;
+    /// Take back a move made with `make_move`.
+    /// Moves must be taken back in the reverse order they were made.
+    fn unmake_move(&mut self, undo: Self::Undo);
     fn game_status(&self) -> GameStatus;
     fn current_player(&self) -> PlayerMark;
+    /// A 64-bit key for the position, e.g. for transposition tables.
+    /// Equal boards must have equal keys. Boards that keep a Zobrist hash should return that,
+    /// the default is to run the `Hash` implementation through the standard hasher.
+    fn key(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.hash(&mut hasher);
+        std::hash::Hasher::finish(&hasher)
+    }
     fn game_is_over(&self) -> bool {
         !matches!(self.game_status(), GameStatus::Undecided)
     }
@@ -68,6 +206,35 @@ pub enum GameStatus {
     Won(PlayerMark),
 }
 
+/// The reasons a move can be rejected by `Board::check_move`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MoveError {
+    /// The coordinate is not on the board
+    OutOfRange,
+    /// There is already a marker there (or the column is full)
+    Occupied,
+    /// The move is not in the sub-board the player is forced to play in
+    WrongSubBoard,
+    /// The game is already decided
+    GameOver,
+    /// It is the other player's turn
+    WrongPlayer,
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::OutOfRange => write!(f, "The move is outside the board"),
+            Self::Occupied => write!(f, "That spot is already taken"),
+            Self::WrongSubBoard => write!(f, "The move must be made in another sub-board"),
+            Self::GameOver => write!(f, "The game is already over"),
+            Self::WrongPlayer => write!(f, "It is not that player's turn"),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
 pub enum GameType {
     /// Normal Tic-Tac-Toe
@@ -87,6 +254,41 @@ pub enum GameEndStatus {
     O,
 }
 
+impl GameEndStatus {
+    /// The end status where `mark` won
+    pub fn win_for(mark: PlayerMark) -> Self {
+        match mark {
+            PlayerMark::Cross => Self::X,
+            PlayerMark::Naught => Self::O,
+        }
+    }
+}
+
+/// Why a game ended
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, Ord, PartialOrd, Default, Serialize, Deserialize)]
+pub enum GameEndReason {
+    /// The game was played to the end
+    #[default]
+    Normal,
+    /// The loser tried to make an illegal move
+    IllegalMove,
+    /// The loser ran out of time
+    Timeout,
+    /// The loser panicked while thinking
+    Crash,
+}
+
+impl Display for GameEndReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::Normal => write!(f, "normal play"),
+            Self::IllegalMove => write!(f, "illegal move"),
+            Self::Timeout => write!(f, "timeout"),
+            Self::Crash => write!(f, "crash"),
+        }
+    }
+}
+
 impl Display for GameEndStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
@@ -97,15 +299,34 @@ impl Display for GameEndStatus {
     }
 }
 
-pub fn run_game<B: Board>(mut p1: Box<dyn Player<B>>, mut p2: Box<dyn Player<B>>) -> GameEndStatus{
+/// Play a game between two players and return who won, and why the game ended.
+/// A player that makes an illegal move or panics loses the game.
+pub fn run_game<B: Board>(mut p1: Box<dyn Player<B>>, mut p2: Box<dyn Player<B>>) -> (GameEndStatus, GameEndReason) {
     let mut current_player = PlayerMark::Naught;
     let mut board = B::default();
     while !board.game_is_over() {
-        let action = match current_player {
+        let action = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match current_player {
             PlayerMark::Naught => p1.play(&board),
             PlayerMark::Cross => p2.play(&board),
+        }));
+        let action = match action {
+            Ok(action) => action,
+            Err(_) => {
+                println!("Player {:?} crashed", current_player);
+                return (GameEndStatus::win_for(current_player.other()), GameEndReason::Crash);
+            }
+        };
+        let info = match current_player {
+            PlayerMark::Naught => p1.last_search(),
+            PlayerMark::Cross => p2.last_search(),
         };
-        board.place_mark(action, current_player);
+        if let Some(info) = info {
+            println!("Player {:?} played {}", current_player, info);
+        }
+        if let Err(e) = board.try_place_mark(action, current_player) {
+            println!("Player {:?} played {}, which is illegal: {}", current_player, action, e);
+            return (GameEndStatus::win_for(current_player.other()), GameEndReason::IllegalMove);
+        }
         current_player = current_player.other();
     }
     println!("{}", &board);
@@ -114,10 +335,11 @@ pub fn run_game<B: Board>(mut p1: Box<dyn Player<B>>, mut p2: Box<dyn Player<B>>
     }
     println!("Game over.");
 
-    match board.game_status() {
+    let status = match board.game_status() {
         GameStatus::Draw => GameEndStatus::Draw,
         GameStatus::Won(PlayerMark::Cross) => GameEndStatus::X,
         GameStatus::Won(PlayerMark::Naught) => GameEndStatus::O,
         GameStatus::Undecided => unreachable!(),
-    }
+    };
+    (status, GameEndReason::Normal)
 }
@@ -1,29 +1,43 @@
 use std::time::Duration;
 
-use log::debug;
+use log::{debug, warn};
 
-use crate::core::{BlitzPlayer, Board, GameEndStatus, GameStatus, PlayerMark};
+use crate::core::{BlitzPlayer, Board, GameEndReason, GameEndStatus, GameStatus, PlayerMark};
 
 pub mod connect_four;
 pub mod tictactoe;
 pub mod ultimate_ttt;
 
+/// Play a game with a chess clock, and return who won, why the game ended and the time each player had left.
+/// A player that runs out of time, makes an illegal move or panics loses the game.
 pub fn run_blitz_game<B: Board>(
     mut p1: Box<dyn BlitzPlayer<B>>,
     mut p2: Box<dyn BlitzPlayer<B>>,
     think_time: Duration,
-) -> (GameEndStatus, Duration, Duration) {
+) -> (GameEndStatus, GameEndReason, Duration, Duration) {
     let mut current_player = PlayerMark::Naught;
     let mut board = B::default();
     let mut time_remaining_naughts = think_time;
     let mut time_remaining_crosses = think_time;
     while !board.game_is_over() {
         let t0 = std::time::Instant::now();
-        let action = match current_player {
+        let action = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match current_player {
             PlayerMark::Naught => p1.blitz(&board, time_remaining_naughts),
             PlayerMark::Cross => p2.blitz(&board, time_remaining_crosses),
-        };
+        }));
         let t1 = std::time::Instant::now();
+        let action = match action {
+            Ok(action) => action,
+            Err(_) => {
+                warn!("{} crashed", current_player);
+                return (
+                    GameEndStatus::win_for(current_player.other()),
+                    GameEndReason::Crash,
+                    time_remaining_naughts,
+                    time_remaining_crosses,
+                );
+            }
+        };
         match current_player {
             PlayerMark::Naught => {
                 time_remaining_naughts = time_remaining_naughts
@@ -33,6 +47,7 @@ pub fn run_blitz_game<B: Board>(
                     debug!("{} ran out of time", PlayerMark::Naught);
                     return (
                         GameEndStatus::X,
+                        GameEndReason::Timeout,
                         time_remaining_naughts,
                         time_remaining_crosses,
                     );
@@ -46,14 +61,30 @@ pub fn run_blitz_game<B: Board>(
                     debug!("{} ran out of time", PlayerMark::Cross);
                     return (
                         GameEndStatus::O,
+                        GameEndReason::Timeout,
                         time_remaining_naughts,
                         time_remaining_crosses,
                     );
                 }
             }
         }
-        debug!("Player {} played {}", current_player, &action);
-        board.place_mark(action, current_player);
+        let info = match current_player {
+            PlayerMark::Naught => p1.last_search(),
+            PlayerMark::Cross => p2.last_search(),
+        };
+        match info {
+            Some(info) => debug!("Player {} played {}", current_player, info),
+            None => debug!("Player {} played {}", current_player, &action),
+        }
+        if let Err(e) = board.try_place_mark(action, current_player) {
+            warn!("{} played {}, which is illegal: {}", current_player, &action, e);
+            return (
+                GameEndStatus::win_for(current_player.other()),
+                GameEndReason::IllegalMove,
+                time_remaining_naughts,
+                time_remaining_crosses,
+            );
+        }
         debug!("\n{}", board);
         current_player = current_player.other();
     }
@@ -69,5 +100,5 @@ pub fn run_blitz_game<B: Board>(
         GameStatus::Undecided => unreachable!(),
     };
     debug!("Game ended with {}", winstatus);
-    (winstatus, time_remaining_naughts, time_remaining_crosses)
+    (winstatus, GameEndReason::Normal, time_remaining_naughts, time_remaining_crosses)
 }
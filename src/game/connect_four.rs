@@ -3,10 +3,25 @@ use lazy_static::lazy_static;
 
 use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::core::{Board, GameStatus, PlayerMark};
+use crate::core::{Board, GameStatus, MoveError, PlayerMark};
 
 const NCOLS:usize = 7;
 const NROWS:usize = 6;
+/// Each column takes up NROWS+1 bits in a bitboard. The extra bit on top is always empty,
+/// so that shifting a line of markers can never wrap around into the next column.
+const COL_HEIGHT: usize = NROWS + 1;
+/// One bit at the bottom of each column
+const BOTTOM_MASK: u64 = {
+    let mut mask = 0;
+    let mut col = 0;
+    while col < NCOLS {
+        mask |= 1 << (col * COL_HEIGHT);
+        col += 1;
+    }
+    mask
+};
+/// All playable cells, i.e. all cells that are not in the sentinel row
+const FULL_MASK: u64 = BOTTOM_MASK * ((1 << NROWS) - 1);
 type RawBoard = [[Option<PlayerMark>; NROWS]; NCOLS];
 lazy_static! {
     /// the ZOBRIST instance a specific hasher that anyone can use without explicitly passing it around
@@ -15,19 +30,25 @@ lazy_static! {
 
 /// A board is a 7x6 grid, where you can place a marker in one of the 7 columns
 /// it lands on the top in that column we number the columns left to right and bottom to top
-/// Since the board is a nested array the first index is the column and the second index is the row
 ///
-/// [0][5]   [1][5]   [2][5]   [3][5]   [4][5]   [5][5]   [6][5]
-/// [0][4]   [1][4]   [2][4]   [3][4]   [4][4]   [5][4]   [6][4]
-/// [0][3]   [1][3]   [2][3]   [3][3]   [4][3]   [5][3]   [6][3]
-/// [0][2]   [1][2]   [2][2]   [3][2]   [4][2]   [5][2]   [6][2]
-/// [0][1]   [1][1]   [2][1]   [3][1]   [4][1]   [5][1]   [6][1]
-/// [0][0]   [1][0]   [2][0]   [3][0]   [4][0]   [5][0]   [6][0]
+/// The markers are stored as bitboards, one u64 per player. Bit `col * 7 + row` is the cell in that column and row.
+/// The 7th bit of each column is never set. With this layout,
+/// the lowest empty cell in a column is found by adding the bottom bit to the occupied cells in it,
+/// and four-in-a-row is found by shifting a bitboard 1 (vertical), 7 (horizontal), 6 and 8 (the diagonals) steps.
+///
+///  5  12  19  26  33  40  47
+///  4  11  18  25  32  39  46
+///  3  10  17  24  31  38  45
+///  2   9  16  23  30  37  44
+///  1   8  15  22  29  36  43
+///  0   7  14  21  28  35  42
 ///
 #[derive(Clone, Copy, Debug, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
 pub struct C4Board {
-    /// 7 columns, 6 rows. N.B. it is column major
-    board: RawBoard,
+    /// The cells occupied by naughts
+    naughts: u64,
+    /// The cells occupied by crosses
+    crosses: u64,
     /// The game status must always be valid. I.e. you must always keep it up to date in all &mut self methods
     status: GameStatus,
     /// Current player must always be valid. I.e. you must always keep it up to date in all &mut self methods
@@ -44,9 +65,8 @@ impl std::hash::Hash for C4Board {
 
 impl PartialEq for C4Board {
     fn eq(&self, other: &Self) -> bool {
-         self.current_player == other.current_player
-            && self.status == other.status
-            && self.board == other.board
+        // the status and current player follow from the markers
+        self.naughts == other.naughts && self.crosses == other.crosses
     }
 }
 
@@ -54,52 +74,109 @@ impl Eq for C4Board {}
 
 impl From<C4Board> for RawBoard {
     fn from(val: C4Board) -> Self {
-        val.board
+        let mut board: RawBoard = Default::default();
+        for (col, column) in board.iter_mut().enumerate() {
+            for (row, cell) in column.iter_mut().enumerate() {
+                *cell = val.cell(col, row);
+            }
+        }
+        board
     }
 }
 
 impl Default for C4Board {
     fn default() -> Self {
-        Self { board: Default::default(), status: Default::default(), current_player: Default::default(), zhash: ZOBRIST.empty }
+        Self { naughts: 0, crosses: 0, status: Default::default(), current_player: Default::default(), zhash: ZOBRIST.empty }
     }
 }
 
 impl Board for C4Board {
     type Coordinate = usize;
+    const NAME: &'static str = "c4";
     fn current_player(&self) -> PlayerMark {
         self.current_player
     }
-    fn valid_moves(&self) -> Vec<usize> {
-        self.board
-            .iter()
-            .enumerate()
-            .filter_map(|(i, col)| if col[5].is_none() { Some(i) } else { None })
-            .collect()
+    fn valid_moves_into(&self, moves: &mut Vec<usize>) {
+        moves.clear();
+        let occupied = self.occupied();
+        moves.extend((0..NCOLS).filter(|&col| occupied & Self::top_mask(col) == 0));
     }
     fn game_status(&self) -> GameStatus {
         self.status
     }
+    fn key(&self) -> u64 {
+        self.zhash
+    }
+
+    fn check_move(&self, column: usize, marker: PlayerMark) -> Result<(), MoveError> {
+        if self.status != GameStatus::Undecided {
+            return Err(MoveError::GameOver);
+        }
+        if marker != self.current_player {
+            return Err(MoveError::WrongPlayer);
+        }
+        if column >= NCOLS {
+            return Err(MoveError::OutOfRange);
+        }
+        if self.occupied() & Self::top_mask(column) != 0 {
+            return Err(MoveError::Occupied);
+        }
+        Ok(())
+    }
 
     fn place_mark(&mut self, column: usize, marker: PlayerMark) {
-        assert!(column < 7, "Column out of bounds");
-        let row = self.board[column]
-            .iter()
-            .position(|x| x.is_none())
-            .expect("Column is full");
-        self.board[column][row] = Some(marker);
-        let i_won = Some(self.current_player);
-        if (Self::raw_winner_in_column(&self.board, column) == i_won)
-            || (Self::raw_winner_in_row(&self.board, row) == i_won)
-            || (Self::raw_winner_in_slash_diagonal(&self.board, 5 + column - row) == i_won)
-            || (Self::raw_winner_in_backslash_diagonal(&self.board, column + row) == i_won)
-        {
+        assert!(column < NCOLS, "Column out of bounds");
+        let occupied = self.occupied();
+        let cell = (occupied + Self::bottom_mask(column)) & Self::column_mask(column);
+        assert!(cell != 0, "Column is full");
+        let row = cell.trailing_zeros() as usize - column * COL_HEIGHT;
+        let markers = match marker {
+            PlayerMark::Naught => &mut self.naughts,
+            PlayerMark::Cross => &mut self.crosses,
+        };
+        *markers |= cell;
+        if Self::has_four_in_a_row(*markers) {
             self.status = GameStatus::Won(marker);
-        } else if row == 5 && self.board.iter().all(|col| col.iter().all(|x| x.is_some())) {
+        } else if occupied | cell == FULL_MASK {
             self.status = GameStatus::Draw;
         }
         self.current_player = self.current_player.other();
         ZOBRIST.update(&mut self.zhash,column,row,marker);
     }
+    type Undo = C4Undo;
+    fn make_move(&mut self, column: usize, marker: PlayerMark) -> C4Undo {
+        let undo = C4Undo {
+            column,
+            status: self.status,
+        };
+        self.place_mark(column, marker);
+        undo
+    }
+    fn unmake_move(&mut self, undo: C4Undo) {
+        let column = undo.column;
+        let in_column = self.occupied() & Self::column_mask(column);
+        assert!(in_column != 0, "Can only take back a move that was made");
+        let top = 63 - in_column.leading_zeros() as usize;
+        let cell = 1 << top;
+        let marker = if self.naughts & cell != 0 {
+            self.naughts ^= cell;
+            PlayerMark::Naught
+        } else {
+            self.crosses ^= cell;
+            PlayerMark::Cross
+        };
+        self.status = undo.status;
+        self.current_player = self.current_player.other();
+        ZOBRIST.update(&mut self.zhash, column, top - column * COL_HEIGHT, marker);
+    }
+}
+
+/// What is needed to take back a move on a `C4Board`.
+/// The rest can be read off the board itself, since the last marker in the column is the one to remove.
+#[derive(Clone, Copy, Debug)]
+pub struct C4Undo {
+    column: usize,
+    status: GameStatus,
 }
 
 #[cfg(test)]
@@ -133,9 +210,11 @@ macro_rules! parse_c4board {
         let current_player = C4Board::raw_current_player(game_board);
         let status = C4Board::raw_game_status(game_board);
         let zhash = ZOBRIST.hash_board(&game_board);
+        let (naughts, crosses) = C4Board::raw_bitboards(&game_board);
 
         C4Board {
-            board: game_board,
+            naughts,
+            crosses,
             current_player,
             status,
             zhash
@@ -150,6 +229,59 @@ impl C4Board {
         }
     }
 
+    /// The marker in a cell, if any. Columns and rows are numbered from the bottom left, starting at 0.
+    pub fn cell(&self, col: usize, row: usize) -> Option<PlayerMark> {
+        let bit = 1 << (col * COL_HEIGHT + row);
+        if self.naughts & bit != 0 {
+            Some(PlayerMark::Naught)
+        } else if self.crosses & bit != 0 {
+            Some(PlayerMark::Cross)
+        } else {
+            None
+        }
+    }
+
+    fn occupied(&self) -> u64 {
+        self.naughts | self.crosses
+    }
+    fn bottom_mask(col: usize) -> u64 {
+        1 << (col * COL_HEIGHT)
+    }
+    fn top_mask(col: usize) -> u64 {
+        1 << (col * COL_HEIGHT + NROWS - 1)
+    }
+    fn column_mask(col: usize) -> u64 {
+        ((1 << NROWS) - 1) << (col * COL_HEIGHT)
+    }
+
+    /// Are there four markers in a row in this bitboard?
+    /// For each direction, `m` marks the cells that start a pair, and then we look for two pairs in a row.
+    fn has_four_in_a_row(markers: u64) -> bool {
+        [1, COL_HEIGHT, COL_HEIGHT - 1, COL_HEIGHT + 1]
+            .iter()
+            .any(|&shift| {
+                let m = markers & (markers >> shift);
+                m & (m >> (2 * shift)) != 0
+            })
+    }
+
+    /// Convert a raw board into bitboards for naughts and crosses
+    #[cfg(test)]
+    fn raw_bitboards(board: &RawBoard) -> (u64, u64) {
+        let mut naughts = 0;
+        let mut crosses = 0;
+        for (col, column) in board.iter().enumerate() {
+            for (row, cell) in column.iter().enumerate() {
+                match cell {
+                    Some(PlayerMark::Naught) => naughts |= 1 << (col * COL_HEIGHT + row),
+                    Some(PlayerMark::Cross) => crosses |= 1 << (col * COL_HEIGHT + row),
+                    None => {}
+                }
+            }
+        }
+        (naughts, crosses)
+    }
+
     /// Compute who is next to go, based on the current board
     /// Useful in debugging
     #[cfg(test)]
@@ -303,7 +435,7 @@ impl Display for C4Board {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for row in (0..6).rev() {
             for col in 0..7 {
-                let cell = match self.board[col][row] {
+                let cell = match self.cell(col, row) {
                     Some(PlayerMark::Cross) => 'x',
                     Some(PlayerMark::Naught) => 'o',
                     None => '.',
@@ -335,11 +467,10 @@ impl Zobrist {
     #[cfg(test)]
     pub fn hash_board(&self,board: &RawBoard) -> u64 {
         let mut hash = self.empty;
-        for col in 0..NCOLS {
-            for row in 0..NROWS {
-                match board[col][row] {
-                    Some(marker) => {self.update(&mut hash,col,row,marker)},
-                    None => {},
+        for (col, column) in board.iter().enumerate() {
+            for (row, cell) in column.iter().enumerate() {
+                if let Some(marker) = *cell {
+                    self.update(&mut hash, col, row, marker)
                 }
             }
         }
@@ -379,7 +510,7 @@ mod tests {
         board.place_mark(3, PlayerMark::Cross);
         assert_eq!(board.winner(), Some(PlayerMark::Cross));
         assert_eq!(
-            C4Board::raw_winner_in_row(&board.board, 0),
+            C4Board::raw_winner_in_row(&RawBoard::from(board), 0),
             Some(PlayerMark::Cross)
         );
     }
@@ -395,12 +526,12 @@ mod tests {
         xooo.o.
         "
         );
-        assert_eq!(board.board[0][1], None);
-        assert_eq!(board.board[0][0], Some(PlayerMark::Cross));
-        assert_eq!(board.board[1][1], Some(PlayerMark::Cross));
-        assert_eq!(board.board[2][2], Some(PlayerMark::Cross));
-        assert_eq!(board.board[3][3], Some(PlayerMark::Cross));
-        assert_eq!(board.board[4][4], None);
+        assert_eq!(RawBoard::from(board)[0][1], None);
+        assert_eq!(RawBoard::from(board)[0][0], Some(PlayerMark::Cross));
+        assert_eq!(RawBoard::from(board)[1][1], Some(PlayerMark::Cross));
+        assert_eq!(RawBoard::from(board)[2][2], Some(PlayerMark::Cross));
+        assert_eq!(RawBoard::from(board)[3][3], Some(PlayerMark::Cross));
+        assert_eq!(RawBoard::from(board)[4][4], None);
     }
     #[test]
     fn test_winner_in_slash_diagonal() {
@@ -414,25 +545,25 @@ mod tests {
         xoo....
         "
         );
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 0), None);
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 1), None);
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 2), None);
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 3), None);
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 4), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 0), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 1), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 2), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 3), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 4), None);
         assert_eq!(
-            C4Board::raw_winner_in_slash_diagonal(&board.board, 5),
+            C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 5),
             Some(PlayerMark::Cross)
         );
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 6), None);
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 7), None);
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 8), None);
-        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&board.board, 9), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 6), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 7), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 8), None);
+        assert_eq!(C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 9), None);
         assert_eq!(
-            C4Board::raw_winner_in_slash_diagonal(&board.board, 10),
+            C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 10),
             None
         );
         assert_eq!(
-            C4Board::raw_winner_in_slash_diagonal(&board.board, 11),
+            C4Board::raw_winner_in_slash_diagonal(&RawBoard::from(board), 11),
             None
         );
     }
@@ -448,7 +579,7 @@ mod tests {
         xoxxo.."
         );
         assert_eq!(
-            C4Board::raw_winner_in_backslash_diagonal(&board.board, 4),
+            C4Board::raw_winner_in_backslash_diagonal(&RawBoard::from(board), 4),
             Some(PlayerMark::Naught)
         );
         assert_eq!(board.winner(), Some(PlayerMark::Naught));
@@ -491,4 +622,42 @@ mod tests {
         assert_eq!(board1,board2);
         assert_eq!(board1.zhash,board2.zhash);
     }
+    #[test]
+    fn unmake_restores_board() {
+        let mut board = parse_c4board!(
+            "
+        .......
+        .......
+        .......
+        .......
+        xxx....
+        ooo....
+        "
+        );
+        let before = board;
+        let undo = board.make_move(3, board.current_player());
+        assert_eq!(board.winner(), Some(PlayerMark::Naught));
+        board.unmake_move(undo);
+        assert_eq!(board, before);
+        assert_eq!(board.zhash, before.zhash);
+        assert_eq!(board.game_status(), GameStatus::Undecided);
+    }
+    #[test]
+    fn bitboard_agrees_with_raw_board() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..200 {
+            let mut board = C4Board::default();
+            while !board.game_is_over() {
+                let moves = board.valid_moves();
+                let col = moves[rng.gen_range(0..moves.len())];
+                board.place_mark(col, board.current_player());
+                let raw = RawBoard::from(board);
+                assert_eq!(board.game_status(), C4Board::raw_game_status(raw), "\n{}", board);
+                assert_eq!(board.current_player(), C4Board::raw_current_player(raw));
+                assert_eq!(board.zhash, ZOBRIST.hash_board(&raw));
+                let valid: Vec<usize> = (0..7).filter(|&c| raw[c][5].is_none()).collect();
+                assert_eq!(board.valid_moves(), valid);
+            }
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use std::{fmt::Debug, str::FromStr};
 
-use crate::core::{Board, PlayerMark};
+use crate::core::{Board, GameStatus, MoveError, PlayerMark};
 
 /// Represents a coordinate on the board
 ///
@@ -41,30 +41,43 @@ impl Debug for TTTBoard {
 
 impl Board for TTTBoard {
     type Coordinate = TTTAddr;
-    fn valid_moves(&self) -> Vec<TTTAddr> {
-        self.0
-            .iter()
-            .enumerate()
-            .filter_map(|(num, &mark)| {
-                if mark.is_none() {
-                    Some(TTTAddr(num + 1))
-                } else {
-                    None
-                }
-            })
-            .collect()
+    const NAME: &'static str = "ttt";
+    fn valid_moves_into(&self, moves: &mut Vec<TTTAddr>) {
+        moves.clear();
+        moves.extend(self.0.iter().enumerate().filter_map(|(num, &mark)| {
+            if mark.is_none() {
+                Some(TTTAddr(num + 1))
+            } else {
+                None
+            }
+        }));
     }
 
-    fn game_status(&self) -> crate::core::GameStatus {
+    fn game_status(&self) -> GameStatus {
         let board_full = self.0.iter().all(|&q| q.is_some());
         let winner = self.winner();
         if let Some(p) = winner {
-            crate::core::GameStatus::Won(p)
+            GameStatus::Won(p)
         } else if board_full {
-            crate::core::GameStatus::Draw
+            GameStatus::Draw
         } else {
-            crate::core::GameStatus::Undecided
+            GameStatus::Undecided
+        }
+    }
+    fn check_move(&self, a: TTTAddr, marker: PlayerMark) -> Result<(), MoveError> {
+        if self.game_status() != GameStatus::Undecided {
+            return Err(MoveError::GameOver);
         }
+        if marker != self.current_player() {
+            return Err(MoveError::WrongPlayer);
+        }
+        if !(1..=9).contains(&a.0) {
+            return Err(MoveError::OutOfRange);
+        }
+        if self.0[a.0 - 1].is_some() {
+            return Err(MoveError::Occupied);
+        }
+        Ok(())
     }
     fn place_mark(&mut self, a: TTTAddr, marker: PlayerMark) {
         let addr = a.0;
@@ -75,12 +88,50 @@ impl Board for TTTBoard {
         if self.0[num].is_some() {
             panic!("There is already a marker there! Invalid move just played!")
         }
+        self.update_counters(num, marker, 1);
+        self.0[num] = Some(marker);
+    }
+    type Undo = TTTAddr;
+    fn make_move(&mut self, a: TTTAddr, marker: PlayerMark) -> TTTAddr {
+        self.place_mark(a, marker);
+        a
+    }
+    fn unmake_move(&mut self, a: TTTAddr) {
+        let num = a.0 - 1;
+        let marker = self.0[num].expect("Can only take back a move that was made");
+        self.update_counters(num, marker, -1);
+        self.0[num] = None;
+    }
+    /// The board read as a base 3 number, so different boards never share a key
+    fn key(&self) -> u64 {
+        self.0.iter().fold(0, |key, mark| {
+            3 * key
+                + match mark {
+                    None => 0,
+                    Some(PlayerMark::Naught) => 1,
+                    Some(PlayerMark::Cross) => 2,
+                }
+        })
+    }
+    fn current_player(&self) -> PlayerMark {
+        if self.n_moves_made().is_multiple_of(2) {
+            PlayerMark::Naught
+        } else {
+            PlayerMark::Cross
+        }
+    }
+}
+
+impl TTTBoard {
+    /// Add (sign=1) or remove (sign=-1) the marker on square `num` (0-8) from the victory counters
+    fn update_counters(&mut self, num: usize, marker: PlayerMark, sign: i32) {
         let row = num / 3;
         let col = num % 3;
-        let delta = match marker {
-            PlayerMark::Naught => 1,
-            PlayerMark::Cross => -1,
-        };
+        let delta = sign
+            * match marker {
+                PlayerMark::Naught => 1,
+                PlayerMark::Cross => -1,
+            };
         self.1[row] += delta;
         self.1[3 + col] += delta;
         if row == col {
@@ -89,22 +140,12 @@ impl Board for TTTBoard {
         if row == 2 - col {
             self.1[7] += delta;
         }
-        self.0[num] = Some(marker);
     }
-    fn current_player(&self) -> PlayerMark {
-        if self.n_moves_made() % 2 == 0 {
-            PlayerMark::Naught
-        } else {
-            PlayerMark::Cross
-        }
-    }
-}
 
-impl TTTBoard {
     /// Is there a winner?
     pub fn winner(&self) -> Option<PlayerMark> {
-        let naught_won = self.1.iter().any(|&x| x == 3);
-        let cross_won = self.1.iter().any(|&x| x == -3);
+        let naught_won = self.1.contains(&3);
+        let cross_won = self.1.contains(&-3);
         if naught_won && !cross_won {
             Some(PlayerMark::Naught)
         } else if !naught_won && cross_won {
@@ -1,41 +1,95 @@
 use std::fmt::Display;
 use std::hash::{Hash, Hasher};
 
-use crate::core::{Board, GameStatus, PlayerMark};
+use lazy_static::lazy_static;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::core::{Board, GameStatus, MoveError, PlayerMark};
+
+lazy_static! {
+    /// the ZOBRIST instance a specific hasher that anyone can use without explicitly passing it around
+    static ref ZOBRIST: Zobrist = Zobrist::make(987654321);
+}
+
+/// All 9 cells of a tic-tac-toe board
+const FULL: u16 = 0b111_111_111;
+
+/// `WINS[mask]` is true if the 9-bit mask contains three in a row.
+/// Cell (k, l) is bit `3 * k + l`, both for cells in a sub-board and for sub-boards in the super-board.
+const WINS: [bool; 512] = {
+    const LINES: [u16; 8] = [
+        0b000_000_111,
+        0b000_111_000,
+        0b111_000_000,
+        0b001_001_001,
+        0b010_010_010,
+        0b100_100_100,
+        0b100_010_001,
+        0b001_010_100,
+    ];
+    let mut wins = [false; 512];
+    let mut mask = 0;
+    while mask < 512 {
+        let mut i = 0;
+        while i < LINES.len() {
+            if mask as u16 & LINES[i] == LINES[i] {
+                wins[mask] = true;
+            }
+            i += 1;
+        }
+        mask += 1;
+    }
+    wins
+};
+
+/// Index of a player in the per-player arrays
+fn idx(mark: PlayerMark) -> usize {
+    match mark {
+        PlayerMark::Naught => 0,
+        PlayerMark::Cross => 1,
+    }
+}
 
 #[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub struct UTTTBoard {
     /// The board is a 3x3 grid of 3x3 grids
-    /// board[i][j] is the sub-board at position (i, j)
-    /// so board[0][0] is the top left sub-board
-    /// and board[i][k][k][l] is the mark at position (k, l) in the sub-board at position (i, j)
+    /// cells[p][3 * i + j] is a 9-bit mask of the markers of player p (0 = naughts, 1 = crosses) in the sub-board at position (i, j)
+    /// so cells[p][0] is the top left sub-board
+    /// and bit 3 * k + l is the position (k, l) in that sub-board
     /// all indices go from 0 to 2
     /// 0 = top or left
     /// 2 = right or bottom
-    board: [[[[Option<PlayerMark>; 3]; 3]; 3]; 3],
-    /// The winner of the game
-    /// None if the game is still running
-    /// Some(None) if the game is a draw
-    /// Some(Some(PlayerMark)) if the game is won by that player
-    sup_board_status: GameStatus,
-    /// The status of the super-board
+    cells: [[u16; 9]; 2],
     /// The super-board is a normal tictactoe board
-    /// While being computable from the board, it is easier to keep track of it separately
-    sup_board: [[GameStatus; 3]; 3],
-    /// The last action taken decides the next board to play in
-    /// In the first move, this is None
-    last_action: Option<Action>,
+    /// won[p] is a 9-bit mask of the sub-boards won by player p
+    won: [u16; 2],
+    /// A 9-bit mask of the sub-boards that are full without a winner
+    drawn: u16,
+    /// The status of the whole game
+    sup_board_status: GameStatus,
+    /// Who is next to go. Kept up to date in place_mark, so it doesn't need to be counted
+    current_player: PlayerMark,
+    /// The index 3 * i + j of the sub-board that the next move must be placed in, set by the position of the last move.
+    /// None in the first move, and when the sub-board at that position is decided, so the next move can go anywhere.
+    /// Only the sub-board is kept, not the last move, so two move orders that reach the same state serialize the same,
+    /// which the memory of `MctsAi` relies on to find a state again.
+    target_board: Option<u8>,
+    /// Zobrist hash value of the markers on the board.
+    /// The sub-board to play in is not part of it, but is mixed in when hashing.
+    zhash: u64,
 }
 
 impl Hash for UTTTBoard {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.board.hash(state);
+        self.key().hash(state);
     }
 }
 
+/// Two states are the same if the markers are the same, and the next move is forced into the same sub-board.
+/// The latter matters, since it decides which moves are legal.
 impl PartialEq for UTTTBoard {
     fn eq(&self, other: &Self) -> bool {
-        self.board == other.board
+        self.cells == other.cells && self.target_board == other.target_board
     }
 }
 
@@ -44,138 +98,147 @@ impl Eq for UTTTBoard {}
 impl Default for UTTTBoard {
     fn default() -> Self {
         Self {
-            sup_board: [[GameStatus::Undecided; 3]; 3],
-            board: [[[[None; 3]; 3]; 3]; 3],
+            cells: [[0; 9]; 2],
+            won: [0; 2],
+            drawn: 0,
             sup_board_status: GameStatus::Undecided,
-            last_action: None,
+            current_player: PlayerMark::Naught,
+            target_board: None,
+            zhash: ZOBRIST.empty,
         }
     }
 }
 
 impl UTTTBoard {
-    pub fn get_sup_board(&self) -> &[[GameStatus; 3]; 3] {
-        &self.sup_board
+    /// The status of all the sub-boards
+    pub fn get_sup_board(&self) -> [[GameStatus; 3]; 3] {
+        let mut sup_board = [[GameStatus::Undecided; 3]; 3];
+        for (i, row) in sup_board.iter_mut().enumerate() {
+            for (j, status) in row.iter_mut().enumerate() {
+                *status = self.sub_board_status(i, j);
+            }
+        }
+        sup_board
+    }
+
+    /// The status of the sub-board at position (i, j)
+    pub fn sub_board_status(&self, i: usize, j: usize) -> GameStatus {
+        let bit = 1 << (3 * i + j);
+        if self.won[0] & bit != 0 {
+            GameStatus::Won(PlayerMark::Naught)
+        } else if self.won[1] & bit != 0 {
+            GameStatus::Won(PlayerMark::Cross)
+        } else if self.drawn & bit != 0 {
+            GameStatus::Draw
+        } else {
+            GameStatus::Undecided
+        }
+    }
+
+    /// The marker at position (k, l) in the sub-board at position (i, j)
+    pub fn cell(&self, i: usize, j: usize, k: usize, l: usize) -> Option<PlayerMark> {
+        let bit = 1 << (3 * k + l);
+        if self.cells[0][3 * i + j] & bit != 0 {
+            Some(PlayerMark::Naught)
+        } else if self.cells[1][3 * i + j] & bit != 0 {
+            Some(PlayerMark::Cross)
+        } else {
+            None
+        }
+    }
+
+    /// A mask of the sub-boards that are won or drawn
+    fn decided(&self) -> u16 {
+        self.won[0] | self.won[1] | self.drawn
     }
 
     /// The next move must be placed in this sub-board
     /// indexed 0-2
     pub fn target_board(&self) -> Option<(usize, usize)> {
-        self.last_action.and_then(|a| {
-            if self.sup_board[a.position.0][a.position.1] == GameStatus::Undecided {
-                Some(a.position)
-            } else {
-                None
-            }
-        })
+        self.target_board.map(|b| (b as usize / 3, b as usize % 3))
     }
 
     /// Mark the given position with the given player mark in the sub-board
     /// and if someone won that sub-board, mark the position in the sup-board
     /// and if someone won the sup-board, mark the winner
     /// and if the sup-board is full, mark the draw
+    /// Panics on a move that breaks the rules, as the other boards do. See `try_place_mark` to get the error instead.
     fn place_mark(&mut self, action: Action, mark: PlayerMark) {
-        let sub_row = action.position.0;
-        let sub_col = action.position.1;
-        self.board[action.board.0][action.board.1][action.position.0][action.position.1] =
-            Some(mark);
-
-        // check if this player won the sub-board
-        let sub_board = &self.board[action.board.0][action.board.1];
-        let won_row = sub_board[sub_row][0] == Some(mark)
-            && sub_board[sub_row][1] == Some(mark)
-            && sub_board[sub_row][2] == Some(mark);
-        let won_col = sub_board[0][sub_col] == Some(mark)
-            && sub_board[1][sub_col] == Some(mark)
-            && sub_board[2][sub_col] == Some(mark);
-        let won_diag_se = sub_col == sub_row
-            && sub_board[0][0] == Some(mark)
-            && sub_board[1][1] == Some(mark)
-            && sub_board[2][2] == Some(mark);
-        let won_diag_ne = sub_col == 2 - sub_row
-            && sub_board[0][2] == Some(mark)
-            && sub_board[1][1] == Some(mark)
-            && sub_board[2][0] == Some(mark);
-        let won_sub_board = won_row || won_col || won_diag_se || won_diag_ne;
-        if won_sub_board {
-            self.sup_board[action.board.0][action.board.1] = GameStatus::Won(mark);
-        }
-        // check if the sub-board is a draw
-        let sub_board_full = sub_board.iter().all(|row| row.iter().all(|&x| x.is_some()));
-        if sub_board_full && !won_sub_board {
-            self.sup_board[action.board.0][action.board.1] = GameStatus::Draw;
-        }
+        assert_eq!(self.validate(action), Ok(()), "Illegal move {action}");
+        let p = idx(mark);
+        let b = action.board_index();
+        self.cells[p][b] |= 1 << action.position_index();
+        ZOBRIST.update(&mut self.zhash, action, mark);
 
-        // check if the super-board is won
-        let won_row = self.sup_board[sub_row][0] == GameStatus::Won(mark)
-            && self.sup_board[sub_row][1] == GameStatus::Won(mark)
-            && self.sup_board[sub_row][2] == GameStatus::Won(mark);
-        let won_col = self.sup_board[0][sub_col] == GameStatus::Won(mark)
-            && self.sup_board[1][sub_col] == GameStatus::Won(mark)
-            && self.sup_board[2][sub_col] == GameStatus::Won(mark);
-        let won_diag_se = sub_col == sub_row
-            && self.sup_board[0][0] == GameStatus::Won(mark)
-            && self.sup_board[1][1] == GameStatus::Won(mark)
-            && self.sup_board[2][2] == GameStatus::Won(mark);
-        let won_diag_ne = sub_col == 2 - sub_row
-            && self.sup_board[0][2] == GameStatus::Won(mark)
-            && self.sup_board[1][1] == GameStatus::Won(mark)
-            && self.sup_board[2][0] == GameStatus::Won(mark);
-        let won_super_board = won_row || won_col || won_diag_se || won_diag_ne;
-        if won_super_board {
-            self.sup_board_status = GameStatus::Won(mark);
+        if WINS[self.cells[p][b] as usize] {
+            self.won[p] |= 1 << b;
+            if WINS[self.won[p] as usize] {
+                self.sup_board_status = GameStatus::Won(mark);
+            }
+        } else if self.cells[0][b] | self.cells[1][b] == FULL {
+            self.drawn |= 1 << b;
         }
 
-        // check if the super-board is a draw
-        let super_board_full = self
-            .sup_board
-            .iter()
-            .all(|row| row.iter().all(|&x| x != GameStatus::Undecided));
-        if super_board_full && !won_super_board {
+        // the super-board is a draw if all sub-boards are decided but nobody got three in a row
+        if self.sup_board_status == GameStatus::Undecided && self.decided() == FULL {
             self.sup_board_status = GameStatus::Draw;
         }
 
-        self.last_action = Some(action);
+        self.current_player = mark.other();
+        let next = action.position_index();
+        self.target_board = (self.decided() & (1 << next) == 0).then_some(next as u8);
     }
 
-    /// Return `true`` if the move is a valid move
-    fn validate(&self, action: Action) -> bool {
-        if self.last_action.is_none() {
-            return true;
+    /// Check that the move is allowed by the rules, ignoring whose turn it is
+    fn validate(&self, action: Action) -> Result<(), MoveError> {
+        if self.sup_board_status != GameStatus::Undecided {
+            return Err(MoveError::GameOver);
         }
 
-        let desired_target_board = self.last_action.unwrap().position;
-
         // if the target board is running, verify that the new move is in that board
-        if self.sup_board[desired_target_board.0][desired_target_board.1] == GameStatus::Undecided
-            && action.board != desired_target_board
-        {
-            return false;
+        if let Some(target_board) = self.target_board() {
+            if action.board != target_board {
+                return Err(MoveError::WrongSubBoard);
+            }
+        }
+
+        // sub-boards that are decided are closed for play
+        let b = action.board_index();
+        if self.decided() & (1 << b) != 0 {
+            return Err(MoveError::WrongSubBoard);
         }
 
         // is the target position playable in the target board?
-        if self.board[action.board.0][action.board.1][action.position.0][action.position.1]
-            .is_some()
-        {
-            return false;
+        if (self.cells[0][b] | self.cells[1][b]) & (1 << action.position_index()) != 0 {
+            return Err(MoveError::Occupied);
         }
 
-        true
+        Ok(())
     }
     pub fn get_winner(&self) -> GameStatus {
         self.sup_board_status
     }
     pub fn n_moves_made(&self) -> usize {
-        self.board
+        self.cells
             .iter()
             .flatten()
-            .flatten()
-            .flatten()
-            .filter(|&x| x.is_some())
-            .count()
+            .map(|sub_board| sub_board.count_ones() as usize)
+            .sum()
     }
 
-    pub fn get_board(&self) -> &[[[[Option<PlayerMark>; 3]; 3]; 3]; 3] {
-        &self.board
+    /// The whole board as a nested array, where board[i][j][k][l] is position (k, l) in the sub-board at position (i, j)
+    pub fn get_board(&self) -> [[[[Option<PlayerMark>; 3]; 3]; 3]; 3] {
+        let mut board = [[[[None; 3]; 3]; 3]; 3];
+        for (i, board_row) in board.iter_mut().enumerate() {
+            for (j, sub_board) in board_row.iter_mut().enumerate() {
+                for (k, row) in sub_board.iter_mut().enumerate() {
+                    for (l, cell) in row.iter_mut().enumerate() {
+                        *cell = self.cell(i, j, k, l);
+                    }
+                }
+            }
+        }
+        board
     }
 }
 
@@ -187,6 +250,34 @@ pub struct Action {
     position: (usize, usize),
 }
 
+impl Action {
+    /// The sub-board as a bit index 0-8
+    pub fn board_index(&self) -> usize {
+        3 * self.board.0 + self.board.1
+    }
+    /// The position within the sub-board as a bit index 0-8
+    pub fn position_index(&self) -> usize {
+        3 * self.position.0 + self.position.1
+    }
+    fn from_indices(board: usize, position: usize) -> Self {
+        Self {
+            board: (board / 3, board % 3),
+            position: (position / 3, position % 3),
+        }
+    }
+}
+
+/// What is needed to take back a move on a `UTTTBoard`.
+/// A move can change the status of its own sub-board and of the super-board, so both are remembered.
+#[derive(Debug, Clone, Copy)]
+pub struct UTTTUndo {
+    action: Action,
+    target_board: Option<u8>,
+    won: [u16; 2],
+    drawn: u16,
+    sup_board_status: GameStatus,
+}
+
 impl Display for Action {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -223,8 +314,7 @@ impl Display for UTTTBoard {
                 board.push('|');
                 for j in 0..3 {
                     for l in 0..3 {
-                        let mark = self.board[i][j][k][l];
-                        let mark = match mark {
+                        let mark = match self.cell(i, j, k, l) {
                             Some(PlayerMark::Naught) => "O",
                             Some(PlayerMark::Cross) => "X",
                             None => " ",
@@ -242,56 +332,227 @@ impl Display for UTTTBoard {
 }
 
 impl Board for UTTTBoard {
-    fn valid_moves(&self) -> Vec<Action> {
-        let mut moves = vec![];
-        for i in 0..3 {
-            for j in 0..3 {
-                if self.sup_board[i][j] != GameStatus::Undecided {
-                    continue;
-                }
-                for k in 0..3 {
-                    for l in 0..3 {
-                        let action = Action::try_from((i, j, k, l)).unwrap();
-                        if self.validate(action) {
-                            moves.push(action);
-                        }
-                    }
-                }
+    fn valid_moves_into(&self, moves: &mut Vec<Action>) {
+        moves.clear();
+        if self.sup_board_status != GameStatus::Undecided {
+            return;
+        }
+        let boards = match self.target_board() {
+            Some((i, j)) => 1 << (3 * i + j),
+            None => FULL & !self.decided(),
+        };
+        for b in (0..9).filter(|b| boards & (1 << b) != 0) {
+            let free = FULL & !(self.cells[0][b] | self.cells[1][b]);
+            for position in (0..9).filter(|p| free & (1 << p) != 0) {
+                moves.push(Action::from_indices(b, position));
             }
         }
-        moves
+    }
+    fn check_move(&self, a: Action, marker: PlayerMark) -> Result<(), MoveError> {
+        if self.sup_board_status != GameStatus::Undecided {
+            return Err(MoveError::GameOver);
+        }
+        if marker != self.current_player {
+            return Err(MoveError::WrongPlayer);
+        }
+        self.validate(a)
     }
     fn place_mark(&mut self, a: Action, marker: PlayerMark) {
         self.place_mark(a, marker);
     }
+    type Undo = UTTTUndo;
+    fn make_move(&mut self, a: Action, marker: PlayerMark) -> UTTTUndo {
+        let undo = UTTTUndo {
+            action: a,
+            target_board: self.target_board,
+            won: self.won,
+            drawn: self.drawn,
+            sup_board_status: self.sup_board_status,
+        };
+        self.place_mark(a, marker);
+        undo
+    }
+    fn unmake_move(&mut self, undo: UTTTUndo) {
+        let a = undo.action;
+        let bit = 1 << a.position_index();
+        let b = a.board_index();
+        let mark = if self.cells[0][b] & bit != 0 {
+            PlayerMark::Naught
+        } else if self.cells[1][b] & bit != 0 {
+            PlayerMark::Cross
+        } else {
+            panic!("Can only take back a move that was made")
+        };
+        self.cells[idx(mark)][b] ^= bit;
+        ZOBRIST.update(&mut self.zhash, a, mark);
+        self.won = undo.won;
+        self.drawn = undo.drawn;
+        self.sup_board_status = undo.sup_board_status;
+        self.current_player = mark;
+        self.target_board = undo.target_board;
+    }
     fn game_status(&self) -> GameStatus {
         self.sup_board_status
     }
     fn current_player(&self) -> PlayerMark {
-        // count the number of noughts and crosses
-        // if they are equal, it is naughts turn
-        let noughts = self
-            .board
-            .iter()
-            .flatten()
-            .flatten()
-            .flatten()
-            .filter(|&x| *x == Some(PlayerMark::Naught))
-            .count();
-        let crosses = self
-            .board
-            .iter()
-            .flatten()
-            .flatten()
-            .flatten()
-            .filter(|&x| *x == Some(PlayerMark::Cross))
-            .count();
-        if noughts == crosses {
-            PlayerMark::Naught
-        } else {
-            PlayerMark::Cross
-        }
+        self.current_player
+    }
+    fn key(&self) -> u64 {
+        self.zhash ^ ZOBRIST.target(self.target_board())
     }
 
     type Coordinate = Action;
+    const NAME: &'static str = "uttt";
+}
+
+/// Zobrist is a struct that helps with updating the hash of game states
+/// It implements an interface for simple Zobrist hashing
+/// Cells are indexed as board[i][j][k][l], and there is one key for each possible target board, plus one for "play anywhere"
+struct Zobrist {
+    xs: [[[[u64; 3]; 3]; 3]; 3],
+    os: [[[[u64; 3]; 3]; 3]; 3],
+    targets: [[u64; 3]; 3],
+    anywhere: u64,
+    empty: u64,
+}
+
+impl Zobrist {
+    fn make(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let xs = rng.gen();
+        let os = rng.gen();
+        let targets = rng.gen();
+        let anywhere = rng.gen();
+        let empty = rng.gen();
+        Self {
+            xs,
+            os,
+            targets,
+            anywhere,
+            empty,
+        }
+    }
+    fn update(&self, zhash: &mut u64, action: Action, marker: PlayerMark) {
+        let keys = match marker {
+            PlayerMark::Naught => &self.os,
+            PlayerMark::Cross => &self.xs,
+        };
+        *zhash ^= keys[action.board.0][action.board.1][action.position.0][action.position.1];
+    }
+    fn target(&self, target_board: Option<(usize, usize)>) -> u64 {
+        match target_board {
+            Some((i, j)) => self.targets[i][j],
+            None => self.anywhere,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+
+    use super::*;
+
+    fn hash_of(b: &UTTTBoard) -> u64 {
+        let mut h = DefaultHasher::new();
+        b.hash(&mut h);
+        h.finish()
+    }
+
+    fn play(moves: &[(usize, usize, usize, usize)]) -> UTTTBoard {
+        let mut b = UTTTBoard::default();
+        for &m in moves {
+            b.try_place_mark(Action::try_from(m).unwrap(), b.current_player())
+                .unwrap();
+        }
+        b
+    }
+
+    #[test]
+    fn transpositions_are_equal() {
+        let b1 = play(&[(1, 1, 0, 0), (0, 0, 1, 1), (1, 1, 2, 2), (2, 2, 1, 1)]);
+        let b2 = play(&[(1, 1, 2, 2), (2, 2, 1, 1), (1, 1, 0, 0), (0, 0, 1, 1)]);
+        assert_eq!(b1, b2);
+        assert_eq!(hash_of(&b1), hash_of(&b2));
+    }
+
+    #[test]
+    fn target_board_is_part_of_identity() {
+        let b1 = play(&[(1, 1, 0, 0), (0, 0, 1, 1), (1, 1, 2, 2), (2, 2, 0, 0)]);
+        // same markers, but as if the last move had been made in another position
+        let mut b2 = b1;
+        b2.target_board = Some(7);
+        assert_eq!(b1.cells, b2.cells);
+        assert_ne!(b1.target_board(), b2.target_board());
+        assert_ne!(b1, b2);
+        assert_ne!(hash_of(&b1), hash_of(&b2));
+    }
+
+    #[test]
+    fn zobrist_survives_unmake() {
+        let mut b = play(&[(1, 1, 0, 0), (0, 0, 1, 1)]);
+        let before = b;
+        let undo = b.make_move(Action::try_from((1, 1, 2, 2)).unwrap(), b.current_player());
+        assert_ne!(b.zhash, before.zhash);
+        b.unmake_move(undo);
+        assert_eq!(b.zhash, before.zhash);
+        assert_eq!(hash_of(&b), hash_of(&before));
+    }
+
+    #[test]
+    #[should_panic(expected = "Illegal move")]
+    fn illegal_moves_panic() {
+        let mut b = play(&[(1, 1, 0, 2)]);
+        // the top right sub-board is the target
+        b.place_mark(Action::try_from((1, 1, 1, 1)).unwrap(), b.current_player());
+    }
+
+    /// Play random games, and check the packed state against a naive recount after every move
+    #[test]
+    fn packed_state_is_consistent() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..200 {
+            let mut b = UTTTBoard::default();
+            while !b.game_is_over() {
+                let moves = b.valid_moves();
+                for &m in &moves {
+                    assert_eq!(b.check_move(m, b.current_player()), Ok(()));
+                }
+                let m = moves[rng.gen_range(0..moves.len())];
+                b.place_mark(m, b.current_player());
+                let flat = b.get_board();
+                let count = |mark| flat.iter().flatten().flatten().flatten().filter(|&&x| x == Some(mark)).count();
+                let expected_player = if count(PlayerMark::Naught) == count(PlayerMark::Cross) {
+                    PlayerMark::Naught
+                } else {
+                    PlayerMark::Cross
+                };
+                assert_eq!(b.current_player(), expected_player);
+                let sup = b.get_sup_board();
+                let lines = [
+                    [(0, 0), (0, 1), (0, 2)],
+                    [(1, 0), (1, 1), (1, 2)],
+                    [(2, 0), (2, 1), (2, 2)],
+                    [(0, 0), (1, 0), (2, 0)],
+                    [(0, 1), (1, 1), (2, 1)],
+                    [(0, 2), (1, 2), (2, 2)],
+                    [(0, 0), (1, 1), (2, 2)],
+                    [(0, 2), (1, 1), (2, 0)],
+                ];
+                let winner = [PlayerMark::Naught, PlayerMark::Cross].into_iter().find(|&mark| {
+                    lines
+                        .iter()
+                        .any(|line| line.iter().all(|&(i, j)| sup[i][j] == GameStatus::Won(mark)))
+                });
+                match winner {
+                    Some(mark) => assert_eq!(b.game_status(), GameStatus::Won(mark)),
+                    None if sup.iter().flatten().all(|&x| x != GameStatus::Undecided) => {
+                        assert_eq!(b.game_status(), GameStatus::Draw)
+                    }
+                    None => assert_eq!(b.game_status(), GameStatus::Undecided),
+                }
+            }
+            assert!(b.valid_moves().is_empty());
+        }
+    }
 }
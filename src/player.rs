@@ -5,9 +5,10 @@ pub mod mcts;
 pub mod console;
 mod heuristics;
 
-pub use mcts::MctsAi;
+pub use mcts::{merge_memory_files, read_memory_file, Eviction, FinalMove, MctsAi, MctsEngine, MemoryError, MemoryLimit, MemoryUsage, Parallelism, Selection};
 pub use alpha_beta::ABAi;
 pub use min_max::MinMaxAi;
 pub use random::RandomAi;
 pub use heuristics::{ttt_heuristic, c4_heuristic, uttt_heuristic};
+pub use heuristics::{ttt_move_prior, c4_move_prior, uttt_move_prior};
 pub use console::ConsolePlayer;
\ No newline at end of file
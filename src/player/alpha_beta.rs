@@ -1,108 +1,453 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
 use log::debug;
 
-use crate::core::{BlitzPlayer, Board, HeuristicFn, Player, PlayerMark};
+use crate::core::{BlitzPlayer, Board, HeuristicFn, MovePriorFn, Player, RootMove, ScoreBound, SearchInfo};
+
+pub mod ordering;
+pub mod transposition;
+
+use ordering::MoveOrdering;
+use transposition::{Bound, TranspositionTable};
+
+/// How large part of the remaining time on the clock to spend on one move
+const TIME_BUDGET_FRACTION: u32 = 8;
+/// Look at the clock every this many leaf evaluations
+const CLOCK_CHECK_INTERVAL: usize = 64;
+
+/// The moves at the root with their scores, best first, and the principal variation
+type RootResult<C> = (Vec<RootMove<C>>, Vec<C>);
 
-pub struct ABAi<B> {
-    my_marker: PlayerMark,
+/// What one thread found out about one move at the root: its index in the list of root moves, its score,
+/// whether the score is exact, and the principal variation
+type RootMoveResult<C> = (usize, f64, ScoreBound, Vec<C>);
+
+/// Alpha-beta search in negamax form: every score is from the point of view of the player to move in that node.
+/// The side to play for is read from the board at the root, so one instance can play either side.
+///
+/// The search can use several threads, by splitting the moves at the root between them.
+/// Each thread has its own transposition table, killer moves and history, so the scores can depend on how the moves were split.
+///
+/// The moves at the root are pruned too: once a move has been scored, the moves after it are only searched far enough
+/// to show that they are no better. Their scores in `SearchInfo::root_moves` are then marked as upper bounds, see `set_exact_root_scores`.
+pub struct ABAi<B: Board> {
     /// A performance counter. If we prune well, this number is small
     n_leafs_evaluated: usize,
+    /// Every call of `alphabeta`, leafs included
+    n_nodes_visited: usize,
     heuristic_fn: HeuristicFn<B>,
     max_depth: usize,
+    /// One buffer of moves per depth, so that the search doesn't allocate
+    move_buffers: Vec<Vec<B::Coordinate>>,
+    /// When searching on a time budget, give up when this time has passed
+    deadline: Option<Instant>,
+    /// Set when the deadline passed during the search. All scores computed after that are garbage.
+    timed_out: bool,
+    /// Remembers positions that have already been searched. None if disabled.
+    tt: Option<TranspositionTable<B::Coordinate>>,
+    tt_size: usize,
+    ordering: MoveOrdering<B>,
+    /// Searchers for the other threads, each with its own transposition table and move ordering
+    helpers: Vec<ABAi<B>>,
+    /// Search every move at the root with a full window, to get their exact scores
+    exact_root_scores: bool,
+    /// The principal variation of the last node searched at each depth
+    pv: Vec<Vec<B::Coordinate>>,
+    last_search: Option<SearchInfo<B::Coordinate>>,
 }
 
-impl<B: Board + Clone> ABAi<B> {
-    pub fn new(mark: PlayerMark, heuristic_fn: HeuristicFn<B>, depth: usize) -> Self {
+impl<B> ABAi<B>
+where
+    B: Board + Clone + Send + Sync,
+    B::Coordinate: Send + Sync,
+{
+    pub fn new(heuristic_fn: HeuristicFn<B>, depth: usize) -> Self {
         ABAi {
-            my_marker: mark,
             n_leafs_evaluated: 0,
+            n_nodes_visited: 0,
             heuristic_fn,
             max_depth: depth,
+            move_buffers: vec![],
+            deadline: None,
+            timed_out: false,
+            tt: None,
+            tt_size: 0,
+            ordering: MoveOrdering::default(),
+            helpers: vec![],
+            exact_root_scores: false,
+            pv: vec![],
+            last_search: None,
+        }
+    }
+
+    /// Search the moves with a high prior first. None searches them in the order the board gives them.
+    pub fn set_move_prior(&mut self, prior: Option<MovePriorFn<B>>) {
+        self.ordering.set_prior(prior);
+        self.rebuild_helpers();
+    }
+
+    /// Search moves that caused a cutoff in a sibling node first
+    pub fn set_killer_moves(&mut self, on: bool) {
+        self.ordering.set_killers(on);
+        self.rebuild_helpers();
+    }
+
+    /// Search moves that have caused many cutoffs anywhere in the tree first
+    pub fn set_history_heuristic(&mut self, on: bool) {
+        self.ordering.set_history(on);
+        self.rebuild_helpers();
+    }
+
+    /// Use a transposition table with room for `size` positions. Zero turns it off.
+    /// It is kept between moves, since the scores are from the point of view of the player to move, whoever that is.
+    pub fn set_transposition_table_size(&mut self, size: usize) {
+        self.tt = (size > 0).then(|| TranspositionTable::new(size));
+        self.tt_size = size;
+        self.rebuild_helpers();
+    }
+
+    /// Search every move at the root with a full window, so that all the scores in `SearchInfo::root_moves` are exact,
+    /// not just the score of the best move. This is off by default, since it makes the search slower.
+    pub fn set_exact_root_scores(&mut self, on: bool) {
+        self.exact_root_scores = on;
+        self.rebuild_helpers();
+    }
+
+    /// Search on this many threads. Every thread has its own transposition table, of the size set for this AI.
+    pub fn set_threads(&mut self, n: usize) {
+        self.helpers = (1..n).map(|_| self.helper()).collect();
+    }
+
+    /// A searcher with the same settings as this one, for another thread
+    fn helper(&self) -> Self {
+        let mut h = ABAi::new(self.heuristic_fn, self.max_depth);
+        h.tt = (self.tt_size > 0).then(|| TranspositionTable::new(self.tt_size));
+        h.tt_size = self.tt_size;
+        h.ordering = self.ordering.same_settings();
+        h.exact_root_scores = self.exact_root_scores;
+        h
+    }
+
+    /// Make the helpers again after the settings changed
+    fn rebuild_helpers(&mut self) {
+        self.set_threads(self.helpers.len() + 1);
+    }
+
+    fn new_search(&mut self) {
+        self.ordering.new_search();
+        for h in self.helpers.iter_mut() {
+            h.ordering.new_search();
         }
     }
 
+    /// The number of leaf nodes that have been evaluated with the heuristic, over all searches so far
+    pub fn n_leafs_evaluated(&self) -> usize {
+        self.n_leafs_evaluated
+    }
+
     fn heuristic(&mut self, b: &B) -> f64 {
         self.n_leafs_evaluated += 1;
-        (self.heuristic_fn)(self.my_marker, b)
+        (self.heuristic_fn)(b.current_player(), b)
+    }
+
+    /// Check the clock. Once the deadline has passed, this stays true for the rest of the search.
+    /// Reading the clock is not free, so it is only done every now and then.
+    fn out_of_time(&mut self) -> bool {
+        if !self.timed_out && self.n_leafs_evaluated.is_multiple_of(CLOCK_CHECK_INTERVAL) {
+            if let Some(deadline) = self.deadline {
+                self.timed_out = Instant::now() >= deadline;
+            }
+        }
+        self.timed_out
     }
 
-    /// compute the score of a node by use of alpha-beta with pruning
-    /// Assumes I want to maximize my score, and the opponent makes moves to minimize it
-    fn alphabeta(&mut self, node: &B, depth: usize, a: f64, b: f64, my_move: bool) -> f64 {
+    /// compute the score of a node by use of alpha-beta with pruning, for the player to move in the node.
+    /// The player to move wants to maximize the score, and the score for the opponent is the negation of it.
+    /// The node is used as scratch space: moves are made and taken back, so it is unchanged when this returns
+    fn alphabeta(&mut self, node: &mut B, depth: usize, a: f64, b: f64) -> f64 {
+        self.n_nodes_visited += 1;
+        if self.pv.len() <= depth {
+            self.pv.resize_with(depth + 1, Vec::new);
+        }
+        self.pv[depth].clear();
+        if self.out_of_time() {
+            return 0.0;
+        }
         if depth == 0 || node.game_is_over() {
             let s = self.heuristic(node);
             // println!("Leaf node board\n {node} gets score {s}, at {depth}. Compare with {a} and {b}");
             return s;
         }
-        let moves = node.valid_moves();
         let mut a = a;
         let mut b = b;
-        let my_marker = self.my_marker; // take a copy here
-        if my_move {
-            // In this branch, the AI tries to find a move for itself that would maximize the score
-            let mut value = -f64::INFINITY;
-            let child_nodes = moves.iter().map(|addr| {
-                let mut child = (*node).clone();
-                child.place_mark(*addr, my_marker);
-                child
-            });
-            for child in child_nodes {
-                let newval = self.alphabeta(&child, depth - 1, a, b, false);
-                value = value.max(newval);
-                a = a.max(value);
-                if value >= b {
-                    break;
+        let key = node.key();
+        let mut hash_move = None;
+        if let Some(entry) = self.tt.as_ref().and_then(|tt| tt.probe(key)) {
+            hash_move = entry.best_move;
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return entry.score,
+                    Bound::Lower => a = a.max(entry.score),
+                    Bound::Upper => b = b.min(entry.score),
+                }
+                if a >= b {
+                    return entry.score;
                 }
             }
-            value
-        } else {
-            // In this branch, the AI tries to find a move for the other player that would minimize the score
-            let mut value = f64::INFINITY;
-            let child_nodes = moves.iter().map(|addr| {
-                let mut child = (*node).clone();
-                child.place_mark(*addr, my_marker.other());
-                child
+        }
+        // the window that is actually searched, to tell what kind of bound the result is
+        let (a0, b0) = (a, b);
+        let mut moves = self.take_moves(node, depth);
+        self.ordering.order(node, &mut moves, depth, hash_move);
+        let mover = node.current_player();
+        let mut best_move = None;
+        let mut value = -f64::INFINITY;
+        for &addr in moves.iter() {
+            let undo = node.make_move(addr, mover);
+            let newval = -self.alphabeta(node, depth - 1, -b, -a);
+            node.unmake_move(undo);
+            if newval > value || best_move.is_none() {
+                value = newval;
+                best_move = Some(addr);
+                let (child, this) = self.pv.split_at_mut(depth);
+                this[0].clear();
+                this[0].push(addr);
+                this[0].extend_from_slice(&child[depth - 1]);
+            }
+            a = a.max(value);
+            if value >= b {
+                self.ordering.record_cutoff(addr, depth);
+                break;
+            }
+        }
+        self.move_buffers[depth] = moves;
+        if !self.timed_out {
+            if let Some(tt) = self.tt.as_mut() {
+                let bound = if value <= a0 {
+                    Bound::Upper
+                } else if value >= b0 {
+                    Bound::Lower
+                } else {
+                    Bound::Exact
+                };
+                tt.store(key, depth, value, bound, best_move);
+            }
+        }
+        value
+    }
+
+    /// Fill the move buffer for this depth and hand it out. Give it back by putting it in `move_buffers[depth]` when done.
+    fn take_moves(&mut self, node: &B, depth: usize) -> Vec<B::Coordinate> {
+        if self.move_buffers.len() <= depth {
+            self.move_buffers.resize_with(depth + 1, Vec::new);
+        }
+        let mut moves = std::mem::take(&mut self.move_buffers[depth]);
+        node.valid_moves_into(&mut moves);
+        moves
+    }
+
+    /// Take moves at the root, from the shared counter `next`, and search them until there are no more.
+    /// A move is searched with a window above the best score this thread has found so far, unless exact scores are asked for.
+    /// Then the score of a move that is no better is only an upper bound.
+    fn search_root_moves(
+        &mut self,
+        b: &B,
+        moves: &[B::Coordinate],
+        depth: usize,
+        next: &AtomicUsize,
+    ) -> Vec<RootMoveResult<B::Coordinate>> {
+        let mut b2 = (*b).clone();
+        let mover = b.current_player();
+        let mut results = vec![];
+        let mut alpha = -f64::INFINITY;
+        while !self.timed_out {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            let Some(&addr) = moves.get(i) else {
+                break;
+            };
+            let undo = b2.make_move(addr, mover);
+            let score = -self.alphabeta(&mut b2, depth, -f64::INFINITY, -alpha);
+            b2.unmake_move(undo);
+            // searched with a window above alpha, a score that is no better is only a bound
+            let bound = if score > alpha { ScoreBound::Exact } else { ScoreBound::Upper };
+            if !self.exact_root_scores {
+                alpha = alpha.max(score);
+            }
+            results.push((i, score, bound, self.pv[depth].clone()));
+        }
+        results
+    }
+
+    /// Score every move at the root by searching `depth` plies below it, on all threads.
+    /// Ties are broken by the given order of the moves.
+    /// Returns the moves with their scores, best first, and the principal variation.
+    /// Returns None if the deadline passed before the search was done.
+    fn search_root(&mut self, b: &B, moves: &[B::Coordinate], depth: usize) -> Option<RootResult<B::Coordinate>> {
+        let next = AtomicUsize::new(0);
+        let mut helpers = std::mem::take(&mut self.helpers);
+        let mut results = std::thread::scope(|scope| {
+            let handles: Vec<_> = helpers
+                .iter_mut()
+                .map(|h| {
+                    h.deadline = self.deadline;
+                    h.timed_out = false;
+                    let next = &next;
+                    scope.spawn(move || h.search_root_moves(b, moves, depth, next))
+                })
+                .collect();
+            let mut results = self.search_root_moves(b, moves, depth, &next);
+            for handle in handles {
+                results.extend(handle.join().expect("A search thread panicked"));
+            }
+            results
+        });
+        for h in helpers.iter_mut() {
+            self.timed_out |= h.timed_out;
+            self.n_nodes_visited += std::mem::take(&mut h.n_nodes_visited);
+            self.n_leafs_evaluated += std::mem::take(&mut h.n_leafs_evaluated);
+        }
+        self.helpers = helpers;
+        if self.timed_out {
+            return None;
+        }
+        results.sort_by_key(|r| r.0);
+        let mut pv = vec![];
+        let mut ranked: Vec<RootMove<B::Coordinate>> = Vec::with_capacity(moves.len());
+        for (i, score, bound, line) in results {
+            if ranked.iter().all(|r| score > r.score) {
+                pv.clear();
+                pv.push(moves[i]);
+                pv.extend(line);
+            }
+            ranked.push(RootMove {
+                coordinate: moves[i],
+                score,
+                bound,
             });
-            for child in child_nodes {
-                value = value.min(self.alphabeta(&child, depth - 1, a, b, true));
-                b = b.min(value);
-                if value <= a {
+        }
+        // stable, so the first of the equally good moves stays first.
+        // A bound can tie with the best score of another thread, but the exact score goes first.
+        ranked.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.bound.cmp(&b.bound))
+        });
+        Some((ranked, pv))
+    }
+
+    /// Search the position to the max depth, and tell what was found
+    pub fn search(&mut self, b: &B) -> SearchInfo<B::Coordinate> {
+        let t0 = Instant::now();
+        let nodes0 = self.n_nodes_visited;
+        self.new_search();
+        let mut moves = b.valid_moves();
+        self.ordering.order(b, &mut moves, self.max_depth + 1, None);
+        let (ranked, pv) = self
+            .search_root(b, &moves, self.max_depth)
+            .expect("There is no deadline, so the search can't time out");
+        let info = SearchInfo {
+            best_move: ranked[0].coordinate,
+            score: ranked[0].score,
+            principal_variation: pv,
+            depth: self.max_depth + 1,
+            nodes: self.n_nodes_visited - nodes0,
+            elapsed: t0.elapsed(),
+            root_moves: ranked,
+            outcome: None,
+            proven_moves: vec![],
+        };
+        self.last_search = Some(info.clone());
+        info
+    }
+
+    /// Iterative deepening: search 0, 1, 2... plies below the root moves, up to the max depth, until the time budget is spent.
+    /// Tells what the deepest search that was completed found.
+    /// The moves are searched best first, as ranked by the previous iteration.
+    pub fn search_with_budget(&mut self, b: &B, budget: Duration) -> SearchInfo<B::Coordinate> {
+        let t0 = Instant::now();
+        let nodes0 = self.n_nodes_visited;
+        self.deadline = Some(t0 + budget);
+        self.timed_out = false;
+        self.new_search();
+        let mut moves = b.valid_moves();
+        self.ordering.order(b, &mut moves, self.max_depth + 1, None);
+        let mut info = SearchInfo {
+            best_move: *moves.first().expect("At least one element"),
+            score: 0.0,
+            principal_variation: vec![],
+            depth: 0,
+            nodes: 0,
+            elapsed: Duration::ZERO,
+            root_moves: vec![],
+            outcome: None,
+            proven_moves: vec![],
+        };
+        for depth in 0..=self.max_depth {
+            match self.search_root(b, &moves, depth) {
+                Some((ranked, pv)) => {
+                    moves.clear();
+                    moves.extend(ranked.iter().map(|r| r.coordinate));
+                    info.best_move = ranked[0].coordinate;
+                    info.score = ranked[0].score;
+                    info.principal_variation = pv;
+                    info.depth = depth + 1;
+                    info.root_moves = ranked;
+                }
+                None => {
+                    debug!("ABAi ran out of time in depth {}", depth);
                     break;
                 }
             }
-            value
         }
+        self.deadline = None;
+        self.timed_out = false;
+        info.nodes = self.n_nodes_visited - nodes0;
+        info.elapsed = t0.elapsed();
+        self.last_search = Some(info.clone());
+        info
+    }
+
+    /// Like `search_with_budget`, but only the best move
+    pub fn play_with_budget(&mut self, b: &B, budget: Duration) -> B::Coordinate {
+        self.search_with_budget(b, budget).best_move
     }
 }
 
-impl<B: Board + Clone> BlitzPlayer<B> for ABAi<B> {
-    fn blitz(&mut self, b: &B, _time_remaining: std::time::Duration) -> <B as Board>::Coordinate {
-        self.play(b)
+impl<B> BlitzPlayer<B> for ABAi<B>
+where
+    B: Board + Clone + Send + Sync,
+    B::Coordinate: Send + Sync,
+{
+    fn blitz(&mut self, b: &B, time_remaining: std::time::Duration) -> <B as Board>::Coordinate {
+        self.play_with_budget(b, time_remaining / TIME_BUDGET_FRACTION)
+    }
+
+    fn last_search(&self) -> Option<&SearchInfo<B::Coordinate>> {
+        self.last_search.as_ref()
     }
 }
 
 
-impl<B: Board + Clone> Player<B> for ABAi<B> {
+impl<B> Player<B> for ABAi<B>
+where
+    B: Board + Clone + Send + Sync,
+    B::Coordinate: Send + Sync,
+{
     fn play(&mut self, b: &B) -> B::Coordinate {
-        let res = b
-            .valid_moves()
-            .iter()
-            .map(|addr| {
-                let mut b2 = (*b).clone();
-                b2.place_mark(*addr, self.my_marker);
-                let score =
-                    self.alphabeta(&b2, self.max_depth, -f64::INFINITY, f64::INFINITY, false);
-                (score, addr)
-            })
-            // .inspect(|x| println!("about to pick the best: {x:?}"))
-            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
-            .map(|(_, &q)| q)
-            .expect("At least one element");
-        res
+        self.search(b).best_move
+    }
+
+    fn last_search(&self) -> Option<&SearchInfo<B::Coordinate>> {
+        self.last_search.as_ref()
     }
 }
 
-impl<M> Drop for ABAi<M> {
+impl<M: Board> Drop for ABAi<M> {
     fn drop(&mut self) {
-        debug!("ABAi evaluated {} leaf nodes", self.n_leafs_evaluated);
+        debug!("ABAi visited {} nodes and evaluated {} leaf nodes", self.n_nodes_visited, self.n_leafs_evaluated);
     }
-}
\ No newline at end of file
+}
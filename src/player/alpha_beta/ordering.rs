@@ -0,0 +1,143 @@
+//! Move ordering for the alpha-beta search.
+//! Alpha-beta prunes the most when the best move is searched first, so every node sorts its moves by how promising they look.
+//! In order of precedence:
+//! 1. The hash move: the best move found the last time the position was searched, from the transposition table
+//! 2. Killer moves: moves that caused a cutoff in a sibling node, i.e. at the same depth
+//! 3. The history heuristic: moves that have caused many cutoffs anywhere in the tree, weighted by depth
+//! 4. A static prior for the game, like "centre columns first" in Connect Four
+//!
+//! Ties keep the order that `valid_moves` gives them.
+
+use std::{cmp::Reverse, collections::HashMap};
+
+use crate::core::{Board, MovePriorFn};
+
+/// Number of killer moves remembered per depth
+const N_KILLERS: usize = 2;
+
+pub struct MoveOrdering<B: Board> {
+    prior: Option<MovePriorFn<B>>,
+    use_killers: bool,
+    use_history: bool,
+    /// The last moves that caused a cutoff, per remaining depth. Newest first.
+    killers: Vec<[Option<B::Coordinate>; N_KILLERS]>,
+    /// Sum of depth^2 over all cutoffs caused by each move
+    history: HashMap<B::Coordinate, u64>,
+}
+
+impl<B: Board> Default for MoveOrdering<B> {
+    fn default() -> Self {
+        MoveOrdering {
+            prior: None,
+            use_killers: false,
+            use_history: false,
+            killers: vec![],
+            history: HashMap::new(),
+        }
+    }
+}
+
+impl<B: Board> MoveOrdering<B> {
+    /// An ordering with the same settings, but nothing learned yet
+    pub fn same_settings(&self) -> Self {
+        MoveOrdering {
+            prior: self.prior,
+            use_killers: self.use_killers,
+            use_history: self.use_history,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_prior(&mut self, prior: Option<MovePriorFn<B>>) {
+        self.prior = prior;
+    }
+
+    pub fn set_killers(&mut self, on: bool) {
+        self.use_killers = on;
+        self.killers.clear();
+    }
+
+    pub fn set_history(&mut self, on: bool) {
+        self.use_history = on;
+        self.history.clear();
+    }
+
+    /// Forget the killers, which were for a different root, and age the history so that new cutoffs count more
+    pub fn new_search(&mut self) {
+        self.killers.clear();
+        self.history.retain(|_, v| {
+            *v /= 2;
+            *v > 0
+        });
+    }
+
+    /// Sort the moves of a node with `depth` plies left to search, most promising first
+    pub fn order(&self, node: &B, moves: &mut [B::Coordinate], depth: usize, hash_move: Option<B::Coordinate>) {
+        let killers = match self.killers.get(depth) {
+            Some(k) if self.use_killers => *k,
+            _ => [None; N_KILLERS],
+        };
+        if self.prior.is_some() || self.use_history || killers[0].is_some() {
+            moves.sort_by_key(|&m| {
+                let killer_rank = killers
+                    .iter()
+                    .position(|&k| k == Some(m))
+                    .map_or(0, |i| N_KILLERS - i);
+                let history = if self.use_history {
+                    self.history.get(&m).copied().unwrap_or(0)
+                } else {
+                    0
+                };
+                let prior = self.prior.map_or(0, |f| f(node, m));
+                Reverse((killer_rank, history, prior))
+            });
+        }
+        if let Some(i) = hash_move.and_then(|m| moves.iter().position(|&x| x == m)) {
+            moves[..=i].rotate_right(1);
+        }
+    }
+
+    /// Remember that `m` caused a beta cutoff in a node with `depth` plies left to search
+    pub fn record_cutoff(&mut self, m: B::Coordinate, depth: usize) {
+        if self.use_killers {
+            if self.killers.len() <= depth {
+                self.killers.resize(depth + 1, [None; N_KILLERS]);
+            }
+            let k = &mut self.killers[depth];
+            if k[0] != Some(m) {
+                k.rotate_right(1);
+                k[0] = Some(m);
+            }
+        }
+        if self.use_history {
+            *self.history.entry(m).or_insert(0) += (depth * depth) as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::connect_four::C4Board;
+    use crate::player::c4_move_prior;
+
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        let b = C4Board::default();
+        let mut ordering = MoveOrdering::<C4Board>::default();
+        ordering.set_prior(Some(c4_move_prior));
+        ordering.set_killers(true);
+        ordering.set_history(true);
+        ordering.record_cutoff(6, 3);
+        ordering.record_cutoff(0, 2);
+        let mut moves = b.valid_moves();
+        ordering.order(&b, &mut moves, 2, Some(5));
+        // hash move, killer, history, then centre first
+        assert_eq!(moves, vec![5, 0, 6, 3, 2, 4, 1]);
+        ordering.new_search();
+        let mut moves = b.valid_moves();
+        ordering.order(&b, &mut moves, 2, None);
+        assert_eq!(moves, vec![6, 0, 3, 2, 4, 1, 5]);
+    }
+}
@@ -0,0 +1,103 @@
+//! A fixed-size transposition table for the alpha-beta search.
+//!
+//! Positions are looked up by `Board::key`. The table is split in buckets of two slots:
+//! the first slot keeps the entry that was searched deepest, the second is always replaced.
+//! Deep entries save the most work, but that slot would fill up with stale entries from old moves,
+//! so the second slot makes sure that recent positions always get stored.
+
+/// What the stored score says about the true score of the position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bound {
+    /// The score is exact
+    Exact,
+    /// The search failed high, so the true score is at least this
+    Lower,
+    /// The search failed low, so the true score is at most this
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<C> {
+    key: u64,
+    /// The number of plies searched below this position
+    pub depth: usize,
+    pub score: f64,
+    pub bound: Bound,
+    /// The best move found, or the move that caused the cutoff
+    pub best_move: Option<C>,
+}
+
+pub struct TranspositionTable<C> {
+    buckets: Vec<[Option<Entry<C>>; 2]>,
+    /// used to turn a key into a bucket index. The number of buckets is a power of two.
+    mask: u64,
+}
+
+impl<C: Copy> TranspositionTable<C> {
+    /// Make a table with room for (at least) `size` entries
+    pub fn new(size: usize) -> Self {
+        let n_buckets = size.div_ceil(2).next_power_of_two();
+        Self {
+            buckets: vec![[None; 2]; n_buckets],
+            mask: n_buckets as u64 - 1,
+        }
+    }
+
+    fn bucket(&self, key: u64) -> usize {
+        (key & self.mask) as usize
+    }
+
+    pub fn probe(&self, key: u64) -> Option<&Entry<C>> {
+        self.buckets[self.bucket(key)]
+            .iter()
+            .flatten()
+            .find(|e| e.key == key)
+    }
+
+    pub fn store(&mut self, key: u64, depth: usize, score: f64, bound: Bound, best_move: Option<C>) {
+        let entry = Entry {
+            key,
+            depth,
+            score,
+            bound,
+            best_move,
+        };
+        let i = self.bucket(key);
+        let bucket = &mut self.buckets[i];
+        match bucket[0] {
+            Some(old) if old.key != key && old.depth > depth => bucket[1] = Some(entry),
+            _ => {
+                // the deep slot is taken over, so what was there gets a second chance in the other slot
+                if let Some(old) = bucket[0].take() {
+                    if old.key != key {
+                        bucket[1] = Some(old);
+                    }
+                }
+                if bucket[1].is_some_and(|e| e.key == key) {
+                    bucket[1] = None;
+                }
+                bucket[0] = Some(entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deep_entries_are_kept() {
+        let mut tt = TranspositionTable::<usize>::new(2);
+        tt.store(1, 5, 1.0, Bound::Exact, Some(3));
+        tt.store(2, 1, 2.0, Bound::Lower, None);
+        tt.store(3, 1, 3.0, Bound::Upper, None);
+        assert_eq!(tt.probe(1).map(|e| e.depth), Some(5));
+        assert!(tt.probe(2).is_none());
+        assert_eq!(tt.probe(3).map(|e| e.bound), Some(Bound::Upper));
+        // a deeper search of the same position replaces the old entry
+        tt.store(3, 6, 4.0, Bound::Exact, Some(0));
+        assert_eq!(tt.probe(3).map(|e| e.score), Some(4.0));
+        assert_eq!(tt.probe(1).map(|e| e.score), Some(1.0));
+    }
+}
@@ -1,7 +1,7 @@
 use std::{io::BufRead, ops::Sub};
 
 use crate::{
-    core::{Player, PlayerMark},
+    core::{Board, Player, PlayerMark},
     game::{
         connect_four::C4Board,
         tictactoe::{self, TTTBoard},
@@ -28,21 +28,23 @@ impl Player<TTTBoard> for ConsolePlayer {
         println!("Time for {} to make a move", self.name);
         print!("{}", b);
         println!("Input a number 1-9 to make a move 1 = top left, 9 = bottom right");
-        let mut line = String::new();
-        std::io::stdin()
-            .lock()
-            .read_line(&mut line)
-            .expect("Could not read line");
-        let num = line
-            .chars()
-            .next()
-            .expect("At least one character must be input");
-        let num = num.to_string().parse::<_>().expect("Must input number");
-        if !(1..=9).contains(&num) {
-            eprintln!("Number not in range 1-N_SQUARES");
+        loop {
+            let line = read_line();
+            let num = match line.trim().parse::<usize>() {
+                Ok(num) => num,
+                Err(_) => {
+                    eprintln!("Must input a number");
+                    continue;
+                }
+            };
+            let addr = tictactoe::TTTAddr(num);
+            if let Err(e) = b.check_move(addr, b.current_player()) {
+                eprintln!("Invalid move: {}", e);
+                continue;
+            }
+            println!("Got {}", num);
+            return addr;
         }
-        println!("Got {}", num);
-        tictactoe::TTTAddr(num)
     }
 }
 impl Player<UTTTBoard> for ConsolePlayer {
@@ -60,19 +62,29 @@ impl Player<UTTTBoard> for ConsolePlayer {
             println!("You can play in any board");
         }
         println!("board-row board-col pos-row pos-col");
-        let mut line = String::new();
-        std::io::stdin()
-            .lock()
-            .read_line(&mut line)
-            .expect("Could not read line");
-        let nums = line
-            .split_ascii_whitespace()
-            .map(|x| x.parse::<usize>().expect("Must input number") - 1)
-            .collect::<Vec<_>>();
-        if nums.len() != 4 {
-            eprintln!("Must input 4 numbers, 1-3 with space in between. i j k l represents board on row i, column j, and in that board, play position row k col l");
+        loop {
+            let line = read_line();
+            let nums = line
+                .split_ascii_whitespace()
+                .map(|x| x.parse::<usize>().ok().and_then(|x| x.checked_sub(1)))
+                .collect::<Option<Vec<_>>>();
+            let action = match nums.as_deref() {
+                Some(&[i, j, k, l]) => ultimate_ttt::Action::try_from((i, j, k, l)),
+                _ => Err("Not four numbers".to_string()),
+            };
+            let action = match action {
+                Ok(action) => action,
+                Err(_) => {
+                    eprintln!("Must input 4 numbers, 1-3 with space in between. i j k l represents board on row i, column j, and in that board, play position row k col l");
+                    continue;
+                }
+            };
+            if let Err(e) = b.check_move(action, b.current_player()) {
+                eprintln!("Invalid move: {}", e);
+                continue;
+            }
+            return action;
         }
-        ultimate_ttt::Action::try_from((nums[0], nums[1], nums[2], nums[3])).unwrap()
     }
 }
 
@@ -85,11 +97,7 @@ impl Player<C4Board> for ConsolePlayer {
             let mut parse_ok = false;
             let mut outnum = 0;
             while !parse_ok {
-                let mut line = String::new();
-                std::io::stdin()
-                    .lock()
-                    .read_line(&mut line)
-                    .expect("Could not read line. Fatal error! Exiting...");
+                let line = read_line();
                 let num = line
                     .trim()
                     .chars()
@@ -101,12 +109,17 @@ impl Player<C4Board> for ConsolePlayer {
                 let num = num.unwrap().to_string().parse::<usize>();
                 if num.is_err() {
                     eprintln!("First char must be a number");
+                    continue;
                 }
                 let num = num.expect("We checked beore that this went ok");
                 if !(1..=7).contains(&num) {
                     eprintln!("Number not in range 1-7");
                     continue;
                 }
+                if let Err(e) = b.check_move(num - 1, b.current_player()) {
+                    eprintln!("Invalid move: {}", e);
+                    continue;
+                }
                 outnum = num;
                 parse_ok = true; // passed all checks
             }
@@ -119,3 +132,13 @@ impl Player<C4Board> for ConsolePlayer {
         num.sub(1)
     }
 }
+
+/// Read one line from stdin. There is no way to continue the game if stdin is gone, so that is fatal.
+fn read_line() -> String {
+    let mut line = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .expect("Could not read line. Fatal error! Exiting...");
+    line
+}
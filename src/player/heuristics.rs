@@ -1,14 +1,16 @@
-use std::f64::INFINITY;
-
 use crate::{
     core::{GameStatus, PlayerMark},
-    game::{connect_four::C4Board, tictactoe::TTTBoard, ultimate_ttt::UTTTBoard},
+    game::{
+        connect_four::C4Board,
+        tictactoe::{TTTAddr, TTTBoard},
+        ultimate_ttt::{Action, UTTTBoard},
+    },
 };
 
 pub fn ttt_heuristic(my_marker: PlayerMark, b: &TTTBoard) -> f64 {
     let n_moves_made: f64 = b.n_moves_made() as f64;
     match b.winner() {
-        None => 0.0 + n_moves_made,
+        None => 0.0,
         Some(mark) => {
             if mark == my_marker {
                 100.0 - n_moves_made
@@ -25,82 +27,56 @@ pub fn ttt_heuristic(my_marker: PlayerMark, b: &TTTBoard) -> f64 {
 /// <http://smpowell.com/wp-content/uploads/2021/07/Powell_Merrill_FinalPaper.pdf>
 ///
 pub fn uttt_heuristic(my_marker: PlayerMark, b: &UTTTBoard) -> f64 {
-    let n_moves_made: f64 = b.n_moves_made() as f64;
-    let n_supboards_win_balance: isize = b
-        .get_sup_board()
+    // +1 for my marker, -1 for the opponent's
+    let sign = |mark: PlayerMark| if mark == my_marker { 1 } else { -1 };
+    let sup_board = b.get_sup_board();
+    let n_supboards_win_balance: isize = sup_board
         .iter()
         .flatten()
         .map(|&x| match x {
-            GameStatus::Won(marker) => {
-                if marker == my_marker {
-                    1
-                } else {
-                    -1
-                }
-            }
+            GameStatus::Won(marker) => sign(marker),
             _ => 0,
         })
         .sum();
-    let did_win_mid_supboard = (b.get_sup_board()[1][1] == GameStatus::Won(my_marker)) as u8 as f64;
+    let mid_supboard_balance = match sup_board[1][1] {
+        GameStatus::Won(marker) => sign(marker) as f64,
+        _ => 0.0,
+    };
     let midpoint_balance = {
-        let board = b.get_board();
         let mut n = 0;
-        for sub_board in board.iter().flatten() {
-            n += match sub_board[1][1] {
-                None => 0,
-                Some(PlayerMark::Cross) => -1,
-                Some(PlayerMark::Naught) => 1,
+        for i in 0..3 {
+            for j in 0..3 {
+                n += b.cell(i, j, 1, 1).map_or(0, sign);
             }
         }
         n as f64
     };
     let win_bonus = match b.get_winner() {
         GameStatus::Undecided | GameStatus::Draw => 0.0,
-        GameStatus::Won(mark) => {
-            if mark == my_marker {
-                INFINITY
-            } else {
-                -INFINITY
-            }
-        }
+        GameStatus::Won(mark) => sign(mark) as f64 * f64::INFINITY,
     };
     win_bonus
-        + n_moves_made * 1.0
         + n_supboards_win_balance as f64 * 100.0
-        + did_win_mid_supboard * 30.0
+        + mid_supboard_balance * 30.0
         + 10.0 * midpoint_balance
 }
 
 pub fn c4_heuristic(my_marker: PlayerMark, b: &C4Board) -> f64 {
     let raw_board: [[Option<PlayerMark>; 6]; 7] = (*b).into();
-    let markers_in_col_3 = raw_board[2]
-        .iter()
-        .filter(|&&x| x == Some(my_marker))
-        .count() as f64;
-    let markers_in_col_4 = raw_board[3]
-        .iter()
-        .filter(|&&x| x == Some(my_marker))
-        .count() as f64;
-    let markers_in_col_5 = raw_board[4]
-        .iter()
-        .filter(|&&x| x == Some(my_marker))
-        .count() as f64;
-    let three_in_rows = {
-        let mut k = 0;
-        for row in 0..6 {
-            let mut j = 0;
-            for col in raw_board.iter().take(4) {
-                if col[row] == Some(my_marker) {
-                    j += 1;
-                } else {
-                    continue;
+    // how well placed the markers of one player are
+    let position = |mark: PlayerMark| {
+        let markers_in_col = |col: usize| raw_board[col].iter().filter(|&&x| x == Some(mark)).count() as f64;
+        let three_in_rows = {
+            let mut k = 0;
+            for row in 0..6 {
+                let j = raw_board.iter().take(4).filter(|col| col[row] == Some(mark)).count();
+                if j == 3 {
+                    k += 1;
                 }
             }
-            if j == 3 {
-                k += 1;
-            }
-        }
-        k as f64
+            k as f64
+        };
+        markers_in_col(2) + 2.0 * markers_in_col(3) + markers_in_col(4) + 5.0 * three_in_rows
     };
     let win = match b.winner() {
         Some(mark) => {
@@ -112,5 +88,26 @@ pub fn c4_heuristic(my_marker: PlayerMark, b: &C4Board) -> f64 {
         }
         _ => 0.0,
     };
-    100.0 * win + markers_in_col_3 + 2.0 * markers_in_col_4 + markers_in_col_5 + 5.0 * three_in_rows
+    100.0 * win + position(my_marker) - position(my_marker.other())
+}
+
+/// Rank of a cell 0-8 in a tic-tac-toe grid: the centre is on 4 lines, the corners on 3 and the edges on 2
+const CELL_PRIOR: [i32; 9] = [3, 2, 3, 2, 4, 2, 3, 2, 3];
+
+/// Move prior for tic-tac-toe: cells on many lines first
+pub fn ttt_move_prior(_b: &TTTBoard, addr: TTTAddr) -> i32 {
+    CELL_PRIOR[addr.0 - 1]
+}
+
+/// Move prior for Ultimate Tic-Tac-Toe: cells on many lines first,
+/// and avoid sending the opponent to a finished sub-board, since that lets them play anywhere
+pub fn uttt_move_prior(b: &UTTTBoard, action: Action) -> i32 {
+    let pos = action.position_index();
+    let free_choice = b.sub_board_status(pos / 3, pos % 3) != GameStatus::Undecided;
+    CELL_PRIOR[pos] - if free_choice { 10 } else { 0 }
+}
+
+/// Move prior for Connect Four: the centre columns first, since they are part of the most fours
+pub fn c4_move_prior(_b: &C4Board, col: usize) -> i32 {
+    -(col as i32 - 3).abs()
 }
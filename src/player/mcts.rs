@@ -4,22 +4,45 @@
 //!
 //! It also holds a Ai struct, that knows how to play a game using MCTS, assuming the MDP structure of the games is known.
 
-use log::info;
-use rand::prelude::SliceRandom;
+use clap::ValueEnum;
+use log::{debug, error, info, warn};
 use rand::rngs::StdRng;
-use rand::seq::IteratorRandom as _;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use std::hash::Hash;
-use std::io::{Read, Write};
-use std::time::Duration;
-use std::{collections::HashMap, fmt::Debug};
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{collections::{HashMap, HashSet}, fmt::Debug};
 
-use crate::core::{BlitzPlayer, Board, GameStatus, Player};
+use crate::core::{BlitzPlayer, Board, GameStatus, Outcome, Player, RootMove, ScoreBound, SearchInfo};
+
+mod final_move;
+mod inspect;
+mod limit;
+mod memory;
+pub mod prior;
+pub mod rollout;
+mod store;
+mod tree;
+
+pub use final_move::FinalMove;
+pub use inspect::{read_memory_file, ActionSummary, StateSummary};
+pub use limit::{Eviction, MemoryLimit, MemoryUsage};
+pub use memory::{merge_memory_files, MemoryError};
+use prior::PriorPolicy;
+use rollout::{RolloutPolicy, UniformRollout};
+use store::KnowledgeStore;
+use limit::Bounds;
+use tree::{Leaf, SearchTree};
 
 pub trait Mdp {
     type Action: Clone
+        + Send
+        + Sync
         + Debug
         + PartialEq
         + Eq
@@ -28,6 +51,8 @@ pub trait Mdp {
         + Serialize
         + for<'de> serde::Deserialize<'de>;
     type State: Sized
+        + Send
+        + Sync
         + Debug
         + Clone
         + PartialEq
@@ -35,7 +60,13 @@ pub trait Mdp {
         + Hash
         + Serialize
         + for<'de> serde::Deserialize<'de>;
+    /// Identifies the MDP in memory files, so that the statistics of one game are never loaded for another
+    const NAME: &'static str;
     const DISCOUNT_FACTOR: f64; // 1= no discount, 0=only immediate reward
+    /// In two player games, a return above this is a win for the actor, and one below minus this is a loss.
+    /// The solver proves a state as soon as one of its actions is a proven win, without proving the others.
+    /// The default means that no return is a win, so a state is only proven once all its actions are.
+    const WIN_THRESHOLD: f64 = f64::INFINITY;
     /// Sample sample from  p(s',r|s,a)
     /// see Sutton&Barto Equation 3.2
     /// The return is always as percieved by the actor that takes the action
@@ -45,57 +76,167 @@ pub trait Mdp {
     fn act(s: Self::State, action: &Self::Action) -> (Self::State, f64);
     fn is_terminal(s: &Self::State) -> bool;
     fn allowed_actions(s: &Self::State) -> Vec<Self::Action>;
-    /// Play randomly until end of game, and return the 'return'
-    /// The return is the sum of all future rewards, discounted by the discount factor
-    fn rollout(s: Self::State, rng: &mut StdRng) -> f64 {
-        if Self::is_terminal(&s) {
-            return 0.0;
-        }
-        let actions = Self::allowed_actions(&s);
-        let action = actions.choose(rng).expect(
-            "This function should never have been called on a state with no actions allowed",
-        );
-        let (state, reward) = Self::act(s, action);
-        reward + Self::DISCOUNT_FACTOR * Self::rollout(state, rng)
+    /// Like `allowed_actions`, but writes into a buffer owned by the caller. The buffer is cleared first.
+    /// Override it if the actions can be generated without allocating.
+    fn allowed_actions_into(s: &Self::State, actions: &mut Vec<Self::Action>) {
+        *actions = Self::allowed_actions(s);
     }
 }
 
+/// The settings of `mcts_step`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StepSettings {
+    /// The exploration constant in the UCB1 or PUCT formula
+    pub c: f64,
+    /// Turns on MCTS-Solver
+    pub solve: bool,
+    /// The equivalence parameter of RAVE, or None to not use RAVE
+    pub rave: Option<f64>,
+}
+
 /// Run one step of the MCTS algorithm
 /// The algorithm is:
 /// 1. Select. Go down the game tree until you find a leaf node. I.e. a node that has not been visited yet.
-///    The selection process is by taking the 'best' child at each node, where 'best' is defined by the UCB1 formula (or some other tree planning algo)
+///    The selection process is by taking the 'best' child at each node, where 'best' is defined by the UCB1 formula,
+///    or by PUCT if there is a `prior`
 /// 2. Expand. If the node is new, expand into all its children. This step is kind of funny, because if you don't keep track of all non-taken actions, it is a noop.
-/// 3. Rollout. From a new state, do a random rollout until the end of the game, and return the return.
+/// 3. Rollout. From a new state, play out the game with the rollout policy, and return the return.
 /// 4. Backup. All the states visited in the selection process are updated with the return of the rollout. Apply discounting if needed.
 ///
 /// N.B. You may accumulate return at every step in the tree.
 /// The "Reward" is called G and is the total reward over all future steps.
+///
+/// With `solve`, this is MCTS-Solver: the exact values of terminal states are backed up with minimax rules, see `prove`.
+/// A proven state is not searched further, its value is returned right away.
+/// This needs `act` to be deterministic, which it is in board games.
+///
+/// With `rave`, the all-moves-as-first statistics are updated too: every action that the actor took later in the simulation,
+/// in the tree or in the rollout, counts as if it had been taken first.
+///
+/// The `buffers` are reused from step to step, so that the search doesn't allocate.
 pub(crate) fn mcts_step<M: Mdp>(
     state: &M::State,
-    c: f64,
+    settings: &StepSettings,
+    prior: Option<&dyn PriorPolicy<M>>,
+    rollout: &dyn RolloutPolicy<M>,
     qmap: &mut QMap<M::State, M::Action>,
+    buffers: &mut StepBuffers<M::Action>,
+    rng: &mut StdRng,
+) -> f64 {
+    buffers.played.clear();
+    simulate::<M>(state, 0, settings, prior, rollout, qmap, buffers, rng)
+}
+
+/// The buffers that `mcts_step` works in
+pub(crate) struct StepBuffers<A> {
+    /// The actions of the simulation, for RAVE
+    played: Vec<A>,
+    /// The actions allowed in a state
+    actions: Vec<A>,
+    /// The priors of those actions
+    priors: Vec<f64>,
+}
+
+impl<A> Default for StepBuffers<A> {
+    fn default() -> Self {
+        StepBuffers {
+            played: vec![],
+            actions: vec![],
+            priors: vec![],
+        }
+    }
+}
+
+/// The recursive part of `mcts_step`. `state` is `ply` plies below the state of the step.
+#[allow(clippy::too_many_arguments)]
+fn simulate<M: Mdp>(
+    state: &M::State,
+    ply: usize,
+    settings: &StepSettings,
+    prior: Option<&dyn PriorPolicy<M>>,
+    rollout: &dyn RolloutPolicy<M>,
+    qmap: &mut QMap<M::State, M::Action>,
+    buffers: &mut StepBuffers<M::Action>,
     rng: &mut StdRng,
 ) -> f64 {
     if M::is_terminal(state) {
         return 0.0;
     }
-    let best_action = best_action::<M>(state, c, qmap, rng);
+    qmap.fault_in(state);
+    qmap.touch(state, ply);
+    if let Some(value) = qmap.proven_value(state) {
+        return value;
+    }
+    let best_action = best_action::<M>(state, settings.c, settings.rave, prior, qmap, buffers, rng);
     let (new_state, reward) = M::act(state.clone(), &best_action);
+    let depth = buffers.played.len();
+    if settings.rave.is_some() {
+        buffers.played.push(best_action.clone());
+    }
+    qmap.fault_in(&new_state);
     let n_visits_to_new = qmap.n_state_visits(&new_state);
     // dbg!(state,&new_state,n_visits_to_new);
-    let g_return = if n_visits_to_new == 0.0 {
+    let (g_return, new_value) = if n_visits_to_new == 0.0 {
         qmap.increment_state_visits(&new_state);
-        reward + M::rollout(new_state, rng) * M::DISCOUNT_FACTOR
+        qmap.touch(&new_state, ply + 1);
+        let new_value = (settings.solve && M::is_terminal(&new_state)).then_some(0.0);
+        let rollout_played = settings.rave.map(|_| &mut buffers.played);
+        (reward + rollout.rollout(new_state, rng, rollout_played) * M::DISCOUNT_FACTOR, new_value)
     } else {
-        reward + mcts_step::<M>(&new_state, c, qmap, rng) * M::DISCOUNT_FACTOR
+        let g_return = reward + simulate::<M>(&new_state, ply + 1, settings, prior, rollout, qmap, buffers, rng) * M::DISCOUNT_FACTOR;
+        let new_value = if !settings.solve {
+            None
+        } else if M::is_terminal(&new_state) {
+            Some(0.0)
+        } else {
+            qmap.proven_value(&new_state)
+        };
+        (g_return, new_value)
     };
 
     // Update the Q-function
     qmap.add_to_state_action_data(state, &best_action, g_return);
 
+    if settings.rave.is_some() {
+        // with a negative discount factor the players take turns, and only the actor's own actions count
+        let stride = if M::DISCOUNT_FACTOR < 0.0 { 2 } else { 1 };
+        qmap.add_to_amaf_data(state, &buffers.played[depth..], stride, g_return);
+    }
+
+    if let Some(v) = new_value {
+        prove::<M>(qmap, state, &best_action, reward + M::DISCOUNT_FACTOR * v, &mut buffers.actions);
+    }
+
     g_return
 }
 
+/// Record that `action` from `state` is proven to return `value`, and prove `state` if that settles it.
+/// Minimax rules: the state is worth the action if it is a win, or the best of its actions once they are all proven.
+/// `actions` is a buffer for the actions allowed in `state`.
+fn prove<M: Mdp>(
+    qmap: &mut QMap<M::State, M::Action>,
+    state: &M::State,
+    action: &M::Action,
+    value: f64,
+    actions: &mut Vec<M::Action>,
+) {
+    qmap.mark_dirty(state);
+    let proofs = qmap.proven.entry(state.clone()).or_default();
+    if proofs.actions.contains_key(action) {
+        return;
+    }
+    proofs.actions.insert(action.clone(), value);
+    qmap.action_entries += 1;
+    if value > M::WIN_THRESHOLD {
+        proofs.value = Some(value);
+    } else {
+        M::allowed_actions_into(state, actions);
+        if actions.iter().all(|a| proofs.actions.contains_key(a)) {
+            proofs.value = proofs.actions.values().copied().reduce(f64::max);
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub(crate) struct QMap<S, A>
 where
@@ -103,10 +244,57 @@ where
     A: Hash + Eq,
 {
     /// map a state to number-of-visits and a secondary map.
-    /// The secondary map maps actions (taken from this state) into the total return observed and the number of times THAT action was taken.
-    state_action_value: HashMap<S, (f64, HashMap<A, (f64, f64)>)>,
+    state_action_value: HashMap<S, (f64, ActionMap<A>)>,
+    /// What the solver has proven, for the states where it has proven anything
+    proven: HashMap<S, Proofs<A>>,
+    /// The all-moves-as-first statistics of RAVE: for each state, the total return and the number of simulations
+    /// in which the actor took the action at any point after the state.
+    amaf: HashMap<S, ActionMap<A>>,
+    /// Where the statistics are saved between runs. The states that are not in the maps are looked up there.
+    /// A default other than `Default`, which serde would require `S` and `A` to implement
+    #[serde(skip, default = "Option::default")]
+    store: Option<KnowledgeStore<S, A>>,
+    /// The bound on what the maps hold, if there is one, see `limit`
+    #[serde(skip, default = "Option::default")]
+    bounds: Option<Bounds<S>>,
+    /// The number of actions in all the maps of all the states, for `usage`
+    #[serde(skip)]
+    action_entries: usize,
+    /// The states that have learned something since they were last saved to the store. Only kept when there is a store.
+    #[serde(skip, default = "HashSet::new")]
+    dirty: HashSet<S>,
+}
+
+/// Everything that the `QMap` knows about one state, as it is saved in the knowledge store
+#[derive(Serialize, Deserialize)]
+pub(crate) struct StateRecord<A: Hash + Eq> {
+    visits: f64,
+    actions: ActionMap<A>,
+    proofs: Option<Proofs<A>>,
+    amaf: Option<ActionMap<A>>,
+}
+
+/// The exact values that the solver has found for a state and its actions
+#[derive(Clone, Serialize, Deserialize)]
+struct Proofs<A: Hash + Eq> {
+    /// The value of the state, once it is proven
+    value: Option<f64>,
+    /// The values of the actions proven so far, as seen by the actor
+    actions: HashMap<A, f64>,
 }
 
+impl<A: Hash + Eq> Default for Proofs<A> {
+    fn default() -> Self {
+        Proofs {
+            value: None,
+            actions: HashMap::new(),
+        }
+    }
+}
+
+/// The secondary map maps actions (taken from a state) into the total return observed and the number of times THAT action was taken.
+type ActionMap<A> = HashMap<A, (f64, f64)>;
+
 impl<S, A> QMap<S, A>
 where
     S: Hash + Eq + Clone,
@@ -115,6 +303,12 @@ where
     pub fn new() -> Self {
         QMap {
             state_action_value: HashMap::new(),
+            proven: HashMap::new(),
+            amaf: HashMap::new(),
+            store: None,
+            bounds: None,
+            action_entries: 0,
+            dirty: HashSet::new(),
         }
     }
     /// Peel off the outer layer in the hashmap stack
@@ -122,17 +316,17 @@ where
         self.state_action_value.get(s).map(|(_, m)| m)
     }
 
+    /// Count a visit to `s` in which `a` was taken and `g_return` was observed.
+    /// Every return counts, the first one of an action too.
     pub fn add_to_state_action_data(&mut self, s: &S, a: &A, g_return: f64) {
         self.increment_state_visits(s);
         if let Some((_,m)) = self.state_action_value.get_mut(s) {
-            match m.get_mut(a) {
-                Some((w,v)) => {
-                    *w += g_return;
-                    *v += 1.0;
-                },
-                None => {}
-            }
-            m.entry(a.clone()).or_insert((0.0,0.0));
+            let (w, v) = m.entry(a.clone()).or_insert_with(|| {
+                self.action_entries += 1;
+                (0.0, 0.0)
+            });
+            *w += g_return;
+            *v += 1.0;
         } else {
             unreachable!("The 'increment_state_visits' ensures this map is created.")
         }
@@ -144,53 +338,323 @@ where
             .map(|(v, _)| v)
             .unwrap_or(&0.0)
     }
+    /// The all-moves-as-first statistics of the actions from the state
+    pub fn get_amaf(&self, s: &S) -> Option<&ActionMap<A>> {
+        self.amaf.get(s)
+    }
+    /// Count the return for every `stride`th action in `played`, the first time it occurs
+    pub fn add_to_amaf_data(&mut self, s: &S, played: &[A], stride: usize, g_return: f64) {
+        self.mark_dirty(s);
+        if !self.amaf.contains_key(s) {
+            self.amaf.insert(s.clone(), HashMap::new());
+        }
+        let m = self.amaf.get_mut(s).expect("Inserted above");
+        for (k, a) in played.iter().enumerate().step_by(stride) {
+            if played[..k].iter().step_by(stride).any(|earlier| earlier == a) {
+                continue;
+            }
+            let (w, v) = m.entry(a.clone()).or_insert_with(|| {
+                self.action_entries += 1;
+                (0.0, 0.0)
+            });
+            *w += g_return;
+            *v += 1.0;
+        }
+    }
+    /// The exact value of the state, if the solver has proven it
+    pub fn proven_value(&self, state: &S) -> Option<f64> {
+        self.proven.get(state).and_then(|p| p.value)
+    }
+    /// The exact values of the actions from the state that the solver has proven
+    pub fn proven_actions(&self, state: &S) -> Option<&HashMap<A, f64>> {
+        self.proven.get(state).map(|p| &p.actions)
+    }
+    /// Count a visit to the state. A state that is not in the map yet starts at one visit.
     /// POSTCONDITION: the state is present in self.state_action_value
     pub fn increment_state_visits(&mut self, state: &S) {
+        self.mark_dirty(state);
         if let Some(v) = self.state_action_value.get_mut(state).map(|(v, _)| v) {
             *v += 1.0;
         } else {
             self.state_action_value
-                .insert(state.clone(), (0.0, HashMap::new()));
+                .insert(state.clone(), (1.0, HashMap::new()));
+        }
+    }
+    /// Note that `state` has learned something that the store doesn't know yet
+    fn mark_dirty(&mut self, state: &S) {
+        if self.store.is_some() && !self.dirty.contains(state) {
+            self.dirty.insert(state.clone());
+        }
+    }
+}
+
+impl<S, A> QMap<S, A>
+where
+    S: Hash + Eq + Clone + Serialize + DeserializeOwned,
+    A: Hash + Eq + Clone + Serialize + DeserializeOwned,
+{
+    /// Keep the statistics in `store` from now on. What is in the maps already is added to what is in the store.
+    pub fn attach_store(&mut self, store: KnowledgeStore<S, A>) {
+        let known = std::mem::replace(self, QMap::new());
+        self.store = Some(store);
+        self.merge(known);
+    }
+
+    /// Read what the store knows about `state` into the maps, unless they have it already.
+    /// A damaged record is reported and skipped, and is replaced the next time the state is saved.
+    pub fn fault_in(&mut self, state: &S) {
+        let Some(store) = self.store.as_mut() else {
+            return;
+        };
+        if self.state_action_value.contains_key(state) {
+            return;
+        }
+        match store.get(state) {
+            Ok(Some(record)) => self.insert_record(state.clone(), record),
+            Ok(None) => {}
+            Err(e) => warn!("Skipping the saved statistics of a state: {e}"),
+        }
+    }
+
+    /// Write the states that have learned something since the last save to the store, if there is one
+    pub fn save_to_store(&mut self) -> Result<(), MemoryError> {
+        let Some(store) = self.store.as_mut() else {
+            return Ok(());
+        };
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        store.put_all(self.dirty.iter().filter_map(|s| {
+            let (visits, actions) = self.state_action_value.get(s)?;
+            let record = StateRecord {
+                visits: *visits,
+                actions: actions.clone(),
+                proofs: self.proven.get(s).cloned(),
+                amaf: self.amaf.get(s).cloned(),
+            };
+            Some((s.clone(), record))
+        }))?;
+        self.dirty.clear();
+        Ok(())
+    }
+
+    /// Add the statistics of `other` to these, as if all its steps had been taken here
+    pub fn merge(&mut self, other: QMap<S, A>) {
+        for s in other.state_action_value.keys() {
+            self.fault_in(s);
+        }
+        self.touch_merged(&other);
+        for s in other.state_action_value.keys().chain(other.amaf.keys()).chain(other.proven.keys()) {
+            self.mark_dirty(s);
+        }
+        for (s, (n, actions)) in other.state_action_value {
+            let (n0, actions0) = self.state_action_value.entry(s).or_insert((0.0, HashMap::new()));
+            *n0 += n;
+            let known = actions0.len();
+            for (a, (w, v)) in actions {
+                let (w0, v0) = actions0.entry(a).or_insert((0.0, 0.0));
+                *w0 += w;
+                *v0 += v;
+            }
+            self.action_entries += actions0.len() - known;
+        }
+        for (s, actions) in other.amaf {
+            let actions0 = self.amaf.entry(s).or_default();
+            let known = actions0.len();
+            for (a, (w, v)) in actions {
+                let (w0, v0) = actions0.entry(a).or_insert((0.0, 0.0));
+                *w0 += w;
+                *v0 += v;
+            }
+            self.action_entries += actions0.len() - known;
         }
+        for (s, proofs) in other.proven {
+            let proofs0 = self.proven.entry(s).or_default();
+            let known = proofs0.actions.len();
+            proofs0.value = proofs0.value.or(proofs.value);
+            proofs0.actions.extend(proofs.actions);
+            self.action_entries += proofs0.actions.len() - known;
+        }
+    }
+    fn insert_record(&mut self, state: S, record: StateRecord<A>) {
+        self.action_entries += record.actions.len()
+            + record.amaf.as_ref().map_or(0, |m| m.len())
+            + record.proofs.as_ref().map_or(0, |p| p.actions.len());
+        if let Some(proofs) = record.proofs {
+            self.proven.insert(state.clone(), proofs);
+        }
+        if let Some(amaf) = record.amaf {
+            self.amaf.insert(state.clone(), amaf);
+        }
+        self.state_action_value.insert(state, (record.visits, record.actions));
+    }
+
+    /// All the statistics, state by state
+    pub fn into_records(mut self) -> impl Iterator<Item = (S, StateRecord<A>)> {
+        let mut proven = std::mem::take(&mut self.proven);
+        let mut amaf = std::mem::take(&mut self.amaf);
+        std::mem::take(&mut self.state_action_value)
+            .into_iter()
+            .map(move |(s, (visits, actions))| {
+                let record = StateRecord {
+                    visits,
+                    actions,
+                    proofs: proven.remove(&s),
+                    amaf: amaf.remove(&s),
+                };
+                (s, record)
+            })
+    }
+}
+
+/// What a search found out about an action at the root
+pub(crate) struct ActionStats<A> {
+    pub action: A,
+    /// Total return observed after taking the action
+    pub w: f64,
+    /// Number of times the action was taken
+    pub n: f64,
+    /// The exact value of the action, if the solver proved it
+    pub proven: Option<f64>,
+}
+
+/// What the map knows about the actions from `state`, in the order of the actions,
+/// so that the ties in `final_move` are broken the same way for the same seed
+fn qmap_stats<M: Mdp>(qmap: &QMap<M::State, M::Action>, state: &M::State) -> Vec<ActionStats<M::Action>> {
+    let proven = qmap.proven_actions(state);
+    let mut stats: Vec<_> = qmap
+        .get(state)
+        .map(|m| {
+            m.iter()
+                .map(|(a, &(w, n))| ActionStats {
+                    action: a.clone(),
+                    w,
+                    n,
+                    proven: proven.and_then(|p| p.get(a)).copied(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    stats.sort_by(|x, y| x.action.cmp(&y.action));
+    stats
+}
+
+/// The line of play that starts with `first` from `state`, and then follows the actions the search took most
+fn qmap_principal_variation<M: Mdp>(qmap: &QMap<M::State, M::Action>, state: &M::State, first: &M::Action) -> Vec<M::Action> {
+    let mut pv = vec![first.clone()];
+    let (mut s, _) = M::act(state.clone(), first);
+    // the map may hold cycles of states in MDPs that are not games, so don't follow it for ever
+    while pv.len() < MAX_PV_LENGTH && !M::is_terminal(&s) {
+        let Some((a, _)) = qmap.get(&s).and_then(|m| m.iter().max_by(|a, b| a.1 .1.total_cmp(&b.1 .1))) else {
+            break;
+        };
+        pv.push(a.clone());
+        s = M::act(s, a).0;
+    }
+    pv
+}
+
+/// Longest principal variation reported from a `QMap`
+const MAX_PV_LENGTH: usize = 100;
+
+/// What a proven value means in a game
+fn outcome<M: Mdp>(value: f64) -> Outcome {
+    if value > M::WIN_THRESHOLD {
+        Outcome::Win
+    } else if value < -M::WIN_THRESHOLD {
+        Outcome::Loss
+    } else {
+        Outcome::Draw
     }
 }
 
+/// The action from `state` to search next: the one with the highest UCB1 value, ties broken at random.
+/// With a `prior`, the one with the highest PUCT value. The priors are not stored in the map, so they are computed again at every visit.
+/// With `rave`, the mean returns are blended with the all-moves-as-first means, see `rave_mean`.
+/// An action that has not been visited yet is then worth its all-moves-as-first mean, instead of the infinite UCB1 value,
+/// so that the most promising new actions are tried first.
 pub(crate) fn best_action<M: Mdp>(
     state: &M::State,
     c: f64,
+    rave: Option<f64>,
+    prior: Option<&dyn PriorPolicy<M>>,
     qmap: &QMap<M::State, M::Action>,
+    buffers: &mut StepBuffers<M::Action>,
     rng: &mut StdRng,
 ) -> M::Action {
-    let allowed_actions = M::allowed_actions(state);
+    let StepBuffers { actions, priors, .. } = buffers;
+    M::allowed_actions_into(state, actions);
+    match prior {
+        Some(prior) => prior.priors(state, actions, priors),
+        None => priors.clear(),
+    }
     let t = qmap.n_state_visits(state);
-    let best_action = if let Some(m) = qmap.get(state) {
-        allowed_actions
-            .into_iter()
-            .fold(
-                (-f64::INFINITY, vec![]),
-                |(mut record, mut actions), action| {
-                    let (w, v) = m.get(&action).unwrap_or(&(0.0, 0.0));
-                    let value = ucb(c, *w, *v, t);
-                    if value == record {
-                        actions.push(action)
-                    } else if value > record {
-                        record = value;
-                        actions = vec![action];
-                    }
-                    (record, actions)
-                },
-            )
-            .1
-            .into_iter()
-            .choose(rng)
-            .expect("There must be at least one action")
-    } else {
-        allowed_actions
-            .into_iter()
-            .choose(rng)
-            .expect("There must be at least one action")
-    };
-    best_action
+    let m = qmap.get(state);
+    let proven = qmap.proven_actions(state);
+    let amaf = rave.and_then(|k| qmap.get_amaf(state).map(|m| (k, m)));
+    let mut record = -f64::INFINITY;
+    let mut best = None;
+    let mut n_ties = 0;
+    for (i, action) in actions.iter().enumerate() {
+        let (mut w, v) = m.and_then(|m| m.get(action)).copied().unwrap_or((0.0, 0.0));
+        let mut first_play = None;
+        if let Some((k, amaf)) = amaf {
+            match amaf.get(action) {
+                Some(&amaf_data) if v > 0.0 => w = v * rave_mean(w, v, amaf_data, k),
+                // β is 1 before the first visit, so the AMAF mean alone decides which new action is tried first
+                Some(&(amaf_g, amaf_n)) if amaf_n > 0.0 => first_play = Some(amaf_g / amaf_n),
+                _ => {}
+            }
+        }
+        let proven = proven.and_then(|p| p.get(action)).is_some();
+        let value = match first_play {
+            Some(mean) if !proven => match priors.get(i) {
+                Some(&p) => mean + puct(c, 0.0, 0.0, t, p),
+                None => ucb(c, mean, 1.0, t),
+            },
+            _ => selection_value(c, w, v, t, priors.get(i).copied(), proven),
+        };
+        if value > record {
+            record = value;
+            best = Some(i);
+            n_ties = 1;
+        } else if value == record {
+            // keep each of the tied actions with the same probability
+            n_ties += 1;
+            if rng.gen_range(0..n_ties) == 0 {
+                best = Some(i);
+            }
+        }
+    }
+    actions[best.expect("There must be at least one action")].clone()
+}
+
+/// The mean return of an action blended with its all-moves-as-first mean, by β = sqrt(k / (3n + k)).
+/// β is 1 for a new action and goes to 0 as it gets visits, so that the quick but biased AMAF estimate is trusted
+/// until there is enough data of its own. `k` is the equivalence parameter: the number of visits at which β is 1/2.
+/// Needs at least one visit and one AMAF visit.
+fn rave_mean(tot_g: f64, n_visits: f64, (amaf_g, amaf_n): (f64, f64), k: f64) -> f64 {
+    let beta = (k / (3.0 * n_visits + k)).sqrt();
+    (1.0 - beta) * tot_g / n_visits + beta * amaf_g / amaf_n
+}
+
+/// How much an action is worth searching: UCB1, or PUCT if it has a `prior`.
+/// For MCTS-Solver, proven actions are skipped: there is nothing left to learn about them,
+/// and the other actions still need proving. The move to play is chosen in `final_move`, where proven actions count at their exact value.
+fn selection_value(c: f64, tot_g: f64, n_visits: f64, time: f64, prior: Option<f64>, proven: bool) -> f64 {
+    match prior {
+        _ if proven => -f64::INFINITY,
+        Some(p) => puct(c, tot_g, n_visits, time, p),
+        None => ucb(c, tot_g, n_visits, time),
+    }
+}
+
+/// The PUCT formula of AlphaZero: the mean return, plus an exploration term in proportion to the prior
+/// that shrinks as the action gets visits.
+/// An action that has not been visited counts with a mean of 0, so the priors decide the order in which the new actions are tried.
+fn puct(c: f64, tot_g: f64, n_visits: f64, time: f64, prior: f64) -> f64 {
+    let mean = if n_visits == 0.0 { 0.0 } else { tot_g / n_visits };
+    mean + c * prior * time.sqrt() / (1.0 + n_visits)
 }
 
 /// The UCB1 formula,
@@ -218,9 +682,20 @@ mod test {
         Sub,
     }
     struct CountGameMDP {}
+
+    const SETTINGS: StepSettings = StepSettings {
+        c: 0.75,
+        solve: false,
+        rave: None,
+    };
+    const RAVE_SETTINGS: StepSettings = StepSettings {
+        rave: Some(100.0),
+        ..SETTINGS
+    };
     impl Mdp for CountGameMDP {
         type Action = CountGameAction;
         type State = CountGameState;
+        const NAME: &'static str = "count-game";
         const DISCOUNT_FACTOR: f64 = 0.99;
         fn is_terminal(s: &CountGameState) -> bool {
             let total = s.0.iter().sum::<i8>();
@@ -250,9 +725,9 @@ mod test {
         let root: CountGameState = CountGameState(vec![]);
         let mut qmap = QMap::new();
         let mut rng = StdRng::from_entropy();
-        let c = 0.75;
-        mcts_step::<CountGameMDP>(&root, c, &mut qmap, &mut rng);
-        mcts_step::<CountGameMDP>(&root, c, &mut qmap, &mut rng);
+        let mut buffers = StepBuffers::default();
+        mcts_step::<CountGameMDP>(&root, &SETTINGS, None, &UniformRollout, &mut qmap, &mut buffers, &mut rng);
+        mcts_step::<CountGameMDP>(&root, &SETTINGS, None, &UniformRollout, &mut qmap, &mut buffers, &mut rng);
         // The root state should have been visited twice
         assert!(qmap.n_state_visits(&root) > 0.0);
         assert_eq!(qmap.n_state_visits(&root), 2.0);
@@ -270,81 +745,545 @@ mod test {
         assert_eq!(*visits[1], 1.0);
     }
 
+    // The first return of an action is counted, and a state starts at one visit
+    #[test]
+    fn test_qmap_counts() {
+        let root: CountGameState = CountGameState(vec![]);
+        let next: CountGameState = CountGameState(vec![1]);
+        let mut qmap = QMap::new();
+        qmap.add_to_state_action_data(&root, &CountGameAction::Add, 1.0);
+        assert_eq!(qmap.n_state_visits(&root), 1.0);
+        assert_eq!(qmap.get(&root).unwrap()[&CountGameAction::Add], (1.0, 1.0));
+        qmap.add_to_state_action_data(&root, &CountGameAction::Add, 0.5);
+        qmap.add_to_state_action_data(&root, &CountGameAction::Sub, -1.0);
+        assert_eq!(qmap.n_state_visits(&root), 3.0);
+        assert_eq!(qmap.get(&root).unwrap()[&CountGameAction::Add], (1.5, 2.0));
+        assert_eq!(qmap.get(&root).unwrap()[&CountGameAction::Sub], (-1.0, 1.0));
+        qmap.increment_state_visits(&next);
+        assert_eq!(qmap.n_state_visits(&next), 1.0);
+        assert!(qmap.get(&next).unwrap().is_empty());
+    }
+
+    // Merging two maps adds up the statistics, as if one map had taken all the steps
+    #[test]
+    fn test_qmap_merge() {
+        let root: CountGameState = CountGameState(vec![]);
+        let mut qmap = QMap::new();
+        let mut other = QMap::new();
+        let mut rng = StdRng::from_entropy();
+        for _ in 0..10 {
+            mcts_step::<CountGameMDP>(&root, &SETTINGS, None, &UniformRollout, &mut qmap, &mut StepBuffers::default(), &mut rng);
+        }
+        for _ in 0..5 {
+            mcts_step::<CountGameMDP>(&root, &SETTINGS, None, &UniformRollout, &mut other, &mut StepBuffers::default(), &mut rng);
+        }
+        qmap.merge(other);
+        assert_eq!(qmap.n_state_visits(&root), 15.0);
+        let visits: f64 = qmap.get(&root).unwrap().values().map(|(_, v)| v).sum();
+        assert_eq!(visits, 15.0);
+        // only the actions new to a state add to the count
+        assert_eq!(qmap.action_entries, qmap.count_action_entries());
+    }
+
     // If I run the game many times, Have I identified the best move?
     #[test]
     fn test_mcts() {
+        for settings in [SETTINGS, RAVE_SETTINGS] {
+            let root: CountGameState = CountGameState(vec![]);
+            let mut qmap = QMap::new();
+            let mut rng = StdRng::from_entropy();
+            let mut buffers = StepBuffers::default();
+            for _ in 0..10000 {
+                mcts_step::<CountGameMDP>(&root, &settings, None, &UniformRollout, &mut qmap, &mut buffers, &mut rng);
+            }
+            let best_move = best_action::<CountGameMDP>(&root, settings.c, settings.rave, None, &qmap, &mut buffers, &mut rng);
+            assert_eq!(
+                best_move,
+                CountGameAction::Add,
+                "Stochastic test that might fail sometimes"
+            );
+        }
+    }
+
+    // Each action the actor took counts once, no matter how often it was taken
+    #[test]
+    fn test_amaf_counts_first_occurrence() {
         let root: CountGameState = CountGameState(vec![]);
         let mut qmap = QMap::new();
-        let mut rng = StdRng::from_entropy();
-        let c = 0.75;
-        for _ in 0..10000 {
-            mcts_step::<CountGameMDP>(&root, c, &mut qmap, &mut rng);
+        use CountGameAction::*;
+        // with a stride of 2, only the even positions are the actor's
+        qmap.add_to_amaf_data(&root, &[Add, Sub, Add, Add, Add], 2, 1.0);
+        qmap.add_to_amaf_data(&root, &[Sub, Add, Sub], 2, -1.0);
+        let amaf = qmap.get_amaf(&root).unwrap();
+        assert_eq!(amaf[&Add], (1.0, 1.0));
+        assert_eq!(amaf[&Sub], (-1.0, 1.0));
+    }
+
+    // RAVE trusts the AMAF mean at first, and the action's own mean later
+    #[test]
+    fn test_rave_mean() {
+        let own = (1.0, 10.0);
+        let amaf = (90.0, 100.0);
+        let early = rave_mean(own.0 * 0.1, 1.0, amaf, 100.0);
+        let late = rave_mean(own.0 * 100.0, 1000.0, amaf, 100.0);
+        assert!(early > 0.8, "{early}");
+        assert!(late < 0.4, "{late}");
+        // at k visits, the two are weighted equally
+        assert!((rave_mean(0.0, 100.0, (100.0, 100.0), 100.0) - 0.5).abs() < 1e-9);
+    }
+}
+
+/// Where `MctsAi` keeps the statistics of the search
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MctsEngine {
+    /// A map from states to statistics. It can be saved to the memory file, so the AI learns across games.
+    #[default]
+    QMap,
+    /// A tree of nodes in an arena. Faster, and the subtree under the moves played is kept between turns,
+    /// but it is not saved to the memory file.
+    Tree,
+}
+
+/// How `MctsAi` searches on more than one thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Parallelism {
+    /// Every thread searches on its own, and the statistics are merged when the search is done
+    #[default]
+    Root,
+    /// All threads search the same tree. A thread going down the tree puts a virtual loss on the moves it takes,
+    /// so that the other threads try other moves until its simulation is done.
+    /// This needs the `Tree` engine.
+    SharedTree,
+}
+
+/// How `MctsAi` picks the action to search next, going down the tree
+pub enum Selection<M: Mdp> {
+    /// UCB1: the mean return plus c * sqrt(ln(visits of the state) / visits of the action)
+    Ucb1,
+    /// PUCT, as in AlphaZero: the mean return plus c * prior * sqrt(visits of the state) / (1 + visits of the action)
+    Puct(Arc<dyn PriorPolicy<M>>),
+}
+
+impl<M: Mdp> Selection<M> {
+    pub fn puct(prior: impl PriorPolicy<M> + 'static) -> Self {
+        Selection::Puct(Arc::new(prior))
+    }
+
+    fn prior(&self) -> Option<&Arc<dyn PriorPolicy<M>>> {
+        match self {
+            Selection::Ucb1 => None,
+            Selection::Puct(prior) => Some(prior),
+        }
+    }
+}
+
+/// The return that a thread takes off a move while it is simulating it, in shared tree search. A loss for the actor.
+const VIRTUAL_LOSS: f64 = 1.0;
+
+/// How much to search for one move
+#[derive(Debug, Clone, Copy)]
+enum Budget {
+    /// This many steps, over all threads
+    Steps(u32),
+    /// Stop before this time has passed
+    Time(Duration),
+}
+
+/// Shared by the search threads, to know when to stop
+struct StopCondition {
+    budget: Budget,
+    t0: Instant,
+    steps: AtomicU32,
+}
+
+impl StopCondition {
+    fn new(budget: Budget) -> Self {
+        StopCondition {
+            budget,
+            t0: Instant::now(),
+            steps: AtomicU32::new(0),
+        }
+    }
+
+    /// Count a step. Tells if there is budget left for another, for a thread that has taken `n_steps` steps itself.
+    fn keep_going(&self, n_steps: u32) -> bool {
+        let total = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.budget {
+            Budget::Steps(max) => total < max,
+            Budget::Time(time) => {
+                let elapsed = self.t0.elapsed();
+                let duration_per_step = elapsed / n_steps;
+                elapsed + duration_per_step + Duration::from_millis(1) <= time
+            }
         }
-        let best_move = best_action::<CountGameMDP>(&root, c, &qmap, &mut rng);
-        assert_eq!(
-            best_move,
-            CountGameAction::Add,
-            "Stochastic test that might fail sometimes"
-        );
+    }
+
+    fn total_steps(&self) -> u32 {
+        self.steps.load(Ordering::Relaxed)
     }
 }
 
 pub struct MctsAi<T: Mdp> {
     qmap: QMap<T::State, T::Action>,
+    engine: MctsEngine,
+    threads: usize,
+    parallelism: Parallelism,
+    /// Only used by the `Tree` engine. Made on the first move.
+    tree: Option<SearchTree<T>>,
+    selection: Selection<T>,
+    rollout: Box<dyn RolloutPolicy<T>>,
+    solve: bool,
+    rave: Option<f64>,
+    final_move: FinalMove,
+    last_search: Option<SearchInfo<T::Action>>,
     rng: StdRng,
     c: f64,
     steps_taken: u32,
-    /// The file into which we save any data that helps this AI across runs
-    mem_path: Option<String>,
     play_steps: usize
 }
 
 impl<M: Mdp> Drop for MctsAi<M> {
     fn drop(&mut self) {
         info!("In my lifetime, I took {} moves", self.steps_taken);
-        if let Some(ref mem_path) = self.mem_path {
-            if let Ok(mut fd) = std::fs::File::create(mem_path) {
-                let bytes = bitcode::serialize(&self.qmap).unwrap();
-                match fd.write_all(&bytes) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        panic!("Failed to serialize the qmap: {}", e);
-                    }
-                }
-            } else {
-                panic!("Failed to open the file for serializing the qmap.");
-            }
+        if let Err(e) = self.save_memory() {
+            error!("Failed to save the memory of the MCTS AI: {e}");
         }
     }
 }
 
 impl<T: Mdp> MctsAi<T> {
     /// seed is for the RNG, c is the exploration constant in the UCB1 formula
-    pub fn new(seed: u64, c: f64, mem_path: Option<String>) -> Self {
-        let mut qmap: QMap<T::State, T::Action> = QMap::<T::State, T::Action>::new();
-        if let Some(ref mem_path) = mem_path {
-            if let Ok(mut fd) = std::fs::File::open(mem_path) {
-                let mut bytes = vec![];
-                let _ = fd.read_to_end(&mut bytes);
-                if let Ok(a) = bitcode::deserialize(&bytes) {
-                    qmap = a;
-                }
-            }
-        }
+    /// The rollouts are uniformly random.
+    /// The AI starts with no memory, see `load_memory` to learn across runs.
+    pub fn new(seed: u64, c: f64) -> Self {
+        Self::with_rollout(seed, c, UniformRollout)
+    }
+
+    /// Like `new`, but the rollouts are played with `rollout`
+    pub fn with_rollout(seed: u64, c: f64, rollout: impl RolloutPolicy<T> + 'static) -> Self {
+        Self::with_policies(seed, c, Selection::Ucb1, rollout)
+    }
+
+    /// Like `new`, but the actions are selected with `selection` and the rollouts are played with `rollout`.
+    /// With `Selection::Puct`, c is the exploration constant of the PUCT formula.
+    pub fn with_policies(seed: u64, c: f64, selection: Selection<T>, rollout: impl RolloutPolicy<T> + 'static) -> Self {
         MctsAi {
-            qmap,
+            qmap: QMap::new(),
+            engine: MctsEngine::default(),
+            threads: 1,
+            parallelism: Parallelism::default(),
+            tree: None,
+            selection,
+            rollout: Box::new(rollout),
+            solve: true,
+            rave: None,
+            final_move: FinalMove::default(),
+            last_search: None,
             rng: StdRng::seed_from_u64(seed),
             c,
             steps_taken: 0,
-            mem_path,
             play_steps:10000
         }
     }
 
+    /// Keep the statistics in the knowledge store at `path`, and save them there when the AI is dropped.
+    /// If there is no file at `path` yet, the AI starts with no memory, and the store is made.
+    /// Nothing is read up front: the statistics of a state are read when the search first visits it.
+    /// On an error, the file is left as it is, and the AI will not save to it.
+    pub fn load_memory(&mut self, path: impl AsRef<Path>) -> Result<(), MemoryError> {
+        let store = KnowledgeStore::open::<T>(path.as_ref())?;
+        self.qmap.attach_store(store);
+        Ok(())
+    }
+
+    /// Save the statistics of the states that have learned something since the last save to the store given to `load_memory`, if any.
+    /// This is done when the AI is dropped too, so only what was learned after the last call is written then.
+    /// Only the `QMap` engine learns across runs.
+    pub fn save_memory(&mut self) -> Result<(), MemoryError> {
+        self.qmap.save_to_store()
+    }
+
     /// Set the number of MCTS steps to take in the `play` call
     pub fn set_play_steps(&mut self, k:usize){
         self.play_steps = k;
     }
+
+    pub fn set_engine(&mut self, engine: MctsEngine) {
+        self.engine = engine;
+    }
+
+    /// Search on this many threads
+    pub fn set_threads(&mut self, n: usize) {
+        self.threads = n.max(1);
+    }
+
+    /// How to search on more than one thread. `SharedTree` selects the `Tree` engine, which it needs.
+    /// The `Tree` engine neither reads nor writes the memory given to `load_memory`, and keeps no statistics for RAVE,
+    /// so with `SharedTree` the AI learns nothing across runs and plays without RAVE. A warning is logged if either was set.
+    pub fn set_parallelism(&mut self, parallelism: Parallelism) {
+        self.parallelism = parallelism;
+        if parallelism == Parallelism::SharedTree {
+            self.engine = MctsEngine::Tree;
+            if self.qmap.store.is_some() {
+                warn!("The shared tree doesn't use the memory of the MCTS AI, so nothing is learned or saved");
+            }
+            if self.rave.is_some() {
+                warn!("The shared tree keeps no statistics for RAVE, so RAVE is off");
+            }
+        }
+    }
+
+    /// Turn MCTS-Solver on or off. It is on by default.
+    /// The solver proves wins, losses and draws from the end of the game up, so that a proven win is always played.
+    pub fn set_solver(&mut self, on: bool) {
+        self.solve = on;
+        self.tree = None;
+    }
+
+    /// Turn on RAVE with the equivalence parameter `k`, or turn it off with None. It is off by default.
+    /// RAVE learns from every move in a simulation, not just the first, so it finds good moves in fewer steps.
+    /// `k` is the number of visits after which the statistics of a move count as much as the RAVE estimate.
+    /// Only the `QMap` engine keeps the statistics for RAVE, so a warning is logged if the `Tree` engine is selected.
+    pub fn set_rave(&mut self, k: Option<f64>) {
+        self.rave = k;
+        if k.is_some() && self.engine == MctsEngine::Tree {
+            warn!("The tree engine keeps no statistics for RAVE, so RAVE is off");
+        }
+    }
+
+    /// How to pick the move to play once the search is done
+    pub fn set_final_move(&mut self, final_move: FinalMove) {
+        self.final_move = final_move;
+    }
+
+    /// Bound what the `QMap` engine keeps in memory, or lift the bound with None. There is no bound by default.
+    /// When the bound is reached, states are evicted by the `eviction` policy. With a memory file, they are saved to it first,
+    /// so they are only forgotten until the search gets back to them.
+    pub fn set_memory_limit(&mut self, limit: Option<MemoryLimit>, eviction: Eviction) {
+        self.qmap.set_limit(limit, eviction);
+    }
+
+    /// How much the `QMap` engine keeps in memory
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.qmap.usage()
+    }
+
+    /// The number of nodes in the search tree of the `Tree` engine
+    pub fn n_tree_nodes(&self) -> usize {
+        self.tree.as_ref().map_or(0, |t| t.n_nodes())
+    }
+
+    /// The tree, with the root moved to `state`
+    fn tree_at(&mut self, state: &T::State) -> &mut SearchTree<T> {
+        match self.tree {
+            Some(ref mut tree) => tree.advance_to(state),
+            None => self.tree = Some(SearchTree::new(state.clone(), self.solve, self.selection.prior().cloned())),
+        }
+        self.tree.as_mut().expect("The tree was just made")
+    }
+
+    /// Search `state` within the budget, and return the best action
+    fn search(&mut self, state: &T::State, budget: Budget) -> T::Action {
+        let stop = StopCondition::new(budget);
+        let (c, solve) = (self.c, self.solve);
+        let settings = StepSettings {
+            c,
+            solve,
+            rave: self.rave,
+        };
+        if self.engine == MctsEngine::Tree {
+            self.tree_at(state);
+        }
+        let rollout = &*self.rollout;
+        let prior = self.selection.prior();
+        let helper_seeds: Vec<u64> = (1..self.threads).map(|_| self.rng.gen()).collect();
+        let limit = self.qmap.limit();
+        match (self.engine, self.parallelism) {
+            (MctsEngine::QMap, _) => {
+                let helpers = std::thread::scope(|scope| {
+                    let handles: Vec<_> = helper_seeds
+                        .into_iter()
+                        .map(|seed| {
+                            let stop = &stop;
+                            scope.spawn(move || {
+                                let mut qmap = QMap::new();
+                                if let Some((limit, eviction)) = limit {
+                                    qmap.set_limit(Some(limit), eviction);
+                                }
+                                search_qmap::<T>(state, &settings, prior.map(|p| &**p), rollout, &mut qmap, &mut StdRng::seed_from_u64(seed), stop);
+                                qmap
+                            })
+                        })
+                        .collect();
+                    search_qmap::<T>(state, &settings, prior.map(|p| &**p), rollout, &mut self.qmap, &mut self.rng, &stop);
+                    handles.into_iter().map(|h| h.join().expect("An MCTS thread panicked")).collect::<Vec<_>>()
+                });
+                for qmap in helpers {
+                    self.qmap.merge(qmap);
+                }
+                self.qmap.enforce_limit(state);
+                debug!("The MCTS memory holds {}", self.qmap.usage());
+            }
+            (MctsEngine::Tree, Parallelism::Root) => {
+                let tree = self.tree.as_mut().expect("The tree is made before searching");
+                let helpers = std::thread::scope(|scope| {
+                    let handles: Vec<_> = helper_seeds
+                        .into_iter()
+                        .map(|seed| {
+                            let stop = &stop;
+                            scope.spawn(move || {
+                                let mut tree = SearchTree::<T>::new(state.clone(), solve, prior.cloned());
+                                search_tree(&mut tree, c, rollout, &mut StdRng::seed_from_u64(seed), stop);
+                                tree
+                            })
+                        })
+                        .collect();
+                    search_tree(tree, c, rollout, &mut self.rng, &stop);
+                    handles.into_iter().map(|h| h.join().expect("An MCTS thread panicked")).collect::<Vec<_>>()
+                });
+                for other in helpers.iter() {
+                    tree.merge_root(other);
+                }
+            }
+            (MctsEngine::Tree, Parallelism::SharedTree) => {
+                let tree = Mutex::new(self.tree.take().expect("The tree is made before searching"));
+                std::thread::scope(|scope| {
+                    for seed in helper_seeds {
+                        let (tree, stop) = (&tree, &stop);
+                        scope.spawn(move || search_shared_tree(tree, c, rollout, &mut StdRng::seed_from_u64(seed), stop));
+                    }
+                    search_shared_tree(&tree, c, rollout, &mut self.rng, &stop);
+                });
+                self.tree = Some(tree.into_inner().expect("An MCTS thread panicked"));
+            }
+        }
+        self.steps_taken += stop.total_steps();
+        let (stats, root_value) = match self.engine {
+            MctsEngine::QMap => (qmap_stats::<T>(&self.qmap, state), self.qmap.proven_value(state)),
+            MctsEngine::Tree => {
+                let tree = self.tree.as_ref().expect("The tree is made before searching");
+                (tree.root_stats(), tree.root_value())
+            }
+        };
+        let best = final_move::choose::<T>(self.final_move, &stats, root_value, self.c, state, &mut self.rng);
+        self.last_search = Some(self.analysis(state, best.clone(), &stats, root_value, &stop));
+        best
+    }
+
+    /// Tell what the search of `state` found
+    fn analysis(
+        &self,
+        state: &T::State,
+        best_move: T::Action,
+        stats: &[ActionStats<T::Action>],
+        root_value: Option<f64>,
+        stop: &StopCondition,
+    ) -> SearchInfo<T::Action> {
+        let principal_variation = match self.engine {
+            MctsEngine::QMap => qmap_principal_variation::<T>(&self.qmap, state, &best_move),
+            MctsEngine::Tree => self
+                .tree
+                .as_ref()
+                .expect("The tree is made before searching")
+                .principal_variation(&best_move),
+        };
+        let mut root_moves: Vec<_> = stats
+            .iter()
+            .filter(|s| s.n > 0.0)
+            .map(|s| RootMove {
+                coordinate: s.action.clone(),
+                score: s.proven.unwrap_or(s.w / s.n),
+                bound: ScoreBound::Exact,
+            })
+            .collect();
+        root_moves.sort_by(|a, b| b.score.total_cmp(&a.score));
+        SearchInfo {
+            score: root_moves.iter().find(|r| r.coordinate == best_move).map_or(0.0, |r| r.score),
+            best_move,
+            depth: principal_variation.len(),
+            principal_variation,
+            nodes: stop.total_steps() as usize,
+            elapsed: stop.t0.elapsed(),
+            root_moves,
+            outcome: root_value.map(outcome::<T>),
+            proven_moves: stats
+                .iter()
+                .filter_map(|s| s.proven.map(|v| (s.action.clone(), outcome::<T>(v))))
+                .collect(),
+        }
+    }
+}
+
+/// Take MCTS steps from `state` until told to stop, or until the solver has proven the value of `state`
+fn search_qmap<T: Mdp>(
+    state: &T::State,
+    settings: &StepSettings,
+    prior: Option<&dyn PriorPolicy<T>>,
+    rollout: &dyn RolloutPolicy<T>,
+    qmap: &mut QMap<T::State, T::Action>,
+    rng: &mut StdRng,
+    stop: &StopCondition,
+) {
+    let mut buffers = StepBuffers::default();
+    let mut n_steps = 0;
+    qmap.begin_search();
+    loop {
+        qmap.enforce_limit(state);
+        mcts_step::<T>(state, settings, prior, rollout, qmap, &mut buffers, rng);
+        n_steps += 1;
+        if !stop.keep_going(n_steps) || qmap.proven_value(state).is_some() {
+            break;
+        }
+    }
+}
+
+/// Take MCTS steps in the tree until told to stop, or until the solver has proven the value of the root
+fn search_tree<T: Mdp>(
+    tree: &mut SearchTree<T>,
+    c: f64,
+    rollout: &dyn RolloutPolicy<T>,
+    rng: &mut StdRng,
+    stop: &StopCondition,
+) {
+    let mut n_steps = 0;
+    loop {
+        tree.step(c, rollout, rng);
+        n_steps += 1;
+        if !stop.keep_going(n_steps) || tree.root_value().is_some() {
+            break;
+        }
+    }
+}
+
+/// Take MCTS steps in a tree shared with other threads until told to stop.
+/// The tree is only locked while going down and backing up, not during the rollout.
+fn search_shared_tree<T: Mdp>(
+    tree: &Mutex<SearchTree<T>>,
+    c: f64,
+    rollout: &dyn RolloutPolicy<T>,
+    rng: &mut StdRng,
+    stop: &StopCondition,
+) {
+    let lock = || tree.lock().expect("An MCTS thread panicked");
+    let mut path = vec![];
+    let mut n_steps = 0;
+    loop {
+        let leaf = lock().descend(c, VIRTUAL_LOSS, &mut path, rng);
+        let value = match leaf {
+            Leaf::Terminal => 0.0,
+            Leaf::New(state) => rollout.rollout(state, rng, None),
+            Leaf::Proven(value) => value,
+        };
+        let solved = {
+            let mut tree = lock();
+            tree.backup(&path, value, VIRTUAL_LOSS);
+            tree.root_value().is_some()
+        };
+        n_steps += 1;
+        if !stop.keep_going(n_steps) || solved {
+            break;
+        }
+    }
 }
 
 impl<T, B> BlitzPlayer<B> for MctsAi<T>
@@ -352,21 +1291,12 @@ where
     T: Mdp<Action = B::Coordinate, State = B>,
     B: Board,
 {
-    fn blitz(&mut self, b: &B, _time_remaining: std::time::Duration) -> <B as Board>::Coordinate {
-        let t0 = std::time::Instant::now();
-        let mut n_steps = 0;
-
-        loop {
-            mcts_step::<T>(b, self.c, &mut self.qmap, &mut self.rng);
-            n_steps += 1;
-            let duration_per_step = t0.elapsed() / n_steps;
-            if t0.elapsed() + duration_per_step + Duration::from_millis(1) > _time_remaining / 8 {
-                break;
-            }
-        }
-        // dbg!(n_steps);
-        self.steps_taken += n_steps;
-        best_action::<T>(b, self.c, &self.qmap, &mut self.rng)
+    fn blitz(&mut self, b: &B, time_remaining: std::time::Duration) -> <B as Board>::Coordinate {
+        self.search(b, Budget::Time(time_remaining / 8))
+    }
+
+    fn last_search(&self) -> Option<&SearchInfo<B::Coordinate>> {
+        self.last_search.as_ref()
     }
 }
 
@@ -376,25 +1306,29 @@ where
     B: Board,
 {
     fn play(&mut self, b: &B) -> B::Coordinate {
-        for _ in 0..self.play_steps {
-            mcts_step::<T>(b, self.c, &mut self.qmap, &mut self.rng);
-            self.steps_taken += 1;
-        }
+        self.search(b, Budget::Steps(self.play_steps as u32))
+    }
 
-        best_action::<T>(b, self.c, &self.qmap, &mut self.rng)
+    fn last_search(&self) -> Option<&SearchInfo<B::Coordinate>> {
+        self.last_search.as_ref()
     }
 }
 
 impl<B: Board> Mdp for B
 where
     B::Coordinate: Ord + Hash + Debug + for<'de> serde::Deserialize<'de> + Serialize,
-    B: Hash + Eq + Clone + Debug + for<'de> serde::Deserialize<'de> + Serialize,
+    B::Coordinate: Send + Sync,
+    B: Hash + Eq + Clone + Debug + Send + Sync + for<'de> serde::Deserialize<'de> + Serialize,
 {
     type Action = B::Coordinate;
 
     type State = B;
 
+    const NAME: &'static str = B::NAME;
+
     const DISCOUNT_FACTOR: f64 = -0.999;
+    // the only rewards are 1 for winning and -1 for losing, so the sign of a return tells the outcome
+    const WIN_THRESHOLD: f64 = 0.0;
 
     fn act(mut board: Self::State, action: &Self::Action) -> (Self::State, f64) {
         let player_mark = board.current_player();
@@ -419,4 +1353,8 @@ where
     fn allowed_actions(s: &Self::State) -> Vec<Self::Action> {
         s.valid_moves()
     }
+
+    fn allowed_actions_into(s: &Self::State, actions: &mut Vec<Self::Action>) {
+        s.valid_moves_into(actions)
+    }
 }
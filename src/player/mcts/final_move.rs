@@ -0,0 +1,149 @@
+//! How `MctsAi` picks the move to play once the search is done.
+//! The search picks moves by UCB1, which adds a bonus for exploring. That is right for learning about the moves,
+//! but the move that is played should only depend on what was learned.
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use super::{ActionStats, Mdp};
+
+/// How `MctsAi` picks the move to play from the statistics of its search
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FinalMove {
+    /// The move that was searched most, the robust child. The search spends its steps on the moves that look best,
+    /// so a move with many visits has a mean that can be trusted.
+    #[default]
+    MaxVisits,
+    /// The move with the highest mean return
+    MaxMean,
+    /// The move with the highest lower confidence bound on its return: the mean minus the exploration term of UCB1.
+    /// Like `MaxMean`, but it does not trust moves that were searched little.
+    LowerBound,
+    /// Sample a move with probability proportional to visits^(1/temperature), for varied games in self-play.
+    /// The lower the temperature, the closer to `MaxVisits`.
+    Temperature(f64),
+}
+
+/// Pick the move to play in `state`, from the statistics of its actions.
+/// The solver overrules the statistics. A proven action counts at its exact value. If the state is proven,
+/// only the actions that prove it are played, and otherwise an action proven to lose is only played if all the others are too.
+/// If nothing was searched, the move is random.
+pub(crate) fn choose<M: Mdp>(
+    policy: FinalMove,
+    stats: &[ActionStats<M::Action>],
+    state_value: Option<f64>,
+    c: f64,
+    state: &M::State,
+    rng: &mut StdRng,
+) -> M::Action {
+    let visited = stats.iter().filter(|s| s.n > 0.0);
+    let candidates: Vec<_> = match state_value {
+        Some(value) => visited.filter(|s| s.proven == Some(value)).collect(),
+        None => {
+            let not_lost: Vec<_> = visited
+                .clone()
+                .filter(|s| !s.proven.is_some_and(|v| v < -M::WIN_THRESHOLD))
+                .collect();
+            if not_lost.is_empty() {
+                visited.collect()
+            } else {
+                not_lost
+            }
+        }
+    };
+    if candidates.is_empty() {
+        return M::allowed_actions(state)
+            .choose(rng)
+            .expect("There must be at least one action")
+            .clone();
+    }
+    let best = match policy {
+        FinalMove::Temperature(temperature) if temperature > 0.0 => {
+            // relative to the most visited, so that a low temperature doesn't overflow
+            let max_n = candidates.iter().map(|s| s.n).fold(0.0, f64::max);
+            let weights = candidates.iter().map(|s| (s.n / max_n).powf(1.0 / temperature));
+            let dist = WeightedIndex::new(weights).expect("The most visited action has weight 1");
+            candidates[dist.sample(rng)]
+        }
+        _ => {
+            let t: f64 = stats.iter().map(|s| s.n).sum();
+            let score = |s: &ActionStats<M::Action>| {
+                let value = s.proven.unwrap_or(s.w / s.n);
+                match policy {
+                    FinalMove::MaxMean => value,
+                    FinalMove::LowerBound if s.proven.is_none() => value - c * (t.ln() / s.n).sqrt(),
+                    FinalMove::LowerBound => value,
+                    FinalMove::MaxVisits | FinalMove::Temperature(_) => s.n,
+                }
+            };
+            let record = candidates.iter().map(|s| score(s)).fold(-f64::INFINITY, f64::max);
+            let ties: Vec<_> = candidates.into_iter().filter(|s| score(s) == record).collect();
+            *ties.choose(rng).expect("At least the record holder")
+        }
+    };
+    best.action.clone()
+}
+
+#[cfg(test)]
+mod test {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::game::tictactoe::{TTTAddr, TTTBoard};
+
+    fn stats(moves: &[(usize, f64, f64, Option<f64>)]) -> Vec<ActionStats<TTTAddr>> {
+        moves
+            .iter()
+            .map(|&(a, w, n, proven)| ActionStats {
+                action: TTTAddr(a),
+                w,
+                n,
+                proven,
+            })
+            .collect()
+    }
+
+    fn pick(policy: FinalMove, stats: &[ActionStats<TTTAddr>], state_value: Option<f64>) -> TTTAddr {
+        let mut rng = StdRng::seed_from_u64(1);
+        choose::<TTTBoard>(policy, stats, state_value, 0.5, &TTTBoard::default(), &mut rng)
+    }
+
+    #[test]
+    fn policies_differ() {
+        // most visited, best lower bound, best mean
+        let s = stats(&[(1, 5.0, 10.0, None), (2, 3.0, 4.0, None), (3, 1.0, 1.0, None)]);
+        assert_eq!(pick(FinalMove::MaxVisits, &s, None), TTTAddr(1));
+        assert_eq!(pick(FinalMove::LowerBound, &s, None), TTTAddr(2));
+        assert_eq!(pick(FinalMove::MaxMean, &s, None), TTTAddr(3));
+        assert_eq!(pick(FinalMove::Temperature(0.01), &s, None), TTTAddr(1));
+    }
+
+    #[test]
+    fn temperature_samples_every_move() {
+        let s = stats(&[(1, 5.0, 10.0, None), (2, 3.0, 4.0, None), (3, 1.0, 1.0, None)]);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut seen = [0; 3];
+        for _ in 0..1000 {
+            let a = choose::<TTTBoard>(FinalMove::Temperature(1.0), &s, None, 0.5, &TTTBoard::default(), &mut rng);
+            seen[a.0 - 1] += 1;
+        }
+        // in proportion to the visits
+        assert!(seen[0] > seen[1] && seen[1] > seen[2] && seen[2] > 0, "{seen:?}");
+    }
+
+    #[test]
+    fn solver_overrules_the_statistics() {
+        // the most visited move turned out to lose
+        let s = stats(&[(1, 5.0, 10.0, Some(-1.0)), (2, 0.0, 4.0, None), (3, -1.0, 2.0, None)]);
+        assert_eq!(pick(FinalMove::MaxVisits, &s, None), TTTAddr(2));
+        // the state is proven by a move that was hardly searched
+        let s = stats(&[(1, 5.0, 10.0, None), (2, 0.0, 4.0, Some(0.0)), (3, 1.0, 1.0, Some(1.0))]);
+        for policy in [FinalMove::MaxVisits, FinalMove::MaxMean, FinalMove::LowerBound, FinalMove::Temperature(1.0)] {
+            assert_eq!(pick(policy, &s, Some(1.0)), TTTAddr(3), "{policy:?}");
+        }
+        // all moves lose, so play one of them anyway
+        let s = stats(&[(1, 5.0, 10.0, Some(-1.0)), (2, 0.0, 4.0, Some(-0.9))]);
+        assert_eq!(pick(FinalMove::MaxMean, &s, Some(-0.9)), TTTAddr(2));
+    }
+}
@@ -0,0 +1,191 @@
+//! Reading a whole memory file, to see what an AI has learned. `arena inspect` prints and exports what is read here.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::store;
+use super::{MemoryError, Mdp, StateRecord};
+
+/// What a memory file holds about a state
+#[derive(Debug, Clone, Serialize)]
+pub struct StateSummary<S, A> {
+    pub state: S,
+    /// The shortest line of play from the start of the game to the state, through the states in the file,
+    /// so its length is the ply of the state. None if the state can't be reached that way.
+    pub moves: Option<Vec<A>>,
+    pub visits: f64,
+    /// The value that the solver proved for the state, as seen by its actor
+    pub proven: Option<f64>,
+    /// The actions that were searched, most visited first
+    pub actions: Vec<ActionSummary<A>>,
+}
+
+/// What a memory file holds about an action from a state
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionSummary<A> {
+    pub action: A,
+    pub visits: f64,
+    /// The mean return after the action, as seen by the actor
+    pub mean: f64,
+    /// The value that the solver proved for the action, as seen by the actor
+    pub proven: Option<f64>,
+}
+
+impl<S, A> StateSummary<S, A> {
+    /// The number of actions from the start of the game to the state, if it can be reached through the file
+    pub fn ply(&self) -> Option<usize> {
+        self.moves.as_ref().map(|m| m.len())
+    }
+}
+
+/// What a memory file holds about each of the states of the MDP `M`
+type Summaries<M> = Vec<StateSummary<<M as Mdp>::State, <M as Mdp>::Action>>;
+
+/// All the states in the memory file at `path`, most visited first.
+/// The lines of play to the states are found by searching the file from `start`, the state that the games start in.
+/// The whole file is read into memory. It is only read, not locked, so it can be inspected while an AI is using it,
+/// but what the AI hasn't saved yet is not in it.
+pub fn read_memory_file<M: Mdp>(
+    path: impl AsRef<Path>,
+    start: &M::State,
+) -> Result<Summaries<M>, MemoryError> {
+    let records = store::read_all::<M>(path.as_ref())?;
+    let mut moves = lines_of_play::<M>(&records, start);
+    let mut states: Vec<_> = records
+        .into_iter()
+        .map(|(state, record)| {
+            let mut actions: Vec<_> = record
+                .actions
+                .iter()
+                .map(|(a, &(w, n))| ActionSummary {
+                    action: a.clone(),
+                    visits: n,
+                    mean: if n > 0.0 { w / n } else { 0.0 },
+                    proven: record.proofs.as_ref().and_then(|p| p.actions.get(a)).copied(),
+                })
+                .collect();
+            actions.sort_by(|a, b| b.visits.total_cmp(&a.visits).then_with(|| a.action.cmp(&b.action)));
+            StateSummary {
+                moves: moves.remove(&state),
+                proven: record.proofs.and_then(|p| p.value),
+                visits: record.visits,
+                actions,
+                state,
+            }
+        })
+        .collect();
+    // the states that can't be reached last among those with as many visits
+    let ply = |s: &StateSummary<_, _>| s.ply().unwrap_or(usize::MAX);
+    states.sort_by(|a, b| b.visits.total_cmp(&a.visits).then_with(|| ply(a).cmp(&ply(b))));
+    Ok(states)
+}
+
+/// The shortest lines of play from `start` to the states in `records`, by a breadth first search through the actions in them
+fn lines_of_play<M: Mdp>(
+    records: &HashMap<M::State, StateRecord<M::Action>>,
+    start: &M::State,
+) -> HashMap<M::State, Vec<M::Action>> {
+    let mut lines = HashMap::new();
+    if !records.contains_key(start) {
+        return lines;
+    }
+    lines.insert(start.clone(), vec![]);
+    let mut queue = VecDeque::from([start.clone()]);
+    while let Some(state) = queue.pop_front() {
+        let mut actions: Vec<_> = records[&state].actions.keys().collect();
+        // in order, so that the same line is found every time
+        actions.sort();
+        for a in actions {
+            let (next, _) = M::act(state.clone(), a);
+            if records.contains_key(&next) && !lines.contains_key(&next) {
+                let mut line = lines[&state].clone();
+                line.push(a.clone());
+                lines.insert(next.clone(), line);
+                queue.push_back(next);
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+    use crate::core::Board;
+    use crate::game::tictactoe::{TTTAddr, TTTBoard};
+    use crate::player::mcts::store::{index_path, lock_path, KnowledgeStore};
+    use crate::player::mcts::QMap;
+
+    #[test]
+    fn states_with_their_lines_of_play() {
+        let path = std::env::temp_dir().join(format!("xoxo-inspect-{}.data", std::process::id()));
+        let start = TTTBoard::default();
+        let mut after = start;
+        after.place_mark(TTTAddr(5), start.current_player());
+        let mut qmap = QMap::<TTTBoard, TTTAddr>::new();
+        qmap.add_to_state_action_data(&start, &TTTAddr(5), 1.0);
+        qmap.add_to_state_action_data(&start, &TTTAddr(5), 0.0);
+        qmap.add_to_state_action_data(&start, &TTTAddr(1), -1.0);
+        qmap.add_to_state_action_data(&after, &TTTAddr(1), 1.0);
+        // a state that no searched action leads to
+        let mut lone = start;
+        lone.place_mark(TTTAddr(9), start.current_player());
+        qmap.increment_state_visits(&lone);
+        qmap.attach_store(KnowledgeStore::open::<TTTBoard>(&path).unwrap());
+        qmap.save_to_store().unwrap();
+        drop(qmap);
+
+        let states = read_memory_file::<TTTBoard>(&path, &start).unwrap();
+        assert_eq!(states.len(), 3);
+        assert_eq!(states[0].state, start);
+        assert_eq!(states[0].ply(), Some(0));
+        assert_eq!(states[0].visits, 3.0);
+        assert_eq!(states[0].actions[0].action, TTTAddr(5));
+        assert_eq!(states[0].actions[0].mean, 0.5);
+        assert_eq!(states[1].moves, Some(vec![TTTAddr(5)]));
+        assert_eq!(states[2].state, lone);
+        assert_eq!(states[2].moves, None);
+        for file in [index_path(&path), lock_path(&path), path] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_without_changing_the_file() {
+        let path = std::env::temp_dir().join(format!("xoxo-inspect-{}-read-only.data", std::process::id()));
+        let start = TTTBoard::default();
+        let mut qmap = QMap::<TTTBoard, TTTAddr>::new();
+        qmap.add_to_state_action_data(&start, &TTTAddr(5), 1.0);
+        qmap.attach_store(KnowledgeStore::open::<TTTBoard>(&path).unwrap());
+        qmap.save_to_store().unwrap();
+        // a record cut short at the end of the log, which opening the store would cut off
+        let mut log = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        log.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+        drop(log);
+        let log = std::fs::read(&path).unwrap();
+        let index = std::fs::read(index_path(&path)).unwrap();
+        // read while the AI still holds the lock
+        let states = read_memory_file::<TTTBoard>(&path, &start).unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].visits, 1.0);
+        assert_eq!(std::fs::read(&path).unwrap(), log);
+        assert_eq!(std::fs::read(index_path(&path)).unwrap(), index);
+        drop(qmap);
+        for file in [index_path(&path), lock_path(&path), path.clone()] {
+            std::fs::remove_file(file).unwrap();
+        }
+
+        // a snapshot of an older version is read as it is, without turning it into a store
+        let snapshot = include_bytes!("../../../tests/data/ttt-before-header.mcts");
+        std::fs::write(&path, snapshot).unwrap();
+        let states = read_memory_file::<TTTBoard>(&path, &start).unwrap();
+        assert_eq!(states.len(), 26);
+        assert_eq!(std::fs::read(&path).unwrap(), snapshot);
+        assert!(!index_path(&path).exists() && !lock_path(&path).exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,410 @@
+//! A bound on the memory of the `QMap`. Without one, every state that the search expands stays in memory for good.
+//! When the bound is reached, states are evicted by an `Eviction` policy until the maps are a tenth under it.
+//! With a knowledge store, the evicted states are saved to it first, and read back if the search gets to them again,
+//! so the bound is on what is kept in memory, not on what is learned.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::mem::size_of;
+
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::{ActionMap, Proofs, QMap, StateRecord};
+
+/// How much the `QMap` of `MctsAi` may hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryLimit {
+    /// At most this many states
+    States(usize),
+    /// At most about this many bytes, as counted by `MemoryUsage`
+    Bytes(usize),
+}
+
+/// Which states to evict when the memory is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eviction {
+    /// The states that the search has not been through for the longest time
+    #[default]
+    LeastRecentlyUsed,
+    /// The states with the fewest visits, which have taught the search the least
+    LowestVisits,
+    /// All the states more than this many plies below the state that the current search is from,
+    /// and then the least recently used, if that is not enough.
+    /// A state counts as deep until the current search gets to it, so the states that only the searches
+    /// from earlier positions got to go first, and so do the states that were loaded or searched before the limit was set.
+    DeeperThan(usize),
+}
+
+/// How much the `QMap` holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub states: usize,
+    /// An estimate: what the keys and values take in the hash maps, without the spare room of the maps
+    pub bytes: usize,
+}
+
+impl Display for MemoryUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} states in about {:.1} MiB", self.states, self.bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+impl MemoryLimit {
+    fn exceeded_by(&self, usage: MemoryUsage) -> bool {
+        match *self {
+            MemoryLimit::States(max) => usage.states > max,
+            MemoryLimit::Bytes(max) => usage.bytes > max,
+        }
+    }
+
+    /// The limit to evict down to, so that there is room to grow before the next eviction
+    fn low_water(&self) -> MemoryLimit {
+        match *self {
+            MemoryLimit::States(max) => MemoryLimit::States(max - max / 10),
+            MemoryLimit::Bytes(max) => MemoryLimit::Bytes(max - max / 10),
+        }
+    }
+}
+
+/// The limit, and what the eviction policies need to know about each state
+pub(super) struct Bounds<S> {
+    limit: MemoryLimit,
+    eviction: Eviction,
+    meta: HashMap<S, StateMeta>,
+    /// Counts the uses of states, so that the order of the uses is known
+    clock: u64,
+    /// Counts the searches, so that the plies of the states are only compared to the root of the current one
+    search: u64,
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct StateMeta {
+    /// The clock when the search last went through the state
+    last_used: u64,
+    /// The fewest plies below the root of the search `search` that the state was found
+    ply: usize,
+    /// The last search that went through the state
+    search: u64,
+}
+
+impl StateMeta {
+    /// The plies below the root of the search `search` at which the state was found, or `usize::MAX` if that search didn't get to it
+    fn ply_in(&self, search: u64) -> usize {
+        if self.search == search {
+            self.ply
+        } else {
+            usize::MAX
+        }
+    }
+}
+
+/// What is known about a state that was not used since the limit was set
+const UNKNOWN: StateMeta = StateMeta {
+    last_used: 0,
+    ply: usize::MAX,
+    search: 0,
+};
+
+impl<S, A> QMap<S, A>
+where
+    S: Hash + Eq + Clone,
+    A: Hash + Eq + Clone,
+{
+    /// Bound the size of the maps, or lift the bound with None. What the maps hold now is only evicted at the next `enforce_limit`.
+    pub fn set_limit(&mut self, limit: Option<MemoryLimit>, eviction: Eviction) {
+        self.bounds = limit.map(|limit| {
+            let (meta, search) = self.bounds.take().map(|b| (b.meta, b.search)).unwrap_or_default();
+            let clock = meta.values().map(|m| m.last_used).max().unwrap_or(0);
+            Bounds {
+                limit,
+                eviction,
+                meta,
+                clock,
+                search,
+            }
+        });
+    }
+
+    pub fn limit(&self) -> Option<(MemoryLimit, Eviction)> {
+        self.bounds.as_ref().map(|b| (b.limit, b.eviction))
+    }
+
+    /// Note that a new search starts. The plies that the earlier searches found were below the states they searched from,
+    /// which the game may have left behind, so they no longer count.
+    pub fn begin_search(&mut self) {
+        if let Some(bounds) = self.bounds.as_mut() {
+            bounds.search += 1;
+        }
+    }
+
+    /// Note that the search went through `state`, `ply` plies below the state it searches from
+    pub fn touch(&mut self, state: &S, ply: usize) {
+        let Some(bounds) = self.bounds.as_mut() else {
+            return;
+        };
+        bounds.clock += 1;
+        let meta = bounds.meta.entry(state.clone()).or_insert(UNKNOWN);
+        meta.last_used = bounds.clock;
+        meta.ply = meta.ply_in(bounds.search).min(ply);
+        meta.search = bounds.search;
+    }
+
+    /// Note that the states of `other` were used now, as it is merged into this map
+    pub(super) fn touch_merged(&mut self, other: &QMap<S, A>) {
+        let Some(bounds) = self.bounds.as_mut() else {
+            return;
+        };
+        bounds.clock += 1;
+        let now = bounds.clock;
+        // the helpers of a search search from the same state, so their plies count for the current search
+        for s in other.state_action_value.keys() {
+            let ply = other.bounds.as_ref().and_then(|b| Some(b.meta.get(s)?.ply_in(b.search))).unwrap_or(usize::MAX);
+            let meta = bounds.meta.entry(s.clone()).or_insert(UNKNOWN);
+            meta.last_used = now;
+            meta.ply = meta.ply_in(bounds.search).min(ply);
+            meta.search = bounds.search;
+        }
+    }
+
+    pub fn usage(&self) -> MemoryUsage {
+        let keyed = |n: usize, value: usize| n * (size_of::<S>() + value);
+        let bytes = keyed(self.state_action_value.len(), size_of::<(f64, ActionMap<A>)>())
+            + keyed(self.amaf.len(), size_of::<ActionMap<A>>())
+            + keyed(self.proven.len(), size_of::<Proofs<A>>())
+            + keyed(self.bounds.as_ref().map_or(0, |b| b.meta.len()), size_of::<StateMeta>())
+            + self.action_entries * (size_of::<A>() + size_of::<(f64, f64)>());
+        MemoryUsage {
+            states: self.state_action_value.len(),
+            bytes,
+        }
+    }
+
+    /// The number of actions in all the maps of all the states, counted again from scratch to check the running count
+    #[cfg(test)]
+    pub(super) fn count_action_entries(&self) -> usize {
+        self.state_action_value.values().map(|(_, m)| m.len()).sum::<usize>()
+            + self.amaf.values().map(|m| m.len()).sum::<usize>()
+            + self.proven.values().map(|p| p.actions.len()).sum::<usize>()
+    }
+
+    /// The states to evict to get under the low water mark, never `keep`, in the order of the policy
+    fn eviction_order(&self, keep: &S) -> Vec<S> {
+        let Some(bounds) = self.bounds.as_ref() else {
+            return vec![];
+        };
+        let meta = |s: &S| bounds.meta.get(s).copied().unwrap_or(UNKNOWN);
+        let mut states: Vec<(StateMeta, f64, &S)> = self
+            .state_action_value
+            .iter()
+            .filter(|(s, _)| *s != keep)
+            .map(|(s, (visits, _))| (meta(s), *visits, s))
+            .collect();
+        match bounds.eviction {
+            Eviction::LeastRecentlyUsed => states.sort_by_key(|(m, _, _)| m.last_used),
+            Eviction::LowestVisits => states.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.last_used.cmp(&b.0.last_used))),
+            Eviction::DeeperThan(n) => states.sort_by_key(|(m, _, _)| (m.ply_in(bounds.search) <= n, m.last_used)),
+        }
+        let low_water = bounds.limit.low_water();
+        let mut usage = self.usage();
+        let mut order = vec![];
+        for (m, _, s) in states {
+            let deep = matches!(bounds.eviction, Eviction::DeeperThan(n) if m.ply_in(bounds.search) > n);
+            if !deep && !low_water.exceeded_by(usage) {
+                break;
+            }
+            usage.states -= 1;
+            usage.bytes = usage.bytes.saturating_sub(self.state_bytes(s));
+            order.push(s.clone());
+        }
+        order
+    }
+
+    /// What `usage` counts for `state`
+    fn state_bytes(&self, state: &S) -> usize {
+        let key = size_of::<S>();
+        let action = size_of::<A>() + size_of::<(f64, f64)>();
+        let mut bytes = key + size_of::<(f64, ActionMap<A>)>() + key + size_of::<StateMeta>();
+        bytes += self.state_action_value.get(state).map_or(0, |(_, m)| m.len() * action);
+        bytes += self.amaf.get(state).map_or(0, |m| key + size_of::<ActionMap<A>>() + m.len() * action);
+        bytes += self.proven.get(state).map_or(0, |p| key + size_of::<Proofs<A>>() + p.actions.len() * action);
+        bytes
+    }
+
+    /// Take everything about `state` out of the maps
+    fn remove_state(&mut self, state: &S) -> Option<StateRecord<A>> {
+        let (visits, actions) = self.state_action_value.remove(state)?;
+        let proofs = self.proven.remove(state);
+        let amaf = self.amaf.remove(state);
+        if let Some(bounds) = self.bounds.as_mut() {
+            bounds.meta.remove(state);
+        }
+        self.action_entries -= actions.len()
+            + amaf.as_ref().map_or(0, |m| m.len())
+            + proofs.as_ref().map_or(0, |p| p.actions.len());
+        Some(StateRecord {
+            visits,
+            actions,
+            proofs,
+            amaf,
+        })
+    }
+}
+
+impl<S, A> QMap<S, A>
+where
+    S: Hash + Eq + Clone + Serialize + DeserializeOwned,
+    A: Hash + Eq + Clone + Serialize + DeserializeOwned,
+{
+    /// If the maps are over the limit, evict states until they are a tenth under it.
+    /// The evicted states that have learned something since the last save are saved to the store, if there is one.
+    /// `keep`, the state searched from, is never evicted.
+    /// Returns the number of states evicted.
+    pub fn enforce_limit(&mut self, keep: &S) -> usize {
+        match self.bounds.as_ref() {
+            Some(bounds) if bounds.limit.exceeded_by(self.usage()) => {}
+            _ => return 0,
+        }
+        let mut n_evicted = 0;
+        let mut evicted = vec![];
+        for s in self.eviction_order(keep) {
+            let dirty = self.dirty.remove(&s);
+            if let Some(record) = self.remove_state(&s) {
+                n_evicted += 1;
+                if dirty {
+                    evicted.push((s, record));
+                }
+            }
+        }
+        if let Some(store) = self.store.as_mut() {
+            if let Err(e) = store.put_all(evicted.into_iter()) {
+                warn!("Failed to save the evicted states, what they knew is lost: {e}");
+            }
+        }
+        info!("Evicted {n_evicted} states from the MCTS memory, {} left", self.usage());
+        n_evicted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::Board;
+    use crate::game::connect_four::C4Board;
+    use crate::player::mcts::store::{index_path, lock_path, KnowledgeStore};
+
+    /// A map with states 0..n, used in that order, where state i has i visits
+    fn qmap(n: u32, limit: MemoryLimit, eviction: Eviction) -> QMap<u32, u8> {
+        let mut qmap = QMap::new();
+        qmap.set_limit(Some(limit), eviction);
+        for s in 0..n {
+            qmap.increment_state_visits(&s);
+            for _ in 0..s {
+                qmap.add_to_state_action_data(&s, &1, 1.0);
+            }
+            qmap.touch(&s, s as usize % 5);
+        }
+        qmap
+    }
+
+    #[test]
+    fn least_recently_used() {
+        let mut qmap = qmap(100, MemoryLimit::States(50), Eviction::LeastRecentlyUsed);
+        assert_eq!(qmap.enforce_limit(&0), 55);
+        assert_eq!(qmap.usage().states, 45);
+        // the state searched from is kept, however long ago it was used
+        assert!(qmap.get(&0).is_some());
+        assert!(qmap.get(&1).is_none());
+        assert!(qmap.get(&99).is_some());
+        // under the limit, nothing happens
+        assert_eq!(qmap.enforce_limit(&0), 0);
+    }
+
+    #[test]
+    fn lowest_visits() {
+        let mut qmap = qmap(100, MemoryLimit::States(50), Eviction::LowestVisits);
+        // the states that were used last are touched again, but have few visits
+        for s in 0..10 {
+            qmap.touch(&s, 0);
+        }
+        qmap.enforce_limit(&99);
+        assert!((0..55).all(|s| qmap.get(&s).is_none()));
+        assert!((55..100).all(|s| qmap.get(&s).is_some()));
+    }
+
+    #[test]
+    fn deeper_than() {
+        let mut qmap = qmap(100, MemoryLimit::States(90), Eviction::DeeperThan(2));
+        // found deep first, then shallow
+        qmap.touch(&3, 1);
+        qmap.enforce_limit(&0);
+        // all the deep states go, even if fewer would do
+        assert_eq!(qmap.usage().states, 61);
+        assert!(qmap.get(&3).is_some());
+        assert!(qmap.get(&4).is_none());
+        assert!(qmap.get(&5).is_some());
+    }
+
+    #[test]
+    fn deeper_than_the_current_root() {
+        let mut qmap = QMap::<u32, u8>::new();
+        qmap.set_limit(Some(MemoryLimit::States(5)), Eviction::DeeperThan(1));
+        for s in 0..6 {
+            qmap.increment_state_visits(&s);
+        }
+        // the first search, from 0, finds 1 and 2 next to it, and 3 and 4 below them
+        qmap.begin_search();
+        for (s, ply) in [(0, 0), (1, 1), (2, 1), (3, 2), (4, 3)] {
+            qmap.touch(&s, ply);
+        }
+        // the game moves on to 3, and the second search finds 4 and 5 next to it
+        qmap.begin_search();
+        for (s, ply) in [(3, 0), (4, 1), (5, 1)] {
+            qmap.touch(&s, ply);
+        }
+        // what was next to the old root can't be reached any more, however shallow it was then
+        assert_eq!(qmap.enforce_limit(&3), 3);
+        assert!((0..3).all(|s| qmap.get(&s).is_none()));
+        assert!((3..6).all(|s| qmap.get(&s).is_some()));
+    }
+
+    #[test]
+    fn bytes() {
+        let mut qmap = qmap(100, MemoryLimit::Bytes(usize::MAX), Eviction::LeastRecentlyUsed);
+        let usage = qmap.usage();
+        assert_eq!(qmap.action_entries, 99);
+        assert_eq!(qmap.count_action_entries(), 99);
+        qmap.set_limit(Some(MemoryLimit::Bytes(usage.bytes / 2)), Eviction::LeastRecentlyUsed);
+        qmap.enforce_limit(&0);
+        assert!(qmap.usage().bytes <= usage.bytes / 2);
+        assert!(qmap.usage().bytes > usage.bytes / 3);
+        assert_eq!(qmap.action_entries, qmap.count_action_entries());
+    }
+
+    #[test]
+    fn evicted_states_are_saved() {
+        let path = std::env::temp_dir().join(format!("xoxo-limit-{}.data", std::process::id()));
+        let mut qmap = QMap::<C4Board, usize>::new();
+        qmap.attach_store(KnowledgeStore::open::<C4Board>(&path).unwrap());
+        qmap.set_limit(Some(MemoryLimit::States(1)), Eviction::LeastRecentlyUsed);
+        let root = C4Board::default();
+        let mut child = root;
+        child.place_mark(3, child.current_player());
+        qmap.add_to_state_action_data(&child, &3, 1.0);
+        qmap.touch(&child, 1);
+        qmap.add_to_state_action_data(&root, &3, 1.0);
+        qmap.touch(&root, 0);
+        assert_eq!(qmap.enforce_limit(&root), 1);
+        assert!(qmap.get(&child).is_none());
+        // the search gets back to it
+        qmap.fault_in(&child);
+        assert_eq!(qmap.get(&child).unwrap()[&3], (1.0, 1.0));
+        drop(qmap);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(index_path(&path)).unwrap();
+        std::fs::remove_file(lock_path(&path)).unwrap();
+    }
+}
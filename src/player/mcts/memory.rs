@@ -0,0 +1,378 @@
+//! The memory files of `MctsAi`: the statistics of the `QMap`, kept across runs so that the AI learns from game to game.
+//!
+//! All memory files start with the same header, with all numbers little-endian:
+//! 1. The magic bytes `XOXOMCTS`
+//! 2. The format version, a u32
+//! 3. The name of the MDP, a u16 length and that many bytes of UTF-8
+//! 4. The discount factor and the win threshold of the MDP, two f64. The statistics mean something else if they change.
+//!
+//! Version 2 is the knowledge store, see `store`.
+//!
+//! Version 1 is a snapshot of the whole `QMap`: after the header, the length of the payload as a u64,
+//! then the payload, the `QMap` serialized with bitcode, and then a CRC-32 of everything before it, a u32.
+//! Files without the magic bytes are from before the header, when the file was just the serialized `QMap`,
+//! which only had the visits and returns of the states and their actions then.
+//! Both are still read, and turned into a knowledge store when they are loaded.
+//! The files from before the header can't be read for Connect Four and Ultimate Tic Tac Toe, since their boards
+//! have been stored differently since. An Ultimate Tic Tac Toe state didn't even tell which sub-board was the target.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::store::KnowledgeStore;
+use super::{ActionMap, Mdp, QMap, StateRecord};
+
+const MAGIC: &[u8; 8] = b"XOXOMCTS";
+
+/// The newest version of the format, the one that is written
+pub(super) const FORMAT_VERSION: u32 = 2;
+
+/// The version of the format of the snapshots
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The MDPs whose states were serialized in another way before the header, so that their files from then can't be read
+const CHANGED_BEFORE_HEADER: &[&str] = &["c4", "uttt"];
+
+/// Why a memory file could not be read or written
+#[derive(Debug)]
+pub enum MemoryError {
+    Io(std::io::Error),
+    /// The file was written by a newer version of the format
+    UnsupportedVersion(u32),
+    /// The file holds the statistics of another game
+    WrongGame { expected: String, found: String },
+    /// The file is for this game, but for another discount factor or win threshold
+    WrongParameters,
+    /// The file ends before the header or the payload does
+    Truncated,
+    /// The checksum does not match the contents, so the file was damaged
+    ChecksumMismatch,
+    /// The payload could not be decoded, or the file is not a memory file at all
+    Corrupt,
+    /// Another AI has the file open, in this process or another
+    Locked,
+    /// The file is from before the header, and the states of this game have been stored differently since
+    LegacyLayout,
+}
+
+impl Display for MemoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::UnsupportedVersion(v) => write!(f, "Format version {v} is newer than this program, which reads up to {FORMAT_VERSION}"),
+            Self::WrongGame { expected, found } => write!(f, "The file is for {found}, not {expected}"),
+            Self::WrongParameters => write!(f, "The file was made with another discount factor or win threshold"),
+            Self::Truncated => write!(f, "The file is cut short"),
+            Self::ChecksumMismatch => write!(f, "The checksum does not match, the file is damaged"),
+            Self::Corrupt => write!(f, "The contents can't be decoded"),
+            Self::Locked => write!(f, "The file is in use by another AI"),
+            Self::LegacyLayout => write!(f, "The file is from an old version that stored the states of this game differently"),
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for MemoryError {
+    fn from(e: std::io::Error) -> Self {
+        MemoryError::Io(e)
+    }
+}
+
+/// The header that all memory files start with: the magic bytes, the format version, and what the MDP is.
+/// The knowledge store adds its own fields after it.
+pub(super) fn encode_header<M: Mdp>(version: u32) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(64);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&version.to_le_bytes());
+    let name = M::NAME.as_bytes();
+    bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(name);
+    bytes.extend_from_slice(&M::DISCOUNT_FACTOR.to_le_bytes());
+    bytes.extend_from_slice(&M::WIN_THRESHOLD.to_le_bytes());
+    bytes
+}
+
+/// The fields of the header written by `encode_header`
+pub(super) struct Header {
+    pub version: u32,
+    name: String,
+    discount: f64,
+    win_threshold: f64,
+}
+
+impl Header {
+    /// Read the header from the start of a memory file. None if the file doesn't start with the magic bytes.
+    pub fn decode(reader: &mut Reader) -> Result<Option<Header>, MemoryError> {
+        if !reader.bytes.starts_with(MAGIC) {
+            return Ok(None);
+        }
+        reader.pos = MAGIC.len();
+        let version = u32::from_le_bytes(reader.take()?);
+        if version > FORMAT_VERSION {
+            return Err(MemoryError::UnsupportedVersion(version));
+        }
+        let name_len = u16::from_le_bytes(reader.take()?) as usize;
+        let name = String::from_utf8_lossy(reader.take_slice(name_len)?).into_owned();
+        let discount = f64::from_le_bytes(reader.take()?);
+        let win_threshold = f64::from_le_bytes(reader.take()?);
+        Ok(Some(Header {
+            version,
+            name,
+            discount,
+            win_threshold,
+        }))
+    }
+
+    /// Check that the file is for the MDP `M`
+    pub fn check<M: Mdp>(self) -> Result<(), MemoryError> {
+        if self.name != M::NAME {
+            return Err(MemoryError::WrongGame {
+                expected: M::NAME.to_string(),
+                found: self.name,
+            });
+        }
+        if self.discount.to_bits() != M::DISCOUNT_FACTOR.to_bits() || self.win_threshold.to_bits() != M::WIN_THRESHOLD.to_bits() {
+            return Err(MemoryError::WrongParameters);
+        }
+        Ok(())
+    }
+}
+
+/// The `QMap` in a snapshot: a file of version 1, or a bare `QMap` from before the header
+pub(super) fn decode_snapshot<M: Mdp>(bytes: &[u8]) -> Result<QMap<M::State, M::Action>, MemoryError> {
+    let mut reader = Reader::new(bytes);
+    let Some(header) = Header::decode(&mut reader)? else {
+        return decode_legacy::<M>(bytes);
+    };
+    if header.version != SNAPSHOT_VERSION {
+        return Err(MemoryError::Corrupt);
+    }
+    let payload_len = u64::from_le_bytes(reader.take()?) as usize;
+    let payload = reader.take_slice(payload_len)?;
+    let checksum_pos = reader.pos;
+    let checksum = u32::from_le_bytes(reader.take()?);
+    if crc32(&bytes[..checksum_pos]) != checksum {
+        return Err(MemoryError::ChecksumMismatch);
+    }
+    header.check::<M>()?;
+    bitcode::deserialize(payload).map_err(|_| MemoryError::Corrupt)
+}
+
+/// The `QMap` as it was serialized before the header
+#[derive(Deserialize)]
+struct LegacyQMap<S: Hash + Eq, A: Hash + Eq> {
+    state_action_value: HashMap<S, (f64, ActionMap<A>)>,
+}
+
+/// The `QMap` in a file from before the header
+fn decode_legacy<M: Mdp>(bytes: &[u8]) -> Result<QMap<M::State, M::Action>, MemoryError> {
+    if CHANGED_BEFORE_HEADER.contains(&M::NAME) {
+        return Err(MemoryError::LegacyLayout);
+    }
+    let legacy: LegacyQMap<M::State, M::Action> = bitcode::deserialize(bytes).map_err(|_| MemoryError::Corrupt)?;
+    let mut qmap = QMap::new();
+    for (state, (visits, actions)) in legacy.state_action_value {
+        let record = StateRecord {
+            visits,
+            actions,
+            proofs: None,
+            amaf: None,
+        };
+        qmap.insert_record(state, record);
+    }
+    Ok(qmap)
+}
+
+/// The number of states that `merge_memory_files` reads at a time
+const MERGE_CHUNK: usize = 1 << 16;
+
+/// Add the statistics in the memory files `from` to the one at `into`, as if all their searches had been made with it:
+/// the visits and returns of every state and action are summed, and what the solver proved is kept.
+/// This is how the memories of AIs that played at the same time, each with a file of its own, are put together.
+/// `into` is made if it isn't there, and `from` are left as they are. Returns the number of states read from `from`.
+///
+/// The files are locked while they are merged, so it fails with `MemoryError::Locked` if an AI is using one of them.
+/// Statistics that the files have in common, because they are copies of the same file, are counted once for every file.
+pub fn merge_memory_files<M: Mdp>(into: impl AsRef<Path>, from: &[impl AsRef<Path>]) -> Result<usize, MemoryError> {
+    // opening a store makes it if it isn't there, which would hide a mistyped path
+    for path in from {
+        std::fs::metadata(path.as_ref())?;
+    }
+    let mut qmap = QMap::<M::State, M::Action>::new();
+    qmap.attach_store(KnowledgeStore::open::<M>(into.as_ref())?);
+    let mut n_states = 0;
+    for path in from {
+        let mut source = KnowledgeStore::<M::State, M::Action>::open::<M>(path.as_ref())?;
+        let mut records = source.records()?.peekable();
+        while records.peek().is_some() {
+            let mut chunk = QMap::new();
+            for record in records.by_ref().take(MERGE_CHUNK) {
+                let (state, record) = record?;
+                chunk.insert_record(state, record);
+                n_states += 1;
+            }
+            qmap.merge(chunk);
+            qmap.save_to_store()?;
+            // start over with an empty map, to read the next chunk into
+            let store = qmap.store.take();
+            qmap = QMap::new();
+            qmap.store = store;
+        }
+    }
+    Ok(n_states)
+}
+
+/// Reads the fields of a file one by one
+pub(super) struct Reader<'a> {
+    bytes: &'a [u8],
+    pub pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub fn take_slice(&mut self, n: usize) -> Result<&'a [u8], MemoryError> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.bytes.len()).ok_or(MemoryError::Truncated)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn take<const N: usize>(&mut self) -> Result<[u8; N], MemoryError> {
+        Ok(self.take_slice(N)?.try_into().expect("The slice has length N"))
+    }
+}
+
+/// The file to write before renaming it to `path`, so that `path` is replaced in one step
+pub(super) fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// The CRC-32 of zlib and PNG, by the byte-wise table method
+pub(super) fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    !bytes
+        .iter()
+        .fold(!0u32, |crc, &b| TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game::tictactoe::{TTTAddr, TTTBoard};
+    use crate::player::mcts::store::{index_path, lock_path};
+
+    /// A snapshot file, as version 1 wrote them
+    pub(crate) fn encode_snapshot<M: Mdp>(qmap: &QMap<M::State, M::Action>) -> Vec<u8> {
+        let payload = bitcode::serialize(qmap).unwrap();
+        let mut bytes = encode_header::<M>(SNAPSHOT_VERSION);
+        bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        let checksum = crc32(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    fn small_qmap() -> QMap<TTTBoard, TTTAddr> {
+        let mut qmap = QMap::new();
+        qmap.add_to_state_action_data(&TTTBoard::default(), &TTTAddr(5), 1.0);
+        qmap
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn damage_is_detected() {
+        let bytes = encode_snapshot::<TTTBoard>(&small_qmap());
+        assert!(decode_snapshot::<TTTBoard>(&bytes).is_ok());
+        for i in [0, 8, 12, 20, bytes.len() / 2, bytes.len() - 1] {
+            let mut damaged = bytes.clone();
+            damaged[i] ^= 0x40;
+            assert!(decode_snapshot::<TTTBoard>(&damaged).is_err(), "byte {i}");
+        }
+        assert!(matches!(decode_snapshot::<TTTBoard>(&bytes[..bytes.len() - 1]), Err(MemoryError::Truncated)));
+    }
+
+    #[test]
+    fn merging_sums_the_statistics() {
+        let path = |name: &str| std::env::temp_dir().join(format!("xoxo-merge-{}-{name}.data", std::process::id()));
+        let (a, b, into) = (path("a"), path("b"), path("into"));
+        for (file, extra) in [(&a, false), (&b, true)] {
+            let mut qmap = small_qmap();
+            if extra {
+                qmap.add_to_state_action_data(&TTTBoard::default(), &TTTAddr(4), -1.0);
+            }
+            qmap.attach_store(KnowledgeStore::open::<TTTBoard>(file).unwrap());
+            qmap.save_to_store().unwrap();
+        }
+        assert_eq!(merge_memory_files::<TTTBoard>(&into, &[&a, &b]).unwrap(), 2);
+        let mut merged = KnowledgeStore::<TTTBoard, TTTAddr>::open::<TTTBoard>(&into).unwrap();
+        let record = merged.get(&TTTBoard::default()).unwrap().unwrap();
+        assert_eq!(record.visits, 3.0);
+        assert_eq!(record.actions[&TTTAddr(5)], (2.0, 2.0));
+        assert_eq!(record.actions[&TTTAddr(4)], (-1.0, 1.0));
+        drop(merged);
+        for file in [a, b, into] {
+            std::fs::remove_file(&file).unwrap();
+            std::fs::remove_file(index_path(&file)).unwrap();
+            std::fs::remove_file(lock_path(&file)).unwrap();
+        }
+    }
+
+    /// Written by the version before the header, after 40 steps from the start of tic tac toe
+    const TTT_BEFORE_HEADER: &[u8] = include_bytes!("../../../tests/data/ttt-before-header.mcts");
+
+    #[test]
+    fn reads_files_from_before_the_header() {
+        let loaded = decode_snapshot::<TTTBoard>(TTT_BEFORE_HEADER).unwrap();
+        assert_eq!(loaded.state_action_value.len(), 26);
+        let start = TTTBoard::default();
+        // that version didn't count the first visit of a state, nor the first return of an action
+        assert_eq!(loaded.n_state_visits(&start), 39.0);
+        let actions = loaded.get(&start).unwrap();
+        assert_eq!(actions.len(), 9);
+        assert_eq!(actions[&TTTAddr(5)].1, 9.0);
+        assert_eq!(actions.values().map(|(_, n)| n).sum::<f64>(), 31.0);
+        assert!(loaded.proven.is_empty() && loaded.amaf.is_empty());
+        assert_eq!(loaded.action_entries, loaded.count_action_entries());
+    }
+
+    #[test]
+    fn refuses_boards_that_changed_since_before_the_header() {
+        let c4 = include_bytes!("../../../tests/data/c4-before-header.mcts");
+        assert!(matches!(decode_snapshot::<crate::game::connect_four::C4Board>(c4), Err(MemoryError::LegacyLayout)));
+    }
+}
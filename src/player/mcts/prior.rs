@@ -0,0 +1,135 @@
+//! Prior policies for PUCT: how likely each action is to be the best, before it has been searched.
+//! UCB1 tries every action once before it looks at the returns, which wastes most of the search when the branching factor is large.
+//! PUCT, the selection rule of AlphaZero, tries the actions with a high prior first, and more often.
+//!
+//! A prior can come from anything that scores an action: a fixed rule, a heuristic, or a model learned from earlier games.
+//! To use a learned prior, implement `PriorPolicy` for the type that holds the model.
+
+use crate::core::{Board, HeuristicFn};
+
+use super::Mdp;
+
+/// The prior probabilities of the actions of a state
+pub trait PriorPolicy<M: Mdp>: Send + Sync {
+    /// How much to favour `a` in `s`. Any weight that is not negative: the weights are normalized over the actions of `s`.
+    fn prior(&self, s: &M::State, a: &M::Action) -> f64;
+
+    /// The priors of all `actions` in `s`, written into `priors` in the same order and normalized to sum to 1.
+    /// If all the weights are 0, the priors are uniform.
+    /// Override it if the priors of a state are cheaper to compute together.
+    fn priors(&self, s: &M::State, actions: &[M::Action], priors: &mut Vec<f64>) {
+        priors.clear();
+        priors.extend(actions.iter().map(|a| self.prior(s, a).max(0.0)));
+        normalize(priors);
+    }
+}
+
+/// Scale `weights` to sum to 1, or make them uniform if they sum to 0
+fn normalize(weights: &mut [f64]) {
+    let total: f64 = weights.iter().sum();
+    if total > 0.0 && total.is_finite() {
+        weights.iter_mut().for_each(|w| *w /= total);
+    } else {
+        let uniform = 1.0 / weights.len() as f64;
+        weights.iter_mut().for_each(|w| *w = uniform);
+    }
+}
+
+/// The same prior for every action. PUCT then explores by the visit counts alone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UniformPrior;
+
+impl<M: Mdp> PriorPolicy<M> for UniformPrior {
+    fn prior(&self, _s: &M::State, _a: &M::Action) -> f64 {
+        1.0
+    }
+}
+
+/// The softmax of the heuristic value of the board after each move, as seen by the player making it:
+/// the weight of a move is exp(heuristic / `temperature`).
+/// A low temperature puts nearly all the prior on the best looking moves, a high one spreads it out evenly.
+#[derive(Debug, Clone, Copy)]
+pub struct HeuristicPrior<B> {
+    pub heuristic: HeuristicFn<B>,
+    pub temperature: f64,
+}
+
+impl<B> HeuristicPrior<B>
+where
+    B: Board + Clone,
+{
+    /// The heuristic value of the board after `a`, divided by the temperature
+    fn logit(&self, s: &B, a: B::Coordinate) -> f64 {
+        let me = s.current_player();
+        let mut b = s.clone();
+        b.place_mark(a, me);
+        (self.heuristic)(me, &b) / self.temperature
+    }
+}
+
+impl<B> PriorPolicy<B> for HeuristicPrior<B>
+where
+    B: Board + Clone + Mdp<State = B, Action = <B as Board>::Coordinate>,
+{
+    fn prior(&self, s: &B, a: &B::Coordinate) -> f64 {
+        self.logit(s, *a).exp()
+    }
+
+    /// The softmax, shifted by the largest logit so that the weights don't overflow
+    fn priors(&self, s: &B, actions: &[B::Coordinate], priors: &mut Vec<f64>) {
+        priors.clear();
+        priors.extend(actions.iter().map(|&a| self.logit(s, a)));
+        let max = priors.iter().copied().fold(-f64::INFINITY, f64::max);
+        for p in priors.iter_mut() {
+            *p = if max == f64::INFINITY {
+                // some heuristics score a won game as infinite, then the winning moves share all of the prior
+                if *p == max { 1.0 } else { 0.0 }
+            } else {
+                (*p - max).exp()
+            };
+        }
+        normalize(priors);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::game::tictactoe::{TTTAddr, TTTBoard};
+    use crate::player::ttt_heuristic;
+
+    #[test]
+    fn priors_sum_to_one() {
+        let b = TTTBoard::from_str("oo xx    ").unwrap();
+        let moves = b.valid_moves();
+        let mut priors = vec![];
+        PriorPolicy::<TTTBoard>::priors(&UniformPrior, &b, &moves, &mut priors);
+        assert!(priors.iter().all(|&p| p == 0.2));
+        let prior = HeuristicPrior {
+            heuristic: ttt_heuristic,
+            temperature: 10.0,
+        };
+        prior.priors(&b, &moves, &mut priors);
+        assert!((priors.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        // O wins at 3, and no other move changes the heuristic
+        let win = moves.iter().position(|&m| m == TTTAddr(3)).unwrap();
+        assert!(priors.iter().enumerate().all(|(i, &p)| i == win || p < priors[win] / 1000.0));
+    }
+
+    #[test]
+    fn infinite_heuristics() {
+        // like uttt_heuristic, score a won game as infinite, which must not turn the priors into NaN
+        let prior = HeuristicPrior {
+            heuristic: |me, b: &TTTBoard| if b.winner() == Some(me) { f64::INFINITY } else { 0.0 },
+            temperature: 1.0,
+        };
+        let b = TTTBoard::from_str("oo xx    ").unwrap();
+        let moves = b.valid_moves();
+        let mut priors = vec![];
+        prior.priors(&b, &moves, &mut priors);
+        let expected: Vec<f64> = moves.iter().map(|&m| if m == TTTAddr(3) { 1.0 } else { 0.0 }).collect();
+        assert_eq!(priors, expected);
+    }
+}
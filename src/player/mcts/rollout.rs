@@ -0,0 +1,214 @@
+//! Rollout policies: how MCTS plays out a game from a new node to estimate its value.
+//! Uniform random moves are cheap, so many can be played. The other policies play better moves at a higher cost per move,
+//! which usually pays off because random play is a poor model of an opponent.
+//!
+//! A rollout is a loop, not a recursion, so long games don't grow the stack.
+
+use rand::prelude::SliceRandom;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use crate::core::{Board, GameStatus, HeuristicFn};
+
+use super::Mdp;
+
+/// How to choose the moves in a rollout, and when to stop it early
+pub trait RolloutPolicy<M: Mdp>: Send + Sync {
+    /// The action to take in `s`, one of `actions`, which is not empty
+    fn choose<'a>(&self, s: &M::State, actions: &'a [M::Action], rng: &mut StdRng) -> &'a M::Action;
+
+    /// The value of `s` as seen by its actor, if the rollout should stop there instead of playing on.
+    /// `ply` is the number of actions taken in the rollout so far.
+    fn cutoff(&self, _s: &M::State, _ply: usize) -> Option<f64> {
+        None
+    }
+
+    /// Play from `s` until the end of the game or a cutoff, and return the 'return', as seen by the actor in `s`.
+    /// The return is the sum of all future rewards, discounted by the discount factor.
+    /// The actions taken are pushed to `played`, if given. RAVE needs them.
+    fn rollout(&self, s: M::State, rng: &mut StdRng, mut played: Option<&mut Vec<M::Action>>) -> f64 {
+        let mut state = s;
+        let mut actions = Vec::new();
+        let mut g_return = 0.0;
+        let mut discount = 1.0;
+        let mut ply = 0;
+        while !M::is_terminal(&state) {
+            if let Some(value) = self.cutoff(&state, ply) {
+                g_return += discount * value;
+                break;
+            }
+            M::allowed_actions_into(&state, &mut actions);
+            assert!(!actions.is_empty(), "A non-terminal state must have at least one action allowed");
+            let action = self.choose(&state, &actions, rng);
+            if let Some(played) = played.as_mut() {
+                played.push(action.clone());
+            }
+            let (new_state, reward) = M::act(state, action);
+            g_return += discount * reward;
+            discount *= M::DISCOUNT_FACTOR;
+            state = new_state;
+            ply += 1;
+        }
+        g_return
+    }
+}
+
+/// Uniformly random moves until the end of the game
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UniformRollout;
+
+impl<M: Mdp> RolloutPolicy<M> for UniformRollout {
+    fn choose<'a>(&self, _s: &M::State, actions: &'a [M::Action], rng: &mut StdRng) -> &'a M::Action {
+        actions.choose(rng).expect("There is at least one action")
+    }
+}
+
+/// Win right away if possible, otherwise block a square where the opponent would win right away, otherwise a random move
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WinBlockRollout;
+
+impl<B> RolloutPolicy<B> for WinBlockRollout
+where
+    B: Board + Clone + Mdp<State = B, Action = <B as Board>::Coordinate>,
+{
+    fn choose<'a>(&self, s: &B, actions: &'a [B::Coordinate], rng: &mut StdRng) -> &'a B::Coordinate {
+        let me = s.current_player();
+        let wins_for = |a: B::Coordinate, mark| {
+            let mut b = s.clone();
+            b.place_mark(a, mark);
+            b.game_status() == GameStatus::Won(mark)
+        };
+        actions
+            .iter()
+            .find(|&&a| wins_for(a, me))
+            .or_else(|| actions.iter().find(|&&a| wins_for(a, me.other())))
+            .unwrap_or_else(|| actions.choose(rng).expect("There is at least one action"))
+    }
+}
+
+/// With probability `epsilon` a random move, otherwise the move after which the heuristic likes the board best
+#[derive(Debug, Clone, Copy)]
+pub struct EpsilonGreedyRollout<B> {
+    pub heuristic: HeuristicFn<B>,
+    pub epsilon: f64,
+}
+
+impl<B> RolloutPolicy<B> for EpsilonGreedyRollout<B>
+where
+    B: Board + Clone + Mdp<State = B, Action = <B as Board>::Coordinate>,
+{
+    fn choose<'a>(&self, s: &B, actions: &'a [B::Coordinate], rng: &mut StdRng) -> &'a B::Coordinate {
+        if rng.gen::<f64>() < self.epsilon {
+            return actions.choose(rng).expect("There is at least one action");
+        }
+        let me = s.current_player();
+        let scores: Vec<f64> = actions
+            .iter()
+            .map(|&a| {
+                let mut b = s.clone();
+                b.place_mark(a, me);
+                (self.heuristic)(me, &b)
+            })
+            .collect();
+        let best = scores.iter().copied().fold(-f64::INFINITY, f64::max);
+        // ties at random, or the heuristics that only score finished games would always pick the first move
+        let ties: Vec<_> = actions.iter().zip(&scores).filter(|(_, &v)| v == best).map(|(a, _)| a).collect();
+        ties.choose(rng).expect("The best move ties with itself")
+    }
+}
+
+/// Random moves for `depth` plies, then the heuristic scores the board instead of playing on.
+/// The score is squashed into the range of the returns by tanh(heuristic / `scale`),
+/// so `scale` is about the heuristic value of a position that is clearly won.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthLimitedRollout<B> {
+    pub heuristic: HeuristicFn<B>,
+    pub depth: usize,
+    pub scale: f64,
+}
+
+impl<B> RolloutPolicy<B> for DepthLimitedRollout<B>
+where
+    B: Board + Clone + Mdp<State = B, Action = <B as Board>::Coordinate>,
+{
+    fn choose<'a>(&self, _s: &B, actions: &'a [B::Coordinate], rng: &mut StdRng) -> &'a B::Coordinate {
+        actions.choose(rng).expect("There is at least one action")
+    }
+
+    fn cutoff(&self, s: &B, ply: usize) -> Option<f64> {
+        (ply >= self.depth).then(|| ((self.heuristic)(s.current_player(), s) / self.scale).tanh())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::game::connect_four::C4Board;
+    use crate::game::tictactoe::{TTTAddr, TTTBoard};
+    use crate::player::{c4_heuristic, ttt_heuristic};
+
+    fn choice<P: RolloutPolicy<TTTBoard>>(policy: &P, b: &TTTBoard) -> TTTAddr {
+        let mut rng = StdRng::seed_from_u64(1);
+        *policy.choose(b, &b.valid_moves(), &mut rng)
+    }
+
+    #[test]
+    fn win_block_wins_then_blocks() {
+        // O can win at 3, and must block X at 6 otherwise
+        assert_eq!(choice(&WinBlockRollout, &TTTBoard::from_str("oo xx    ").unwrap()), TTTAddr(3));
+        assert_eq!(choice(&WinBlockRollout, &TTTBoard::from_str("o  xx o  ").unwrap()), TTTAddr(6));
+    }
+
+    #[test]
+    fn greedy_follows_the_heuristic() {
+        let greedy = EpsilonGreedyRollout {
+            heuristic: ttt_heuristic,
+            epsilon: 0.0,
+        };
+        assert_eq!(choice(&greedy, &TTTBoard::from_str("oo xx    ").unwrap()), TTTAddr(3));
+    }
+
+    #[test]
+    fn rollouts_end_the_game() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let greedy = EpsilonGreedyRollout {
+            heuristic: c4_heuristic,
+            epsilon: 0.1,
+        };
+        for policy in [&UniformRollout as &dyn RolloutPolicy<C4Board>, &WinBlockRollout, &greedy] {
+            let mut played = vec![];
+            let g_return = policy.rollout(C4Board::default(), &mut rng, Some(&mut played));
+            // the first player wins, loses or draws, and the discount shrinks the return a little with every move
+            assert!(g_return.abs() <= 1.0);
+            let mut b = C4Board::default();
+            for &m in &played {
+                b.try_place_mark(m, b.current_player()).unwrap();
+            }
+            assert!(b.game_is_over());
+        }
+    }
+
+    #[test]
+    fn depth_limited_scores_the_cutoff() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let policy = DepthLimitedRollout {
+            heuristic: c4_heuristic,
+            depth: 4,
+            scale: 10.0,
+        };
+        let mut played = vec![];
+        let g_return = policy.rollout(C4Board::default(), &mut rng, Some(&mut played));
+        assert_eq!(played.len(), 4);
+        let mut b = C4Board::default();
+        for &m in &played {
+            b.place_mark(m, b.current_player());
+        }
+        // four plies on, it is the first player's turn again, and the discount has been applied four times
+        let expected = (c4_heuristic(b.current_player(), &b) / 10.0).tanh() * C4Board::DISCOUNT_FACTOR.powi(4);
+        assert!((g_return - expected).abs() < 1e-12, "{g_return} vs {expected}");
+    }
+}
@@ -0,0 +1,811 @@
+//! The knowledge store: the memory file of `MctsAi`, made so that loading and saving don't grow with the games played.
+//! Only the states that a search visits are read, and only the states that changed are written.
+//!
+//! It is two files:
+//! - The log, at the path of the memory. Every time a state is saved, a record of it is appended,
+//!   so the log also holds the older records of states that have been saved again.
+//!   When it is more than half old records, it is compacted: the records that are still used are copied to a new log.
+//! - The index, at the same path with `.idx` added. A hash table on disk from the states to their newest record in the log.
+//!   A lookup reads one or a few buckets and the record, so it doesn't matter how large the files are.
+//!
+//! The log starts with the header of `memory`, version 2, followed by a random id of the log, a u64, and a CRC-32 of the header.
+//! A record is its length, a u32, a CRC-32 of the rest, the length of the state, a u32, and then the state and its statistics,
+//! both serialized with bitcode.
+//!
+//! The index starts with a header of its own, see `IndexHeader`, and then the buckets,
+//! each a u64 hash of the state and the u64 offset of its record in the log, with 0 for an empty bucket.
+//! Collisions are resolved by linear probing, and by comparing the states themselves.
+//! The states are compared serialized, so states that are equal must also serialize the same.
+//!
+//! Crash safety: records are appended to the log and synced to disk before the index points to them.
+//! The index header tells how much of the log is indexed, and any records after that are indexed on the next open.
+//! Some of their buckets may be written already, so the states and bytes in the header are counted again from the buckets first.
+//! A record that was cut short by a crash is cut off the log. If the index is missing, damaged or belongs to another log,
+//! it is built again from the log.
+//!
+//! A store is locked while it is open, so that two processes never write the same memory at once.
+//! The lock is on a third file, at the same path with `.lock` added, because the log and the index are replaced by renaming.
+//! It is left behind when the store is closed: removing it could let two processes lock different files.
+//! `read_all` reads a memory file without opening a store, for looking at it: it takes no lock and writes nothing.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::hash::Hash;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::memory::{crc32, decode_snapshot, encode_header, temp_path, Header, MemoryError, Reader, FORMAT_VERSION};
+use super::{Mdp, StateRecord};
+
+const INDEX_MAGIC: &[u8; 8] = b"XOXOIDX1";
+/// The buckets start here, after the index header
+const INDEX_HEADER_LEN: u64 = 64;
+const BUCKET_LEN: u64 = 16;
+/// The number of buckets in a new index
+const MIN_CAPACITY: u64 = 1 << 10;
+/// The log is not compacted while the old records take up less than this
+const COMPACT_SLACK: u64 = 1 << 20;
+
+/// The fields at the start of the index file
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct IndexHeader {
+    /// The id of the log that the index is for
+    log_id: u64,
+    /// The records before this offset in the log are in the index
+    indexed_len: u64,
+    /// The number of states in the index
+    n_states: u64,
+    /// The total length of the newest records of the states
+    live_bytes: u64,
+    /// The number of buckets, a power of two
+    capacity: u64,
+}
+
+impl IndexHeader {
+    fn encode(&self) -> [u8; INDEX_HEADER_LEN as usize] {
+        let mut bytes = [0; INDEX_HEADER_LEN as usize];
+        bytes[..8].copy_from_slice(INDEX_MAGIC);
+        let fields = [self.log_id, self.indexed_len, self.n_states, self.live_bytes, self.capacity];
+        for (i, f) in fields.iter().enumerate() {
+            bytes[8 + 8 * i..16 + 8 * i].copy_from_slice(&f.to_le_bytes());
+        }
+        let checksum = crc32(&bytes[..48]);
+        bytes[48..52].copy_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// None if the bytes are not a valid index header
+    fn decode(bytes: &[u8]) -> Option<IndexHeader> {
+        if bytes.len() < INDEX_HEADER_LEN as usize || !bytes.starts_with(INDEX_MAGIC) {
+            return None;
+        }
+        let checksum = u32::from_le_bytes(bytes[48..52].try_into().ok()?);
+        if crc32(&bytes[..48]) != checksum {
+            return None;
+        }
+        let field = |i: usize| u64::from_le_bytes(bytes[8 + 8 * i..16 + 8 * i].try_into().expect("8 bytes"));
+        let header = IndexHeader {
+            log_id: field(0),
+            indexed_len: field(1),
+            n_states: field(2),
+            live_bytes: field(3),
+            capacity: field(4),
+        };
+        header.capacity.is_power_of_two().then_some(header)
+    }
+}
+
+/// A record in the log, without its statistics decoded
+struct RawRecord {
+    /// The length of the whole record, with its length and checksum
+    len: u64,
+    state: Vec<u8>,
+    stats: Vec<u8>,
+}
+
+/// The statistics of the states of an MDP, on disk. See the module docs.
+pub(crate) struct KnowledgeStore<S, A> {
+    /// Held for as long as the store is open
+    _lock: File,
+    log_path: PathBuf,
+    log: File,
+    index: File,
+    header: IndexHeader,
+    log_len: u64,
+    /// Where the records start, after the header of the log
+    records_start: u64,
+    _types: PhantomData<fn() -> (S, A)>,
+}
+
+impl<S, A> KnowledgeStore<S, A>
+where
+    S: Hash + Eq + Clone + Serialize + DeserializeOwned,
+    A: Hash + Eq + Clone + Serialize + DeserializeOwned,
+{
+    /// Open the store at `path`, or make an empty one if there is no file there.
+    /// A snapshot from an older version of the format is turned into a store.
+    /// Fails with `MemoryError::Locked` if another store has it open, in this process or another.
+    pub fn open<M: Mdp<State = S, Action = A>>(path: &Path) -> Result<Self, MemoryError> {
+        let lock = lock(path)?;
+        let mut head = vec![];
+        match File::open(path) {
+            Ok(f) => {
+                f.take(4096).read_to_end(&mut head)?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::create::<M>(path, lock, std::iter::empty()),
+            Err(e) => return Err(e.into()),
+        }
+        match decode_log_header::<M>(&head)? {
+            Some((log_id, records_start)) => Self::open_log(path, lock, log_id, records_start),
+            // a snapshot of the whole `QMap`, from version 1 or from before the header
+            None => {
+                let qmap = decode_snapshot::<M>(&std::fs::read(path)?)?;
+                Self::create::<M>(path, lock, qmap.into_records())
+            }
+        }
+    }
+
+    /// Make a new store at `path` with `records` in it. The files are written next to it first and then renamed,
+    /// so a file that is already at `path` is only replaced once the new store is complete.
+    fn create<M: Mdp<State = S, Action = A>>(
+        path: &Path,
+        lock: File,
+        records: impl Iterator<Item = (S, StateRecord<A>)>,
+    ) -> Result<Self, MemoryError> {
+        let log_id: u64 = rand::random();
+        let mut log_header = encode_header::<M>(FORMAT_VERSION);
+        log_header.extend_from_slice(&log_id.to_le_bytes());
+        let checksum = crc32(&log_header);
+        log_header.extend_from_slice(&checksum.to_le_bytes());
+
+        let tmp = temp_path(path);
+        std::fs::write(&tmp, &log_header)?;
+        let tmp_index = index_path(&tmp);
+        let _ = std::fs::remove_file(&tmp_index);
+        let mut store = Self::open_log(&tmp, lock, log_id, log_header.len() as u64)?;
+        store.put_all(records)?;
+        store.log.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        std::fs::rename(&tmp_index, index_path(path))?;
+        store.log_path = path.to_path_buf();
+        Ok(store)
+    }
+
+    /// Open the log at `path`, whose header has been checked, and its index.
+    /// The index is built again if it doesn't fit the log, and records that are not indexed yet are added to it.
+    fn open_log(path: &Path, lock: File, log_id: u64, records_start: u64) -> Result<Self, MemoryError> {
+        let log = OpenOptions::new().read(true).write(true).open(path)?;
+        let log_len = log.metadata()?.len();
+        let index = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(index_path(path))?;
+        let mut store = KnowledgeStore {
+            _lock: lock,
+            log_path: path.to_path_buf(),
+            log,
+            index,
+            header: IndexHeader {
+                log_id,
+                indexed_len: records_start,
+                n_states: 0,
+                live_bytes: 0,
+                capacity: MIN_CAPACITY,
+            },
+            log_len,
+            records_start,
+            _types: PhantomData,
+        };
+        let mut head = [0; INDEX_HEADER_LEN as usize];
+        let header = match store.index.read_exact(&mut head) {
+            Ok(()) => IndexHeader::decode(&head),
+            Err(_) => None,
+        };
+        match header {
+            Some(h) if h.log_id == log_id && h.indexed_len <= log_len && h.indexed_len >= records_start => {
+                store.header = h;
+                if h.indexed_len < log_len {
+                    store.recount()?;
+                }
+            }
+            _ => {
+                if store.index.metadata()?.len() > 0 {
+                    warn!("The index of {} doesn't fit the log, building it again", path.display());
+                }
+                store.reset_index(MIN_CAPACITY)?;
+            }
+        }
+        store.index_tail()?;
+        Ok(store)
+    }
+
+    /// The statistics of `state`, if they are in the store
+    pub fn get(&mut self, state: &S) -> Result<Option<StateRecord<A>>, MemoryError> {
+        let state_bytes = bitcode::serialize(state).map_err(|_| MemoryError::Corrupt)?;
+        let (_, found) = self.probe(hash(&state_bytes), &state_bytes)?;
+        let Some((offset, _)) = found else {
+            return Ok(None);
+        };
+        let record = self.read_record(offset)?.ok_or(MemoryError::ChecksumMismatch)?;
+        bitcode::deserialize(&record.stats).map(Some).map_err(|_| MemoryError::Corrupt)
+    }
+
+    /// All the states in the store and their statistics, in the order of the log
+    pub fn records(&mut self) -> Result<impl Iterator<Item = Result<(S, StateRecord<A>), MemoryError>> + '_, MemoryError> {
+        let mut offsets: Vec<u64> = self.read_buckets()?.into_iter().map(|(_, offset)| offset).collect();
+        offsets.sort_unstable();
+        Ok(offsets.into_iter().map(|offset| {
+            let record = self.read_record(offset)?.ok_or(MemoryError::ChecksumMismatch)?;
+            let state = bitcode::deserialize(&record.state).map_err(|_| MemoryError::Corrupt)?;
+            let stats = bitcode::deserialize(&record.stats).map_err(|_| MemoryError::Corrupt)?;
+            Ok((state, stats))
+        }))
+    }
+
+    /// Save the statistics of the states, replacing what was saved for them before
+    pub fn put_all(&mut self, records: impl Iterator<Item = (S, StateRecord<A>)>) -> Result<(), MemoryError> {
+        // first the records are appended and synced, then the index is pointed at them
+        let start = self.log_len;
+        self.log.seek(SeekFrom::Start(start))?;
+        let mut writer = BufWriter::new(&self.log);
+        let mut len = start;
+        for (state, stats) in records {
+            let record = encode_record(&state, &stats)?;
+            writer.write_all(&record)?;
+            len += record.len() as u64;
+        }
+        writer.flush()?;
+        drop(writer);
+        self.log.sync_data()?;
+        self.log_len = len;
+        self.index_tail()?;
+        if self.log_len - self.records_start > 2 * self.header.live_bytes + COMPACT_SLACK {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Add the records in the log after `indexed_len` to the index.
+    /// If the log ends in a record that is cut short or damaged, as after a crash while appending, that record is cut off.
+    fn index_tail(&mut self) -> Result<(), MemoryError> {
+        let mut offset = self.header.indexed_len;
+        while offset < self.log_len {
+            let Some(record) = self.read_record(offset)? else {
+                warn!("Cutting off a damaged record at the end of {}", self.log_path.display());
+                self.log.set_len(offset)?;
+                self.log_len = offset;
+                break;
+            };
+            self.index_record(offset, &record)?;
+            offset += record.len;
+        }
+        self.header.indexed_len = offset;
+        self.write_index_header()?;
+        self.index.sync_data()?;
+        Ok(())
+    }
+
+    /// Point the index at the record of a state at `offset`
+    fn index_record(&mut self, offset: u64, record: &RawRecord) -> Result<(), MemoryError> {
+        if (self.header.n_states + 1) * 2 > self.header.capacity {
+            self.grow()?;
+        }
+        let key = hash(&record.state);
+        let (bucket, old) = self.probe(key, &record.state)?;
+        match old {
+            Some((_, old_len)) => self.header.live_bytes -= old_len,
+            None => self.header.n_states += 1,
+        }
+        self.header.live_bytes += record.len;
+        self.write_bucket(bucket, key, offset)
+    }
+
+    /// Count the states in the index and the length of their records again, from the buckets.
+    /// A crash while records were indexed can leave buckets written for them without the header that counts them,
+    /// and then indexing them again would find them there already and not count them.
+    /// If a bucket points at a record that isn't in the log, the index is built again instead.
+    fn recount(&mut self) -> Result<(), MemoryError> {
+        let entries = self.read_buckets()?;
+        let mut live_bytes = 0;
+        for &(_, offset) in entries.iter() {
+            match self.read_record(offset)? {
+                Some(record) => live_bytes += record.len,
+                None => {
+                    warn!("The index of {} points at a damaged record, building it again", self.log_path.display());
+                    return self.reset_index(MIN_CAPACITY);
+                }
+            }
+        }
+        self.header.n_states = entries.len() as u64;
+        self.header.live_bytes = live_bytes;
+        Ok(())
+    }
+
+    /// The bucket of the state with these bytes and hash, and the offset and length of its record if it is in the index.
+    /// If it isn't, the bucket is the empty one where it goes.
+    /// Fails with `MemoryError::Corrupt` if the index is full, which it never is unless its counts are wrong.
+    fn probe(&mut self, key: u64, state: &[u8]) -> Result<(u64, Option<(u64, u64)>), MemoryError> {
+        let mask = self.header.capacity - 1;
+        let mut bucket = key & mask;
+        for _ in 0..self.header.capacity {
+            let (k, offset) = self.read_bucket(bucket)?;
+            if offset == 0 {
+                return Ok((bucket, None));
+            }
+            if k == key {
+                if let Some(record) = self.read_record(offset)? {
+                    if record.state == state {
+                        return Ok((bucket, Some((offset, record.len))));
+                    }
+                }
+            }
+            bucket = (bucket + 1) & mask;
+        }
+        Err(MemoryError::Corrupt)
+    }
+
+    /// The record at `offset`, or None if it is cut short or its checksum doesn't match
+    fn read_record(&mut self, offset: u64) -> Result<Option<RawRecord>, MemoryError> {
+        if offset + 8 > self.log_len {
+            return Ok(None);
+        }
+        self.log.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![0; 8];
+        self.log.read_exact(&mut bytes)?;
+        let body_len = u32::from_le_bytes(bytes[..4].try_into().expect("4 bytes")) as u64;
+        if offset + 8 + body_len > self.log_len {
+            return Ok(None);
+        }
+        bytes.resize(8 + body_len as usize, 0);
+        self.log.read_exact(&mut bytes[8..])?;
+        Ok(decode_record(&bytes))
+    }
+
+    fn read_bucket(&mut self, bucket: u64) -> Result<(u64, u64), MemoryError> {
+        self.index.seek(SeekFrom::Start(INDEX_HEADER_LEN + bucket * BUCKET_LEN))?;
+        let mut bytes = [0; BUCKET_LEN as usize];
+        self.index.read_exact(&mut bytes)?;
+        Ok(decode_bucket(&bytes))
+    }
+
+    fn write_bucket(&mut self, bucket: u64, key: u64, offset: u64) -> Result<(), MemoryError> {
+        self.index.seek(SeekFrom::Start(INDEX_HEADER_LEN + bucket * BUCKET_LEN))?;
+        self.index.write_all(&encode_bucket(key, offset))?;
+        Ok(())
+    }
+
+    /// The hashes and offsets in all the buckets that are not empty
+    fn read_buckets(&mut self) -> Result<Vec<(u64, u64)>, MemoryError> {
+        let mut bytes = vec![0; (self.header.capacity * BUCKET_LEN) as usize];
+        self.index.seek(SeekFrom::Start(INDEX_HEADER_LEN))?;
+        self.index.read_exact(&mut bytes)?;
+        Ok(bytes
+            .chunks_exact(BUCKET_LEN as usize)
+            .map(decode_bucket)
+            .filter(|&(_, offset)| offset != 0)
+            .collect())
+    }
+
+    fn write_index_header(&mut self) -> Result<(), MemoryError> {
+        self.index.seek(SeekFrom::Start(0))?;
+        self.index.write_all(&self.header.encode())?;
+        Ok(())
+    }
+
+    /// Empty the index, with room for `capacity` buckets. Nothing in the log is indexed after this.
+    fn reset_index(&mut self, capacity: u64) -> Result<(), MemoryError> {
+        self.header = IndexHeader {
+            indexed_len: self.records_start,
+            n_states: 0,
+            live_bytes: 0,
+            capacity,
+            ..self.header
+        };
+        self.index.set_len(0)?;
+        self.index.set_len(INDEX_HEADER_LEN + capacity * BUCKET_LEN)?;
+        self.write_index_header()
+    }
+
+    /// Double the number of buckets. The index is written to a new file and renamed, so a crash leaves the old one.
+    fn grow(&mut self) -> Result<(), MemoryError> {
+        let entries = self.read_buckets()?;
+        let header = IndexHeader {
+            capacity: self.header.capacity * 2,
+            ..self.header
+        };
+        self.write_index(header, &entries)
+    }
+
+    /// Replace the index file with one that has `header` and the buckets `entries`
+    fn write_index(&mut self, header: IndexHeader, entries: &[(u64, u64)]) -> Result<(), MemoryError> {
+        let mask = header.capacity - 1;
+        let mut buckets = vec![0u8; (header.capacity * BUCKET_LEN) as usize];
+        for &(key, offset) in entries {
+            let mut bucket = key & mask;
+            while decode_bucket(&buckets[(bucket * BUCKET_LEN) as usize..][..BUCKET_LEN as usize]).1 != 0 {
+                bucket = (bucket + 1) & mask;
+            }
+            buckets[(bucket * BUCKET_LEN) as usize..][..BUCKET_LEN as usize].copy_from_slice(&encode_bucket(key, offset));
+        }
+        let path = index_path(&self.log_path);
+        let tmp = temp_path(&path);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&tmp)?;
+        file.write_all(&header.encode())?;
+        file.write_all(&buckets)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &path)?;
+        self.index = file;
+        self.header = header;
+        Ok(())
+    }
+
+    /// Copy the newest records to a new log, and drop the old ones
+    fn compact(&mut self) -> Result<(), MemoryError> {
+        let entries = self.read_buckets()?;
+        let mut head = vec![0; self.records_start as usize];
+        self.log.seek(SeekFrom::Start(0))?;
+        self.log.read_exact(&mut head)?;
+        // a new id, so that the old index is never used with the new log
+        let log_id: u64 = rand::random();
+        let id_pos = head.len() - 12;
+        head[id_pos..id_pos + 8].copy_from_slice(&log_id.to_le_bytes());
+        let checksum = crc32(&head[..id_pos + 8]);
+        head[id_pos + 8..].copy_from_slice(&checksum.to_le_bytes());
+
+        let tmp = temp_path(&self.log_path);
+        let mut new_log = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&tmp)?;
+        let mut writer = BufWriter::new(&mut new_log);
+        writer.write_all(&head)?;
+        let mut new_entries = Vec::with_capacity(entries.len());
+        let mut offset = self.records_start;
+        for (key, old_offset) in entries {
+            let record = self.read_record(old_offset)?.ok_or(MemoryError::ChecksumMismatch)?;
+            writer.write_all(&encode_raw(&record.state, &record.stats))?;
+            new_entries.push((key, offset));
+            offset += record.len;
+        }
+        writer.flush()?;
+        drop(writer);
+        new_log.sync_all()?;
+        std::fs::rename(&tmp, &self.log_path)?;
+        self.log = new_log;
+        self.log_len = offset;
+        let header = IndexHeader {
+            log_id,
+            indexed_len: offset,
+            live_bytes: offset - self.records_start,
+            ..self.header
+        };
+        self.write_index(header, &new_entries)
+    }
+}
+
+/// All the states in the memory file at `path` and their statistics, read without changing any file.
+/// Unlike `KnowledgeStore::open`, it doesn't lock the file, a snapshot of an older version is decoded in memory
+/// instead of being turned into a store, and the index is not used: the log is read from start to end.
+/// A damaged record is reported and left in the file, and nothing after it is read.
+pub(crate) fn read_all<M: Mdp>(path: &Path) -> Result<HashMap<M::State, StateRecord<M::Action>>, MemoryError> {
+    let bytes = std::fs::read(path)?;
+    let Some((_, records_start)) = decode_log_header::<M>(&bytes)? else {
+        return Ok(decode_snapshot::<M>(&bytes)?.into_records().collect());
+    };
+    // the newest record of each state, by the serialized state, as the index finds them
+    let mut newest = HashMap::new();
+    let mut offset = records_start as usize;
+    while offset < bytes.len() {
+        let Some(record) = decode_record(&bytes[offset..]) else {
+            warn!("{} has a damaged record at byte {offset}, the records from there on are not read", path.display());
+            break;
+        };
+        offset += record.len as usize;
+        newest.insert(record.state, record.stats);
+    }
+    newest
+        .into_iter()
+        .map(|(state, stats)| {
+            let state = bitcode::deserialize(&state).map_err(|_| MemoryError::Corrupt)?;
+            let stats = bitcode::deserialize(&stats).map_err(|_| MemoryError::Corrupt)?;
+            Ok((state, stats))
+        })
+        .collect()
+}
+
+/// The id of the log and where its records start, if `head`, the start of a file, is the header of a log.
+/// None if the file is a snapshot of the whole `QMap`, from version 1 or from before the header.
+fn decode_log_header<M: Mdp>(head: &[u8]) -> Result<Option<(u64, u64)>, MemoryError> {
+    let mut reader = Reader::new(head);
+    match Header::decode(&mut reader) {
+        Ok(Some(header)) if header.version == FORMAT_VERSION => {
+            let log_id = u64::from_le_bytes(reader.take()?);
+            let checksum_pos = reader.pos;
+            let checksum = u32::from_le_bytes(reader.take()?);
+            if crc32(&head[..checksum_pos]) != checksum {
+                return Err(MemoryError::ChecksumMismatch);
+            }
+            header.check::<M>()?;
+            Ok(Some((log_id, reader.pos as u64)))
+        }
+        Err(e) => Err(e),
+        _ => Ok(None),
+    }
+}
+
+/// The record at the start of `bytes`, or None if it is cut short or its checksum doesn't match
+fn decode_record(bytes: &[u8]) -> Option<RawRecord> {
+    let head = bytes.get(..8)?;
+    let body_len = u32::from_le_bytes(head[..4].try_into().expect("4 bytes")) as usize;
+    let checksum = u32::from_le_bytes(head[4..].try_into().expect("4 bytes"));
+    let body = bytes.get(8..8 + body_len)?;
+    if body_len < 4 || crc32(body) != checksum {
+        return None;
+    }
+    let state_len = u32::from_le_bytes(body[..4].try_into().expect("4 bytes")) as usize;
+    let state = body.get(4..4 + state_len)?;
+    Some(RawRecord {
+        len: 8 + body_len as u64,
+        state: state.to_vec(),
+        stats: body[4 + state_len..].to_vec(),
+    })
+}
+
+/// Where the index of the log at `path` is
+pub(crate) fn index_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".idx");
+    path.with_file_name(name)
+}
+
+/// Where the lock of the store at `path` is
+pub(crate) fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+/// Lock the store at `path`, without waiting for another process to let go of it
+fn lock(path: &Path) -> Result<File, MemoryError> {
+    let file = OpenOptions::new().write(true).create(true).truncate(false).open(lock_path(path))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(MemoryError::Locked),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+fn encode_record<S: Serialize, A: Serialize + Hash + Eq>(state: &S, stats: &StateRecord<A>) -> Result<Vec<u8>, MemoryError> {
+    let state = bitcode::serialize(state).map_err(|_| MemoryError::Corrupt)?;
+    let stats = bitcode::serialize(stats).map_err(|_| MemoryError::Corrupt)?;
+    Ok(encode_raw(&state, &stats))
+}
+
+fn encode_raw(state: &[u8], stats: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(4 + state.len() + stats.len());
+    body.extend_from_slice(&(state.len() as u32).to_le_bytes());
+    body.extend_from_slice(state);
+    body.extend_from_slice(stats);
+    let mut record = Vec::with_capacity(8 + body.len());
+    record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32(&body).to_le_bytes());
+    record.extend_from_slice(&body);
+    record
+}
+
+fn encode_bucket(key: u64, offset: u64) -> [u8; BUCKET_LEN as usize] {
+    let mut bytes = [0; BUCKET_LEN as usize];
+    bytes[..8].copy_from_slice(&key.to_le_bytes());
+    bytes[8..].copy_from_slice(&offset.to_le_bytes());
+    bytes
+}
+
+fn decode_bucket(bytes: &[u8]) -> (u64, u64) {
+    let key = u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"));
+    let offset = u64::from_le_bytes(bytes[8..16].try_into().expect("8 bytes"));
+    (key, offset)
+}
+
+/// FNV-1a of the serialized state. It must not change between runs, so it can't be the `Hash` of the state.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |h: u64, &b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::core::Board;
+    use crate::game::connect_four::C4Board;
+    use crate::game::tictactoe::{TTTAddr, TTTBoard};
+    use crate::game::ultimate_ttt::{Action, UTTTBoard};
+
+    type Store = KnowledgeStore<C4Board, usize>;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("xoxo-store-{}-{name}.data", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(index_path(&path));
+        path
+    }
+
+    fn remove(path: &Path) {
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(index_path(path)).unwrap();
+        std::fs::remove_file(lock_path(path)).unwrap();
+    }
+
+    /// The boards after one move from the empty board, and then `n` moves in the first column
+    fn boards(n: usize) -> Vec<C4Board> {
+        let mut b = C4Board::default();
+        let mut boards = vec![];
+        for i in 0..n {
+            b.place_mark(i % 7, b.current_player());
+            boards.push(b);
+            if b.game_is_over() || b.valid_moves().is_empty() {
+                b = C4Board::default();
+            }
+        }
+        boards
+    }
+
+    fn record(visits: f64) -> StateRecord<usize> {
+        StateRecord {
+            visits,
+            actions: HashMap::from([(3, (visits / 2.0, visits))]),
+            proofs: None,
+            amaf: None,
+        }
+    }
+
+    #[test]
+    fn put_and_get() {
+        let path = temp_file("put-and-get");
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        let boards = boards(40);
+        store.put_all(boards.iter().enumerate().map(|(i, b)| (*b, record(i as f64)))).unwrap();
+        // saving a state again replaces it
+        store.put_all(std::iter::once((boards[0], record(100.0)))).unwrap();
+        drop(store);
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        assert_eq!(store.header.n_states, 40);
+        assert_eq!(store.get(&boards[0]).unwrap().unwrap().visits, 100.0);
+        assert_eq!(store.get(&boards[7]).unwrap().unwrap().visits, 7.0);
+        assert!(store.get(&C4Board::default()).unwrap().is_none());
+        remove(&path);
+    }
+
+    #[test]
+    fn grows_and_compacts() {
+        let path = temp_file("compacts");
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        let boards = boards(3000);
+        let distinct: std::collections::HashSet<_> = boards.iter().collect();
+        for round in 0..40 {
+            store.put_all(boards.iter().map(|b| (*b, record(round as f64)))).unwrap();
+        }
+        assert!(store.header.capacity >= 2 * distinct.len() as u64);
+        assert_eq!(store.header.n_states, distinct.len() as u64);
+        // without compaction, the log would hold all 40 rounds
+        let log_len = std::fs::metadata(&path).unwrap().len();
+        assert!(log_len < 2 * store.header.live_bytes + COMPACT_SLACK + store.records_start);
+        drop(store);
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        assert_eq!(store.get(&boards[1234]).unwrap().unwrap().visits, 39.0);
+        remove(&path);
+    }
+
+    #[test]
+    fn recovers_from_a_crash() {
+        let path = temp_file("crash");
+        let boards = boards(20);
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        store.put_all(boards.iter().map(|b| (*b, record(1.0)))).unwrap();
+        drop(store);
+        let good_len = std::fs::metadata(&path).unwrap().len();
+        // a record cut short at the end of the log
+        let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+        log.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+        drop(log);
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), good_len);
+        assert_eq!(store.get(&boards[5]).unwrap().unwrap().visits, 1.0);
+        drop(store);
+        // a lost index
+        std::fs::remove_file(index_path(&path)).unwrap();
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        assert_eq!(store.header.n_states, 20);
+        assert_eq!(store.get(&boards[5]).unwrap().unwrap().visits, 1.0);
+        remove(&path);
+    }
+
+    #[test]
+    fn counts_what_a_crash_indexed_without_the_header() {
+        let path = temp_file("crash-indexing");
+        let boards = boards(40);
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        store.put_all(boards[..20].iter().map(|b| (*b, record(1.0)))).unwrap();
+        let mut header = [0; INDEX_HEADER_LEN as usize];
+        store.index.seek(SeekFrom::Start(0)).unwrap();
+        store.index.read_exact(&mut header).unwrap();
+        store.put_all(boards[20..].iter().map(|b| (*b, record(1.0)))).unwrap();
+        let expected = store.header;
+        // the crash came after the buckets of the last records were written, and before the header
+        store.index.seek(SeekFrom::Start(0)).unwrap();
+        store.index.write_all(&header).unwrap();
+        drop(store);
+        let store = Store::open::<C4Board>(&path).unwrap();
+        assert_eq!(store.header, expected);
+        remove(&path);
+    }
+
+    #[test]
+    fn full_index_is_corrupt() {
+        let path = temp_file("full-index");
+        let boards = boards(2);
+        let mut store = Store::open::<C4Board>(&path).unwrap();
+        store.put_all(std::iter::once((boards[0], record(1.0)))).unwrap();
+        // every bucket points at the record of another state
+        for bucket in 0..store.header.capacity {
+            store.write_bucket(bucket, hash(&bitcode::serialize(&boards[1]).unwrap()), store.records_start).unwrap();
+        }
+        assert!(matches!(store.get(&boards[1]), Err(MemoryError::Corrupt)));
+        drop(store);
+        remove(&path);
+    }
+
+    #[test]
+    fn finds_transposed_states() {
+        let path = temp_file("transposed");
+        let play = |moves: &[(usize, usize, usize, usize)]| {
+            let mut b = UTTTBoard::default();
+            for &m in moves {
+                b.place_mark(Action::try_from(m).unwrap(), b.current_player());
+            }
+            b
+        };
+        // the same markers and target sub-board, reached by two move orders
+        let b1 = play(&[(1, 1, 0, 0), (0, 0, 1, 1), (1, 1, 2, 2), (2, 2, 1, 1)]);
+        let b2 = play(&[(1, 1, 2, 2), (2, 2, 1, 1), (1, 1, 0, 0), (0, 0, 1, 1)]);
+        assert_eq!(b1, b2);
+        let mut store = KnowledgeStore::<UTTTBoard, Action>::open::<UTTTBoard>(&path).unwrap();
+        store
+            .put_all(std::iter::once((
+                b1,
+                StateRecord {
+                    visits: 5.0,
+                    actions: HashMap::new(),
+                    proofs: None,
+                    amaf: None,
+                },
+            )))
+            .unwrap();
+        drop(store);
+        let mut store = KnowledgeStore::<UTTTBoard, Action>::open::<UTTTBoard>(&path).unwrap();
+        assert_eq!(store.get(&b2).unwrap().unwrap().visits, 5.0);
+        remove(&path);
+    }
+
+    #[test]
+    fn turns_snapshots_into_stores() {
+        let path = temp_file("snapshot");
+        std::fs::write(&path, include_bytes!("../../../tests/data/ttt-before-header.mcts")).unwrap();
+        let mut store = KnowledgeStore::<TTTBoard, TTTAddr>::open::<TTTBoard>(&path).unwrap();
+        assert_eq!(store.header.n_states, 26);
+        let record = store.get(&TTTBoard::default()).unwrap().unwrap();
+        assert_eq!(record.visits, 39.0);
+        assert_eq!(record.actions[&TTTAddr(5)].1, 9.0);
+        assert!(std::fs::read(&path).unwrap().starts_with(b"XOXOMCTS"));
+        drop(store);
+        remove(&path);
+        // a board that is stored differently now is refused, and the file is left as it was
+        let c4 = include_bytes!("../../../tests/data/c4-before-header.mcts");
+        std::fs::write(&path, c4).unwrap();
+        assert!(matches!(Store::open::<C4Board>(&path), Err(MemoryError::LegacyLayout)));
+        assert_eq!(std::fs::read(&path).unwrap(), c4);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(lock_path(&path)).unwrap();
+    }
+}
@@ -0,0 +1,508 @@
+//! MCTS on an explicit search tree.
+//! The nodes live in one `Vec`, and refer to their children by index. Compared with the `QMap`,
+//! a step through the tree is an index lookup instead of a hash lookup and a clone of the state.
+//!
+//! Only the state at the root is stored. The states further down are found by playing the actions from the root,
+//! so this assumes that the transitions of the MDP are deterministic, which they are for all the board games.
+//!
+//! Between turns, the subtree under the moves that were actually played is kept, and the rest is thrown away.
+//!
+//! With the solver on, the nodes also hold the exact values proven by MCTS-Solver, see `mcts_step`.
+//!
+//! With a prior, the edges are selected by PUCT. The priors of a node are computed once, when it is expanded.
+
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use super::prior::PriorPolicy;
+use super::rollout::RolloutPolicy;
+use super::{selection_value, ActionStats, Mdp};
+
+pub(crate) type NodeId = usize;
+
+struct Edge<A> {
+    action: A,
+    /// The reward for taking the action, as seen by the actor
+    reward: f64,
+    /// Total return observed after taking the action
+    w: f64,
+    /// Number of times the action was taken
+    n: f64,
+    /// The prior probability of the action, if the tree has a prior
+    prior: Option<f64>,
+    /// None until the action has been taken once
+    child: Option<NodeId>,
+}
+
+struct Node<A> {
+    /// Number of simulations that passed through this node
+    n: f64,
+    /// Filled in when the node is selected from for the first time
+    edges: Vec<Edge<A>>,
+    expanded: bool,
+    /// The exact value of the state, once the solver has proven it
+    proven: Option<f64>,
+}
+
+impl<A> Node<A> {
+    fn new() -> Self {
+        Node {
+            n: 0.0,
+            edges: vec![],
+            expanded: false,
+            proven: None,
+        }
+    }
+}
+
+pub(crate) struct SearchTree<M: Mdp> {
+    root_state: M::State,
+    nodes: Vec<Node<M::Action>>,
+    /// The nodes and edges that the current simulation went through
+    path: Vec<(NodeId, usize)>,
+    actions: Vec<M::Action>,
+    priors: Vec<f64>,
+    solve: bool,
+    prior: Option<Arc<dyn PriorPolicy<M>>>,
+}
+
+/// Where a simulation left the tree
+pub(crate) enum Leaf<S> {
+    /// The game is over, so there is nothing to roll out
+    Terminal,
+    /// A node was added for this state, roll out from it
+    New(S),
+    /// The solver knows the value of the state, no need to roll out
+    Proven(f64),
+}
+
+/// The root is always the first node
+const ROOT: NodeId = 0;
+
+/// How many plies below the old root to look for the new root.
+/// Two is enough for the usual case of our move followed by the opponent's.
+const REUSE_DEPTH: usize = 2;
+
+impl<M: Mdp> SearchTree<M> {
+    /// `solve` turns on the solver, and `prior` selects with PUCT instead of UCB1
+    pub fn new(root_state: M::State, solve: bool, prior: Option<Arc<dyn PriorPolicy<M>>>) -> Self {
+        SearchTree {
+            root_state,
+            nodes: vec![Node::new()],
+            path: vec![],
+            actions: vec![],
+            priors: vec![],
+            solve,
+            prior,
+        }
+    }
+
+    pub fn n_nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    #[cfg(test)]
+    pub fn root_state(&self) -> &M::State {
+        &self.root_state
+    }
+
+    /// Number of simulations that went through the root
+    #[cfg(test)]
+    pub fn root_visits(&self) -> f64 {
+        self.nodes[ROOT].n
+    }
+
+    /// The statistics of the actions at the root: (action, total return, visits)
+    #[cfg(test)]
+    pub fn root_edges(&self) -> impl Iterator<Item = (&M::Action, f64, f64)> {
+        self.nodes[ROOT].edges.iter().map(|e| (&e.action, e.w, e.n))
+    }
+
+    /// Move the root to `state`, keeping what is known about it if it is in the tree close to the old root.
+    /// Otherwise start over with an empty tree.
+    pub fn advance_to(&mut self, state: &M::State) {
+        if *state == self.root_state {
+            return;
+        }
+        match self.find(ROOT, self.root_state.clone(), state, REUSE_DEPTH) {
+            Some(id) => self.reroot(id, state.clone()),
+            None => *self = Self::new(state.clone(), self.solve, self.prior.take()),
+        }
+    }
+
+    /// Depth first search for the node of `target` below `id`, which has the state `s`
+    fn find(&self, id: NodeId, s: M::State, target: &M::State, depth: usize) -> Option<NodeId> {
+        if depth == 0 {
+            return None;
+        }
+        for e in self.nodes[id].edges.iter() {
+            if let Some(child) = e.child {
+                let (s2, _) = M::act(s.clone(), &e.action);
+                if s2 == *target {
+                    return Some(child);
+                }
+                if let Some(found) = self.find(child, s2, target, depth - 1) {
+                    return Some(found);
+                }
+            }
+        }
+        None
+    }
+
+    /// Make `new_root` the root, and drop every node that is not under it
+    fn reroot(&mut self, new_root: NodeId, state: M::State) {
+        let mut old = std::mem::take(&mut self.nodes);
+        // nodes are moved over in the order they are found, so the new root gets index 0
+        let mut stack = vec![new_root];
+        let mut new_index = vec![usize::MAX; old.len()];
+        let mut order = vec![];
+        while let Some(id) = stack.pop() {
+            new_index[id] = order.len();
+            order.push(id);
+            stack.extend(old[id].edges.iter().filter_map(|e| e.child));
+        }
+        self.nodes = order
+            .into_iter()
+            .map(|id| {
+                let mut node = std::mem::replace(&mut old[id], Node::new());
+                for e in node.edges.iter_mut() {
+                    e.child = e.child.map(|c| new_index[c]);
+                }
+                node
+            })
+            .collect();
+        self.root_state = state;
+    }
+
+    /// Run one simulation: select down the tree with UCB1, add one node, roll out from it and back up the return.
+    /// Returns the return as seen from the root.
+    pub fn step(&mut self, c: f64, rollout: &dyn RolloutPolicy<M>, rng: &mut StdRng) -> f64 {
+        let mut path = std::mem::take(&mut self.path);
+        let value = match self.descend(c, 0.0, &mut path, rng) {
+            Leaf::Terminal => 0.0,
+            Leaf::New(state) => rollout.rollout(state, rng, None),
+            Leaf::Proven(value) => value,
+        };
+        let g_return = self.backup(&path, value, 0.0);
+        self.path = path;
+        g_return
+    }
+
+    /// The select and expand part of a simulation. The edges taken are written to `path`.
+    /// Their visits are counted already here, and `virtual_loss` is taken off their returns,
+    /// so that other threads searching the same tree go elsewhere until the simulation is backed up.
+    pub fn descend(&mut self, c: f64, virtual_loss: f64, path: &mut Vec<(NodeId, usize)>, rng: &mut StdRng) -> Leaf<M::State> {
+        path.clear();
+        if M::is_terminal(&self.root_state) {
+            return Leaf::Terminal;
+        }
+        if let Some(value) = self.nodes[ROOT].proven {
+            return Leaf::Proven(value);
+        }
+        let mut state = self.root_state.clone();
+        let mut id = ROOT;
+        loop {
+            if !self.nodes[id].expanded {
+                M::allowed_actions_into(&state, &mut self.actions);
+                match self.prior.as_ref() {
+                    Some(prior) => prior.priors(&state, &self.actions, &mut self.priors),
+                    None => self.priors.clear(),
+                }
+                let mut priors = self.priors.iter().copied();
+                let node = &mut self.nodes[id];
+                node.edges = self
+                    .actions
+                    .drain(..)
+                    .map(|action| Edge {
+                        action,
+                        reward: 0.0,
+                        w: 0.0,
+                        n: 0.0,
+                        prior: priors.next(),
+                        child: None,
+                    })
+                    .collect();
+                node.expanded = true;
+            }
+            let i = select::<M>(&self.nodes, id, c, rng);
+            let node = &mut self.nodes[id];
+            node.n += 1.0;
+            let edge = &mut node.edges[i];
+            let (new_state, reward) = M::act(state, &edge.action);
+            state = new_state;
+            edge.reward = reward;
+            edge.n += 1.0;
+            edge.w -= virtual_loss;
+            let next = edge.child;
+            path.push((id, i));
+            match next {
+                Some(child) => {
+                    if M::is_terminal(&state) {
+                        // count the visit to the terminal node, which is never selected from
+                        self.nodes[child].n += 1.0;
+                        return Leaf::Terminal;
+                    }
+                    if let Some(value) = self.nodes[child].proven {
+                        self.nodes[child].n += 1.0;
+                        return Leaf::Proven(value);
+                    }
+                    id = child;
+                }
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes[id].edges[i].child = Some(child);
+                    let mut node = Node::new();
+                    node.n = 1.0;
+                    if self.solve && M::is_terminal(&state) {
+                        node.proven = Some(0.0);
+                    }
+                    self.nodes.push(node);
+                    return Leaf::New(state);
+                }
+            }
+        }
+    }
+
+    /// Back up the return `value` of the leaf at the end of `path`, and take back the virtual loss.
+    /// With the solver on, the nodes whose child on the path was proven are proven too, if that settles them.
+    /// Returns the return as seen from the root.
+    pub fn backup(&mut self, path: &[(NodeId, usize)], value: f64, virtual_loss: f64) -> f64 {
+        let mut g_return = value;
+        for &(id, i) in path.iter().rev() {
+            let edge = &mut self.nodes[id].edges[i];
+            g_return = edge.reward + M::DISCOUNT_FACTOR * g_return;
+            edge.w += g_return + virtual_loss;
+            let child = edge.child;
+            if self.solve && self.nodes[id].proven.is_none() && child.is_some_and(|c| self.nodes[c].proven.is_some()) {
+                self.prove(id);
+            }
+        }
+        g_return
+    }
+
+    /// Minimax rules: the node is worth an edge that is a win, or the best of its edges once they are all proven
+    fn prove(&mut self, id: NodeId) {
+        let mut best = -f64::INFINITY;
+        let mut all_proven = true;
+        for e in self.nodes[id].edges.iter() {
+            match edge_value::<M>(&self.nodes, e) {
+                Some(v) => best = best.max(v),
+                None => all_proven = false,
+            }
+        }
+        if all_proven || best > M::WIN_THRESHOLD {
+            self.nodes[id].proven = Some(best);
+        }
+    }
+
+    /// The exact value of the root, if the solver has proven it
+    pub fn root_value(&self) -> Option<f64> {
+        self.nodes[ROOT].proven
+    }
+
+    /// What is known about the actions at the root
+    pub fn root_stats(&self) -> Vec<ActionStats<M::Action>> {
+        self.nodes[ROOT]
+            .edges
+            .iter()
+            .map(|e| ActionStats {
+                action: e.action.clone(),
+                w: e.w,
+                n: e.n,
+                proven: edge_value::<M>(&self.nodes, e),
+            })
+            .collect()
+    }
+
+    /// The line of play that starts with `first` from the root, and then follows the actions the search took most
+    pub fn principal_variation(&self, first: &M::Action) -> Vec<M::Action> {
+        let mut pv = vec![first.clone()];
+        let mut next = self.nodes[ROOT].edges.iter().find(|e| e.action == *first).and_then(|e| e.child);
+        while let Some(id) = next {
+            match self.nodes[id].edges.iter().filter(|e| e.n > 0.0).max_by(|a, b| a.n.total_cmp(&b.n)) {
+                Some(e) => {
+                    pv.push(e.action.clone());
+                    next = e.child;
+                }
+                None => break,
+            }
+        }
+        pv
+    }
+
+    /// Add the statistics at the root of `other`, a tree of the same state, to the root of this tree.
+    /// With the solver on, the actions that `other` proved are proven here too, and then the root, if that settles it.
+    pub fn merge_root(&mut self, other: &SearchTree<M>) {
+        let root = &mut self.nodes[ROOT];
+        let other_root = &other.nodes[ROOT];
+        if !root.expanded {
+            root.edges = other_root
+                .edges
+                .iter()
+                .map(|e| Edge {
+                    action: e.action.clone(),
+                    reward: e.reward,
+                    w: 0.0,
+                    n: 0.0,
+                    prior: e.prior,
+                    child: None,
+                })
+                .collect();
+            root.expanded = other_root.expanded;
+        }
+        root.n += other_root.n;
+        for e in other_root.edges.iter() {
+            if let Some(mine) = root.edges.iter_mut().find(|mine| mine.action == e.action) {
+                mine.w += e.w;
+                mine.n += e.n;
+            }
+        }
+        if !self.solve {
+            return;
+        }
+        for e in other_root.edges.iter() {
+            let Some(other_child) = e.child.filter(|&c| other.nodes[c].proven.is_some()) else {
+                continue;
+            };
+            let Some(i) = self.nodes[ROOT].edges.iter().position(|mine| mine.action == e.action) else {
+                continue;
+            };
+            // the same action from the same state, so the child is the same state, with the same value
+            let child = match self.nodes[ROOT].edges[i].child {
+                Some(child) => child,
+                None => {
+                    let child = self.nodes.len();
+                    let mut node = Node::new();
+                    node.n = other.nodes[other_child].n;
+                    self.nodes.push(node);
+                    self.nodes[ROOT].edges[i].child = Some(child);
+                    child
+                }
+            };
+            self.nodes[ROOT].edges[i].reward = e.reward;
+            self.nodes[child].proven = other.nodes[other_child].proven;
+        }
+        if self.nodes[ROOT].proven.is_none() {
+            self.prove(ROOT);
+        }
+    }
+}
+
+/// The exact value of taking the edge, if the state it leads to is proven
+fn edge_value<M: Mdp>(nodes: &[Node<M::Action>], edge: &Edge<M::Action>) -> Option<f64> {
+    edge.child
+        .and_then(|c| nodes[c].proven)
+        .map(|v| edge.reward + M::DISCOUNT_FACTOR * v)
+}
+
+/// The index of the edge of node `id` to search next: the one with the highest UCB1 or PUCT value, skipping what the solver has proven.
+/// Ties are broken at random, by reservoir sampling.
+fn select<M: Mdp>(nodes: &[Node<M::Action>], id: NodeId, c: f64, rng: &mut StdRng) -> usize {
+    let node = &nodes[id];
+    let mut best = 0;
+    let mut record = -f64::INFINITY;
+    let mut n_ties = 0;
+    for (i, e) in node.edges.iter().enumerate() {
+        let proven = edge_value::<M>(nodes, e).is_some();
+        let value = selection_value(c, e.w, e.n, node.n, e.prior, proven);
+        if value > record {
+            record = value;
+            best = i;
+            n_ties = 1;
+        } else if value == record {
+            n_ties += 1;
+            if rng.gen_range(0..n_ties) == 0 {
+                best = i;
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::core::Board;
+    use crate::player::mcts::rollout::UniformRollout;
+    use crate::game::tictactoe::{TTTAddr, TTTBoard};
+
+    #[test]
+    fn visits_add_up() {
+        let mut tree = SearchTree::<TTTBoard>::new(TTTBoard::default(), false, None);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            tree.step(1.0, &UniformRollout, &mut rng);
+        }
+        assert_eq!(tree.root_visits(), 100.0);
+        assert_eq!(tree.root_edges().map(|(_, _, n)| n).sum::<f64>(), 100.0);
+        // each simulation adds one node, except when it ends in a terminal node that is already known
+        assert!(tree.n_nodes() <= 101);
+    }
+
+    #[test]
+    fn subtree_is_kept() {
+        let mut tree = SearchTree::<TTTBoard>::new(TTTBoard::default(), false, None);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..2000 {
+            tree.step(1.0, &UniformRollout, &mut rng);
+        }
+        let mut b = TTTBoard::default();
+        b.place_mark(TTTAddr(5), b.current_player());
+        b.place_mark(TTTAddr(1), b.current_player());
+        let before = tree.n_nodes();
+        tree.advance_to(&b);
+        assert_eq!(tree.root_state(), &b);
+        assert!(tree.root_visits() > 1.0);
+        assert!(tree.n_nodes() < before);
+        // the statistics of the subtree are still consistent
+        assert_eq!(tree.root_edges().map(|(_, _, n)| n).sum::<f64>(), tree.root_visits() - 1.0);
+        // a position that can't be reached starts a new tree
+        let mut far = b;
+        far.place_mark(TTTAddr(9), far.current_player());
+        far.place_mark(TTTAddr(2), far.current_player());
+        far.place_mark(TTTAddr(3), far.current_player());
+        tree.advance_to(&far);
+        assert_eq!(tree.n_nodes(), 1);
+    }
+
+    #[test]
+    fn merged_proofs_settle_the_root() {
+        // O wins at 3
+        let b = TTTBoard::from_str("oo xx    ").unwrap();
+        let mut helper = SearchTree::<TTTBoard>::new(b, true, None);
+        let mut rng = StdRng::seed_from_u64(1);
+        while helper.root_value().is_none() {
+            helper.step(1.0, &UniformRollout, &mut rng);
+        }
+        // a tree that hasn't searched the winning move yet learns that it wins from the helper
+        let mut tree = SearchTree::<TTTBoard>::new(b, true, None);
+        tree.merge_root(&helper);
+        assert_eq!(tree.root_value(), helper.root_value());
+        let win = tree.root_stats().into_iter().find(|s| s.action == TTTAddr(3)).unwrap();
+        assert!(win.proven.is_some_and(|v| v > TTTBoard::WIN_THRESHOLD));
+        // and the search stops there, like in the helper
+        assert!(matches!(tree.descend(1.0, 0.0, &mut vec![], &mut rng), Leaf::Proven(_)));
+    }
+
+    #[test]
+    fn virtual_loss_is_taken_back() {
+        let mut tree = SearchTree::<TTTBoard>::new(TTTBoard::default(), false, None);
+        let mut rng = StdRng::seed_from_u64(1);
+        let (mut path1, mut path2) = (vec![], vec![]);
+        tree.descend(1.0, 1.0, &mut path1, &mut rng);
+        // the first move has a virtual loss now, so a second simulation started before the first is backed up goes elsewhere
+        tree.descend(1.0, 1.0, &mut path2, &mut rng);
+        assert_ne!(path1[0], path2[0]);
+        tree.backup(&path1, 0.0, 1.0);
+        tree.backup(&path2, 0.0, 1.0);
+        assert_eq!(tree.root_visits(), 2.0);
+        // the returns are back to what they would be without the virtual losses
+        assert!(tree.root_edges().all(|(_, w, _)| w == 0.0));
+    }
+}
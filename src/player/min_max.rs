@@ -1,22 +1,49 @@
+use std::time::{Duration, Instant};
+
 use log::debug;
 
-use crate::core::{BlitzPlayer, Board, HeuristicFn, Player, PlayerMark};
+use crate::core::{BlitzPlayer, Board, HeuristicFn, Player, RootMove, ScoreBound, SearchInfo};
+
+/// How large part of the remaining time on the clock to spend on one move
+const TIME_BUDGET_FRACTION: u32 = 8;
+/// Look at the clock every this many leaf evaluations
+const CLOCK_CHECK_INTERVAL: usize = 64;
 
-pub struct MinMaxAi<B> {
-    my_marker: PlayerMark,
+/// The moves at the root with their scores, best first, and the principal variation
+type RootResult<C> = (Vec<RootMove<C>>, Vec<C>);
+
+/// Plain minimax in negamax form: every score is from the point of view of the player to move in that node.
+/// The side to play for is read from the board at the root, so one instance can play either side.
+pub struct MinMaxAi<B: Board> {
     /// A performance counter. If we prune well, this number is small
     n_leafs_evaluated: usize,
+    /// Every call of `minimax`, leafs included
+    n_nodes_visited: usize,
     heuristic_fn: HeuristicFn<B>,
     max_depth: usize,
+    /// One buffer of moves per depth, so that the search doesn't allocate
+    move_buffers: Vec<Vec<B::Coordinate>>,
+    /// When searching on a time budget, give up when this time has passed
+    deadline: Option<Instant>,
+    /// Set when the deadline passed during the search. All scores computed after that are garbage.
+    timed_out: bool,
+    /// The principal variation of the last node searched at each depth
+    pv: Vec<Vec<B::Coordinate>>,
+    last_search: Option<SearchInfo<B::Coordinate>>,
 }
 
 impl<B: Board + Clone> MinMaxAi<B> {
-    pub fn new(mark: PlayerMark, heuristic_fn: HeuristicFn<B>, depth: usize) -> Self {
+    pub fn new(heuristic_fn: HeuristicFn<B>, depth: usize) -> Self {
         Self {
-            my_marker: mark,
             n_leafs_evaluated: 0,
+            n_nodes_visited: 0,
             heuristic_fn,
             max_depth: depth,
+            move_buffers: vec![],
+            deadline: None,
+            timed_out: false,
+            pv: vec![],
+            last_search: None,
         }
     }
 
@@ -26,74 +53,189 @@ impl<B: Board + Clone> MinMaxAi<B> {
     /// It is always good to hold the mid point
     fn heuristic(&mut self, b: &B) -> f64 {
         self.n_leafs_evaluated += 1;
-        (self.heuristic_fn)(self.my_marker, b)
+        (self.heuristic_fn)(b.current_player(), b)
+    }
+
+    /// Check the clock. Once the deadline has passed, this stays true for the rest of the search.
+    /// Reading the clock is not free, so it is only done every now and then.
+    fn out_of_time(&mut self) -> bool {
+        if !self.timed_out && self.n_leafs_evaluated.is_multiple_of(CLOCK_CHECK_INTERVAL) {
+            if let Some(deadline) = self.deadline {
+                self.timed_out = Instant::now() >= deadline;
+            }
+        }
+        self.timed_out
     }
 
-    /// compute the score of a node by use of minimax
-    /// Assumes I want to maximize my score, and the opponent makes moves to minimize it
-    fn minimax(&mut self, node: &B, depth: usize, my_move: bool) -> f64 {
+    /// compute the score of a node by use of minimax, for the player to move in the node.
+    /// The player to move wants to maximize the score, and the score for the opponent is the negation of it.
+    /// The node is used as scratch space: moves are made and taken back, so it is unchanged when this returns
+    fn minimax(&mut self, node: &mut B, depth: usize) -> f64 {
+        self.n_nodes_visited += 1;
+        if self.pv.len() <= depth {
+            self.pv.resize_with(depth + 1, Vec::new);
+        }
+        self.pv[depth].clear();
+        if self.out_of_time() {
+            return 0.0;
+        }
         if depth == 0 || node.game_is_over() {
             let s = self.heuristic(node);
             return s;
         }
-        let moves = node.valid_moves();
-        let my_marker = self.my_marker; // take a copy here
-        if my_move {
-            // In this branch, the AI tries to find a move for itself that would maximize the score
-            let mut value = -f64::INFINITY;
-            let child_nodes = moves.iter().map(|addr| {
-                let mut child = (*node).clone();
-                child.place_mark(*addr, my_marker);
-                child
-            });
-            for child in child_nodes {
-                let newval = self.minimax(&child, depth - 1, false);
-                value = value.max(newval);
+        let moves = self.take_moves(node, depth);
+        let mover = node.current_player();
+        let mut value = -f64::INFINITY;
+        for &addr in moves.iter() {
+            let undo = node.make_move(addr, mover);
+            let newval = -self.minimax(node, depth - 1);
+            node.unmake_move(undo);
+            if newval > value || self.pv[depth].is_empty() {
+                value = newval;
+                let (child, this) = self.pv.split_at_mut(depth);
+                this[0].clear();
+                this[0].push(addr);
+                this[0].extend_from_slice(&child[depth - 1]);
             }
-            value
-        } else {
-            // In this branch, the AI tries to find a move for the other player that would minimize the score
-            let mut value = f64::INFINITY;
-            let child_nodes = moves.iter().map(|addr| {
-                let mut child = (*node).clone();
-                child.place_mark(*addr, my_marker.other());
-                child
+        }
+        self.move_buffers[depth] = moves;
+        value
+    }
+
+    /// Fill the move buffer for this depth and hand it out. Give it back by putting it in `move_buffers[depth]` when done.
+    fn take_moves(&mut self, node: &B, depth: usize) -> Vec<B::Coordinate> {
+        if self.move_buffers.len() <= depth {
+            self.move_buffers.resize_with(depth + 1, Vec::new);
+        }
+        let mut moves = std::mem::take(&mut self.move_buffers[depth]);
+        node.valid_moves_into(&mut moves);
+        moves
+    }
+
+    /// Score every move at the root by searching `depth` plies below it.
+    /// Returns the moves with their scores, best first, and the principal variation.
+    /// Returns None if the deadline passed before the search was done.
+    fn search_root(&mut self, b: &B, depth: usize) -> Option<RootResult<B::Coordinate>> {
+        let mut b2 = (*b).clone();
+        let mover = b.current_player();
+        let moves = b.valid_moves();
+        let mut ranked = Vec::with_capacity(moves.len());
+        let mut pv = vec![];
+        for addr in moves {
+            let undo = b2.make_move(addr, mover);
+            let score = -self.minimax(&mut b2, depth);
+            b2.unmake_move(undo);
+            if ranked.iter().all(|r: &RootMove<_>| score > r.score) {
+                pv.clear();
+                pv.push(addr);
+                pv.extend_from_slice(&self.pv[depth]);
+            }
+            ranked.push(RootMove {
+                coordinate: addr,
+                score,
+                bound: ScoreBound::Exact,
             });
-            for child in child_nodes {
-                value = value.min(self.minimax(&child, depth - 1, true));
+        }
+        if self.timed_out {
+            return None;
+        }
+        // stable, so the first of the equally good moves stays first
+        ranked.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        Some((ranked, pv))
+    }
+
+    /// Search the position to the max depth, and tell what was found
+    pub fn search(&mut self, b: &B) -> SearchInfo<B::Coordinate> {
+        let t0 = Instant::now();
+        let nodes0 = self.n_nodes_visited;
+        let (ranked, pv) = self
+            .search_root(b, self.max_depth)
+            .expect("There is no deadline, so the search can't time out");
+        let info = SearchInfo {
+            best_move: ranked[0].coordinate,
+            score: ranked[0].score,
+            principal_variation: pv,
+            depth: self.max_depth + 1,
+            nodes: self.n_nodes_visited - nodes0,
+            elapsed: t0.elapsed(),
+            root_moves: ranked,
+            outcome: None,
+            proven_moves: vec![],
+        };
+        self.last_search = Some(info.clone());
+        info
+    }
+
+    /// Search 0, 1, 2... plies below the root moves, up to the max depth, until the time budget is spent.
+    /// Tells what the deepest search that was completed found.
+    pub fn search_with_budget(&mut self, b: &B, budget: Duration) -> SearchInfo<B::Coordinate> {
+        let t0 = Instant::now();
+        let nodes0 = self.n_nodes_visited;
+        self.deadline = Some(t0 + budget);
+        self.timed_out = false;
+        let mut info = SearchInfo {
+            best_move: *b.valid_moves().first().expect("At least one element"),
+            score: 0.0,
+            principal_variation: vec![],
+            depth: 0,
+            nodes: 0,
+            elapsed: Duration::ZERO,
+            root_moves: vec![],
+            outcome: None,
+            proven_moves: vec![],
+        };
+        for depth in 0..=self.max_depth {
+            match self.search_root(b, depth) {
+                Some((ranked, pv)) => {
+                    info.best_move = ranked[0].coordinate;
+                    info.score = ranked[0].score;
+                    info.principal_variation = pv;
+                    info.depth = depth + 1;
+                    info.root_moves = ranked;
+                }
+                None => {
+                    debug!("MinMaxAi ran out of time in depth {}", depth);
+                    break;
+                }
             }
-            value
         }
+        self.deadline = None;
+        self.timed_out = false;
+        info.nodes = self.n_nodes_visited - nodes0;
+        info.elapsed = t0.elapsed();
+        self.last_search = Some(info.clone());
+        info
+    }
+
+    /// Like `search_with_budget`, but only the best move
+    pub fn play_with_budget(&mut self, b: &B, budget: Duration) -> B::Coordinate {
+        self.search_with_budget(b, budget).best_move
     }
 }
 
 impl<B: Board+Clone> BlitzPlayer<B> for MinMaxAi<B>{
-    fn blitz(&mut self, b: &B, _time_remaining: std::time::Duration) -> <B as Board>::Coordinate {
-        self.play(b)
+    fn blitz(&mut self, b: &B, time_remaining: std::time::Duration) -> <B as Board>::Coordinate {
+        self.play_with_budget(b, time_remaining / TIME_BUDGET_FRACTION)
+    }
+
+    fn last_search(&self) -> Option<&SearchInfo<B::Coordinate>> {
+        self.last_search.as_ref()
     }
 }
 
 impl<B: Board + Clone> Player<B> for MinMaxAi<B> {
     fn play(&mut self, b: &B) -> B::Coordinate {
-        let res = b
-            .valid_moves()
-            .iter()
-            .map(|addr| {
-                let mut b2 = (*b).clone();
-                b2.place_mark(*addr, self.my_marker);
-                let score = self.minimax(&b2, self.max_depth, false);
-                (score, addr)
-            })
-            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
-            .map(|(_, &q)| q)
-            .expect("At least one element");
-        res
+        self.search(b).best_move
+    }
+
+    fn last_search(&self) -> Option<&SearchInfo<B::Coordinate>> {
+        self.last_search.as_ref()
     }
 }
 
-impl<M> Drop for MinMaxAi<M> {
+impl<M: Board> Drop for MinMaxAi<M> {
     fn drop(&mut self) {
-        debug!("MinMaxAi evaluated {} leafs", self.n_leafs_evaluated);
+        debug!("MinMaxAi visited {} nodes and evaluated {} leafs", self.n_nodes_visited, self.n_leafs_evaluated);
     }
 }
 
@@ -1,23 +1,26 @@
 use rand::{rngs::StdRng, SeedableRng};
 
 use crate::core::{BlitzPlayer, Board, Player};
-pub struct RandomAi<R> {
+/// Plays a random valid move. `C` is the coordinate type of the board it plays on.
+pub struct RandomAi<R, C> {
     rng: R,
+    /// Reused from move to move, so that playing doesn't allocate
+    moves: Vec<C>,
 }
 
-impl<Rng, B> Player<B> for RandomAi<Rng>
+impl<Rng, B> Player<B> for RandomAi<Rng, B::Coordinate>
 where
     Rng: rand::Rng,
     B: Board,
 {
     fn play(&mut self, b: &B) -> B::Coordinate {
-        let moves: Vec<_> = b.valid_moves();
-        let idx = self.rng.next_u32() as usize % moves.len();
-        moves[idx]
+        b.valid_moves_into(&mut self.moves);
+        let idx = self.rng.next_u32() as usize % self.moves.len();
+        self.moves[idx]
     }
 }
 
-impl<R,B> BlitzPlayer<B> for RandomAi<R>
+impl<R,B> BlitzPlayer<B> for RandomAi<R, B::Coordinate>
 where
     R: rand::Rng,
     B: Board,
@@ -28,13 +31,14 @@ where
     }
 }
 
-impl RandomAi<rand::prelude::StdRng> {
+impl<C> RandomAi<rand::prelude::StdRng, C> {
     pub fn new(seed: Option<u64>) -> Self {
         Self {
             rng: match seed {
                 None => StdRng::from_entropy(),
                 Some(seed) => StdRng::seed_from_u64(seed),
             },
+            moves: Vec::new(),
         }
     }
 }
@@ -0,0 +1,29 @@
+//! Integration test that the search AIs keep within their time budget in blitz games
+use std::time::{Duration, Instant};
+
+use xoxo::{
+    core::{BlitzPlayer, Board},
+    game::ultimate_ttt::UTTTBoard,
+    player::{uttt_heuristic, ABAi, MinMaxAi},
+};
+
+/// Way too deep to finish, so only the clock can stop the search
+const DEPTH: usize = 30;
+
+fn assert_quick(ai: &mut dyn BlitzPlayer<UTTTBoard>) {
+    let b = UTTTBoard::default();
+    let t0 = Instant::now();
+    let action = ai.blitz(&b, Duration::from_millis(400));
+    assert!(t0.elapsed() < Duration::from_millis(200), "Took {:?}", t0.elapsed());
+    assert!(b.valid_moves().contains(&action));
+}
+
+#[test]
+fn alphabeta_honours_the_clock() {
+    assert_quick(&mut ABAi::<UTTTBoard>::new(uttt_heuristic, DEPTH));
+}
+
+#[test]
+fn minimax_honours_the_clock() {
+    assert_quick(&mut MinMaxAi::<UTTTBoard>::new(uttt_heuristic, DEPTH));
+}
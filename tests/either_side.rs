@@ -0,0 +1,62 @@
+//! Integration test that one search AI instance plays for whichever side is to move
+use std::str::FromStr;
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use xoxo::{
+    core::{Board, Player, PlayerMark},
+    game::{
+        connect_four::C4Board,
+        tictactoe::{TTTAddr, TTTBoard},
+        ultimate_ttt::UTTTBoard,
+    },
+    player::{c4_heuristic, ttt_heuristic, uttt_heuristic, ABAi, MinMaxAi},
+};
+
+/// Naught to move, and can win in the top right corner
+const NAUGHT_WINS: &str = "oo xx    ";
+/// Cross to move, and can win on the middle row before naught wins on the top row
+const CROSS_WINS: &str = "oo xx o  ";
+
+fn check_both_sides(ai: &mut dyn Player<TTTBoard>) {
+    let b = TTTBoard::from_str(NAUGHT_WINS).unwrap();
+    assert_eq!(b.current_player(), PlayerMark::Naught);
+    assert_eq!(ai.play(&b), TTTAddr(3));
+    let b = TTTBoard::from_str(CROSS_WINS).unwrap();
+    assert_eq!(b.current_player(), PlayerMark::Cross);
+    assert_eq!(ai.play(&b), TTTAddr(6));
+}
+
+#[test]
+fn alpha_beta_plays_either_side() {
+    let mut ai = ABAi::<TTTBoard>::new(ttt_heuristic, 10);
+    check_both_sides(&mut ai);
+    // the transposition table is shared between the sides
+    ai.set_transposition_table_size(1 << 12);
+    check_both_sides(&mut ai);
+    check_both_sides(&mut ai);
+}
+
+#[test]
+fn minimax_plays_either_side() {
+    check_both_sides(&mut MinMaxAi::<TTTBoard>::new(ttt_heuristic, 10));
+}
+
+/// Play random moves, and check that the heuristic is zero-sum in every position
+fn check_zero_sum<B: Board>(heuristic: fn(PlayerMark, &B) -> f64, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut b = B::default();
+    while !b.game_is_over() {
+        let m = *b.valid_moves().choose(&mut rng).unwrap();
+        b.place_mark(m, b.current_player());
+        assert_eq!(heuristic(PlayerMark::Naught, &b), -heuristic(PlayerMark::Cross, &b), "{b}");
+    }
+}
+
+#[test]
+fn heuristics_are_zero_sum() {
+    for seed in 0..10 {
+        check_zero_sum::<TTTBoard>(ttt_heuristic, seed);
+        check_zero_sum::<C4Board>(c4_heuristic, seed);
+        check_zero_sum::<UTTTBoard>(uttt_heuristic, seed);
+    }
+}
@@ -0,0 +1,65 @@
+//! Integration test that the game runners forfeit misbehaving players instead of crashing
+use std::time::Duration;
+
+use xoxo::{
+    core::{run_game, BlitzPlayer, GameEndReason, GameEndStatus, Player},
+    game::{run_blitz_game, tictactoe::{TTTAddr, TTTBoard}},
+    player::RandomAi,
+};
+
+/// Always plays in the top left corner, so it makes an illegal move on its second turn
+struct CornerBot;
+
+impl Player<TTTBoard> for CornerBot {
+    fn play(&mut self, _b: &TTTBoard) -> TTTAddr {
+        TTTAddr(1)
+    }
+}
+
+impl BlitzPlayer<TTTBoard> for CornerBot {
+    fn blitz(&mut self, b: &TTTBoard, _time_remaining: Duration) -> TTTAddr {
+        self.play(b)
+    }
+}
+
+struct PanicBot;
+
+impl Player<TTTBoard> for PanicBot {
+    fn play(&mut self, _b: &TTTBoard) -> TTTAddr {
+        panic!("PanicBot always panics")
+    }
+}
+
+impl BlitzPlayer<TTTBoard> for PanicBot {
+    fn blitz(&mut self, b: &TTTBoard, _time_remaining: Duration) -> TTTAddr {
+        self.play(b)
+    }
+}
+
+#[test]
+fn illegal_move_loses() {
+    let (status, reason) = run_game::<TTTBoard>(Box::new(CornerBot), Box::new(CornerBot));
+    assert_eq!(status, GameEndStatus::O);
+    assert_eq!(reason, GameEndReason::IllegalMove);
+    let (status, reason, _, _) = run_blitz_game::<TTTBoard>(
+        Box::new(CornerBot),
+        Box::new(CornerBot),
+        Duration::from_secs(1),
+    );
+    assert_eq!(status, GameEndStatus::O);
+    assert_eq!(reason, GameEndReason::IllegalMove);
+}
+
+#[test]
+fn crash_loses() {
+    let (status, reason) = run_game::<TTTBoard>(Box::new(PanicBot), Box::new(RandomAi::new(Some(1))));
+    assert_eq!(status, GameEndStatus::X);
+    assert_eq!(reason, GameEndReason::Crash);
+    let (status, reason, _, _) = run_blitz_game::<TTTBoard>(
+        Box::new(RandomAi::new(Some(1))),
+        Box::new(PanicBot),
+        Duration::from_secs(1),
+    );
+    assert_eq!(status, GameEndStatus::O);
+    assert_eq!(reason, GameEndReason::Crash);
+}
@@ -0,0 +1,44 @@
+//! Integration test of the ways MctsAi picks the move to play
+use xoxo::{
+    core::Player,
+    game::tictactoe::TTTBoard,
+    player::{FinalMove, MctsAi, MctsEngine},
+};
+
+fn ai(seed: u64, engine: MctsEngine, policy: FinalMove) -> MctsAi<TTTBoard> {
+    let mut ai = MctsAi::<TTTBoard>::new(seed, 1.0);
+    ai.set_engine(engine);
+    ai.set_final_move(policy);
+    ai.set_play_steps(200);
+    ai
+}
+
+/// The policies only differ in how they read the statistics, so with the same seed the search is the same.
+/// A temperature of zero then plays the most visited move, and `MaxMean` the move with the best score in the search info.
+#[test]
+fn policies_read_the_same_search() {
+    let b = TTTBoard::default();
+    for engine in [MctsEngine::QMap, MctsEngine::Tree] {
+        for seed in 0..5 {
+            let mut max_visits = ai(seed, engine, FinalMove::MaxVisits);
+            let m = max_visits.play(&b);
+            assert_eq!(ai(seed, engine, FinalMove::Temperature(0.0)).play(&b), m, "{engine:?} seed {seed}");
+            let info = max_visits.last_search().unwrap();
+            let best_mean = info.root_moves[0].coordinate;
+            assert_eq!(ai(seed, engine, FinalMove::MaxMean).play(&b), best_mean, "{engine:?} seed {seed}");
+        }
+    }
+}
+
+#[test]
+fn temperature_varies_the_opening() {
+    let b = TTTBoard::default();
+    let mut ai = MctsAi::<TTTBoard>::new(1, 1.0);
+    ai.set_engine(MctsEngine::Tree);
+    ai.set_final_move(FinalMove::Temperature(1.0));
+    ai.set_play_steps(500);
+    let mut openings: Vec<_> = (0..20).map(|_| ai.play(&b)).collect();
+    openings.sort_by_key(|a| a.0);
+    openings.dedup();
+    assert!(openings.len() > 1);
+}
@@ -0,0 +1,51 @@
+//! Integration test of bounding the memory of MctsAi
+use std::str::FromStr;
+
+use xoxo::{
+    core::{Board, Player},
+    game::{
+        connect_four::C4Board,
+        tictactoe::{TTTAddr, TTTBoard},
+    },
+    player::{Eviction, MctsAi, MemoryLimit},
+};
+
+const POLICIES: [Eviction; 3] = [Eviction::LeastRecentlyUsed, Eviction::LowestVisits, Eviction::DeeperThan(3)];
+
+#[test]
+fn memory_stays_within_the_limit() {
+    for eviction in POLICIES {
+        for threads in [1, 2] {
+            let mut ai = MctsAi::<C4Board>::new(1, 1.0);
+            ai.set_memory_limit(Some(MemoryLimit::States(300)), eviction);
+            ai.set_threads(threads);
+            ai.set_play_steps(3000);
+            let mut b = C4Board::default();
+            for _ in 0..4 {
+                let m = ai.play(&b);
+                b.place_mark(m, b.current_player());
+                assert!(ai.memory_usage().states <= 300, "{eviction:?} on {threads} threads: {}", ai.memory_usage());
+            }
+        }
+    }
+
+    let mut ai = MctsAi::<C4Board>::new(1, 1.0);
+    ai.set_play_steps(3000);
+    ai.play(&C4Board::default());
+    let unbounded = ai.memory_usage();
+    assert!(unbounded.states > 1000);
+    ai.set_memory_limit(Some(MemoryLimit::Bytes(unbounded.bytes / 4)), Eviction::LeastRecentlyUsed);
+    ai.play(&C4Board::default());
+    assert!(ai.memory_usage().bytes <= unbounded.bytes / 4);
+}
+
+#[test]
+fn bounded_ai_finds_winning_move() {
+    let b = TTTBoard::from_str("oo xx    ").unwrap();
+    for eviction in POLICIES {
+        let mut ai = MctsAi::<TTTBoard>::new(1, 1.0);
+        ai.set_memory_limit(Some(MemoryLimit::States(20)), eviction);
+        ai.set_play_steps(2000);
+        assert_eq!(ai.play(&b), TTTAddr(3), "{eviction:?}");
+    }
+}
@@ -0,0 +1,206 @@
+//! Integration test of the memory files of MctsAi
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use xoxo::{
+    core::{Board, Player},
+    game::{connect_four::C4Board, tictactoe::TTTBoard},
+    player::{merge_memory_files, read_memory_file, MctsAi, MemoryError},
+};
+
+/// A path in the temp dir that no other test uses
+fn temp_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("xoxo-{}-{name}.data", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(index_path(&path));
+    let _ = std::fs::remove_file(with_extension(&path, ".lock"));
+    path
+}
+
+/// `path` with `extension` added, as the knowledge store names the files it keeps next to the log
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// The index that the knowledge store keeps next to the log
+fn index_path(path: &Path) -> PathBuf {
+    with_extension(path, ".idx")
+}
+
+fn remove(path: &Path) {
+    std::fs::remove_file(path).unwrap();
+    let _ = std::fs::remove_file(index_path(path));
+    let _ = std::fs::remove_file(with_extension(path, ".lock"));
+}
+
+/// The number of first moves that an AI with the memory at `path` knows after a single step
+fn known_first_moves(path: &Path) -> usize {
+    let mut ai = MctsAi::<TTTBoard>::new(3, 1.0);
+    ai.load_memory(path).unwrap();
+    ai.set_play_steps(1);
+    ai.play(&TTTBoard::default());
+    ai.last_search().unwrap().root_moves.len()
+}
+
+/// An AI that has searched the first move of tic-tac-toe, and will save to `path`
+fn trained_ai(path: &PathBuf) -> MctsAi<TTTBoard> {
+    let mut ai = MctsAi::<TTTBoard>::new(1, 1.0);
+    ai.load_memory(path).unwrap();
+    ai.set_play_steps(300);
+    ai.play(&TTTBoard::default());
+    ai
+}
+
+#[test]
+fn round_trip() {
+    let path = temp_file("round-trip");
+    drop(trained_ai(&path));
+    let saved = std::fs::read(&path).unwrap();
+    assert!(saved.starts_with(b"XOXOMCTS"));
+    assert!(index_path(&path).exists());
+    // no temporary file is left behind
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".tmp");
+    assert!(!PathBuf::from(tmp).exists());
+
+    let mut ai = MctsAi::<TTTBoard>::new(2, 1.0);
+    ai.load_memory(&path).unwrap();
+    ai.save_memory().unwrap();
+    // nothing was searched, so nothing is appended
+    assert_eq!(std::fs::read(&path).unwrap().len(), saved.len());
+    drop(ai);
+    remove(&path);
+}
+
+#[test]
+fn statistics_are_learned_across_runs() {
+    let path = temp_file("learned");
+    // without memory, a single step only visits one first move
+    assert!(known_first_moves(&path) <= 1);
+    remove(&path);
+    drop(trained_ai(&path));
+    assert_eq!(known_first_moves(&path), 9);
+    // the index is only a cache, the log has all that is needed
+    std::fs::remove_file(index_path(&path)).unwrap();
+    assert_eq!(known_first_moves(&path), 9);
+    // and a second run adds to the first
+    let before = std::fs::metadata(&path).unwrap().len();
+    drop(trained_ai(&path));
+    assert!(std::fs::metadata(&path).unwrap().len() > before);
+    assert_eq!(known_first_moves(&path), 9);
+    remove(&path);
+}
+
+#[test]
+fn only_what_was_learned_since_the_last_save_is_written() {
+    let path = temp_file("dirty");
+    let mut ai = trained_ai(&path);
+    ai.save_memory().unwrap();
+    let saved = std::fs::metadata(&path).unwrap().len();
+    // saving again, or dropping, has nothing to add
+    ai.save_memory().unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), saved);
+    // a single step only teaches a few states, which are all that is written
+    ai.set_play_steps(1);
+    ai.play(&TTTBoard::default());
+    drop(ai);
+    let grown = std::fs::metadata(&path).unwrap().len() - saved;
+    assert!(grown > 0);
+    assert!(grown < saved / 10, "{grown} bytes appended to {saved}");
+    remove(&path);
+}
+
+#[test]
+fn damaged_files_are_reported_and_kept() {
+    let path = temp_file("damaged");
+    drop(trained_ai(&path));
+    let mut bytes = std::fs::read(&path).unwrap();
+    // the discount factor in the header
+    bytes[20] ^= 0xFF;
+    std::fs::write(&path, &bytes).unwrap();
+
+    let mut ai = MctsAi::<TTTBoard>::new(1, 1.0);
+    assert!(matches!(ai.load_memory(&path), Err(MemoryError::ChecksumMismatch)));
+    ai.play(&TTTBoard::default());
+    drop(ai);
+    // the AI did not save over the file it could not read
+    assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+    bytes.truncate(20);
+    std::fs::write(&path, &bytes).unwrap();
+    assert!(matches!(MctsAi::<TTTBoard>::new(1, 1.0).load_memory(&path), Err(MemoryError::Truncated)));
+    remove(&path);
+}
+
+#[test]
+fn other_games_are_refused() {
+    let path = temp_file("other-game");
+    drop(trained_ai(&path));
+    let mut ai = MctsAi::<C4Board>::new(1, 1.0);
+    match ai.load_memory(&path) {
+        Err(MemoryError::WrongGame { expected, found }) => {
+            assert_eq!(expected, C4Board::NAME);
+            assert_eq!(found, TTTBoard::NAME);
+        }
+        other => panic!("Expected WrongGame, got {other:?}"),
+    }
+    remove(&path);
+}
+
+#[test]
+fn memory_in_use_is_locked() {
+    let path = temp_file("locked");
+    let ai = trained_ai(&path);
+    let mut other = MctsAi::<TTTBoard>::new(2, 1.0);
+    assert!(matches!(other.load_memory(&path), Err(MemoryError::Locked)));
+    let target = temp_file("merge-target");
+    assert!(matches!(merge_memory_files::<TTTBoard>(&target, &[&path]), Err(MemoryError::Locked)));
+    drop(ai);
+    other.load_memory(&path).unwrap();
+    drop(other);
+    remove(&path);
+    remove(&target);
+}
+
+#[test]
+fn merged_files_know_what_each_learned() {
+    let (a, b, merged) = (temp_file("merge-a"), temp_file("merge-b"), temp_file("merged"));
+    drop(trained_ai(&a));
+    // b only knows the replies to the first move in the middle
+    let mut ai = MctsAi::<TTTBoard>::new(4, 1.0);
+    ai.load_memory(&b).unwrap();
+    ai.set_play_steps(300);
+    ai.play(&TTTBoard::from_str("    o    ").unwrap());
+    drop(ai);
+    assert!(known_first_moves(&b) <= 1);
+
+    let n_states = merge_memory_files::<TTTBoard>(&merged, &[&a, &b]).unwrap();
+    assert!(n_states > 100);
+    assert_eq!(known_first_moves(&merged), 9);
+    let mut ai = MctsAi::<TTTBoard>::new(3, 1.0);
+    ai.load_memory(&merged).unwrap();
+    ai.set_play_steps(1);
+    ai.play(&TTTBoard::from_str("    o    ").unwrap());
+    assert_eq!(ai.last_search().unwrap().root_moves.len(), 8);
+    drop(ai);
+    for path in [a, b, merged] {
+        remove(&path);
+    }
+}
+
+#[test]
+fn inspection_shows_what_was_learned() {
+    let path = temp_file("inspect");
+    drop(trained_ai(&path));
+    let states = read_memory_file::<TTTBoard>(&path, &TTTBoard::default()).unwrap();
+    // the first move was searched, so the start is the most visited state, and all the first moves lead somewhere
+    assert_eq!(states[0].state, TTTBoard::default());
+    assert_eq!(states[0].actions.len(), 9);
+    assert_eq!(states.iter().filter(|s| s.ply() == Some(1)).count(), 9);
+    let visits: f64 = states[0].actions.iter().map(|a| a.visits).sum();
+    assert_eq!(visits, states[0].visits);
+    assert!(matches!(read_memory_file::<TTTBoard>(temp_file("missing"), &TTTBoard::default()), Err(MemoryError::Io(_))));
+    remove(&path);
+}
@@ -0,0 +1,59 @@
+//! Integration test of MCTS with PUCT selection
+use std::{collections::HashMap, str::FromStr};
+
+use xoxo::{
+    core::Player,
+    game::tictactoe::{TTTAddr, TTTBoard},
+    player::{
+        mcts::prior::PriorPolicy,
+        mcts::rollout::UniformRollout,
+        MctsAi, MctsEngine, Selection,
+    },
+};
+
+/// A stand-in for a prior learned from earlier games: a table of weights for the cells, whatever the board
+struct TablePrior(HashMap<TTTAddr, f64>);
+
+impl PriorPolicy<TTTBoard> for TablePrior {
+    fn prior(&self, _s: &TTTBoard, a: &TTTAddr) -> f64 {
+        self.0.get(a).copied().unwrap_or(0.0)
+    }
+}
+
+fn puct_ai(engine: MctsEngine, selection: Selection<TTTBoard>, steps: usize) -> MctsAi<TTTBoard> {
+    let mut ai = MctsAi::with_policies(1, 1.5, selection, UniformRollout);
+    ai.set_engine(engine);
+    // the solver would prove the win in one without looking at the prior
+    ai.set_solver(false);
+    ai.set_play_steps(steps);
+    ai
+}
+
+/// Early on, PUCT spends its visits in proportion to the prior, so the most visited move is the one the prior favours.
+/// UCB1 would visit all nine openings once before it looked at anything else.
+#[test]
+fn visits_follow_a_skewed_prior() {
+    let b = TTTBoard::default();
+    for engine in [MctsEngine::QMap, MctsEngine::Tree] {
+        for favourite in [1, 2, 5, 9] {
+            let mut weights: HashMap<_, _> = (1..=9).map(|a| (TTTAddr(a), 1.0)).collect();
+            weights.insert(TTTAddr(favourite), 50.0);
+            let prior = TablePrior(weights);
+            let mut ai = puct_ai(engine, Selection::puct(prior), 30);
+            assert_eq!(ai.play(&b), TTTAddr(favourite), "{engine:?}");
+        }
+    }
+}
+
+#[test]
+fn learned_prior() {
+    let b = TTTBoard::from_str("oo xx    ").unwrap();
+    for engine in [MctsEngine::QMap, MctsEngine::Tree] {
+        // a good prior finds the win in a few steps
+        let good = TablePrior(HashMap::from([(TTTAddr(3), 10.0)]));
+        assert_eq!(puct_ai(engine, Selection::puct(good), 20).play(&b), TTTAddr(3), "{engine:?}");
+        // a bad one is overcome by the returns, given more steps
+        let bad = TablePrior(HashMap::from([(TTTAddr(9), 10.0), (TTTAddr(3), 0.1)]));
+        assert_eq!(puct_ai(engine, Selection::puct(bad), 2000).play(&b), TTTAddr(3), "{engine:?}");
+    }
+}
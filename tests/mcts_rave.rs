@@ -0,0 +1,34 @@
+//! Integration test of MCTS with RAVE
+use xoxo::{
+    core::{Board, Player},
+    game::connect_four::C4Board,
+    player::MctsAi,
+};
+
+/// An AI that gets only a few steps, so that it has to make the most of each simulation
+fn few_steps_ai(seed: u64, rave: Option<f64>) -> MctsAi<C4Board> {
+    let mut ai = MctsAi::<C4Board>::new(seed, 1.0);
+    ai.set_rave(rave);
+    // the solver would prove the win in one step
+    ai.set_solver(false);
+    ai.set_play_steps(10);
+    ai
+}
+
+/// With 7 moves and 10 steps, plain MCTS tries most moves once and has to guess.
+/// RAVE learns about the winning move from the simulations that play it later on, so it finds it far more often.
+#[test]
+fn finds_winning_move_in_fewer_steps() {
+    let mut b = C4Board::default();
+    for column in [0, 1, 0, 1, 0, 1] {
+        b.place_mark(column, b.current_player());
+    }
+    let n_wins = |rave| {
+        (0..100)
+            .filter(|&seed| few_steps_ai(seed, rave).play(&b) == 0)
+            .count()
+    };
+    let plain = n_wins(None);
+    let rave = n_wins(Some(300.0));
+    assert!(rave >= plain + 20, "RAVE found the win {rave} times out of 100, plain MCTS {plain} times");
+}
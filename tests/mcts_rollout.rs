@@ -0,0 +1,61 @@
+//! Integration test of the rollout policies of MctsAi
+use std::str::FromStr;
+
+use rand::{rngs::StdRng, SeedableRng};
+use xoxo::{
+    core::{Board, PlayerMark},
+    game::tictactoe::{TTTAddr, TTTBoard},
+    player::{
+        mcts::rollout::{DepthLimitedRollout, RolloutPolicy, UniformRollout, WinBlockRollout},
+        ttt_heuristic,
+    },
+};
+
+/// The mean return of `n` rollouts from `b`, as seen by the player to move
+fn mean_return(policy: &dyn RolloutPolicy<TTTBoard>, b: &TTTBoard, n: usize) -> f64 {
+    let mut rng = StdRng::seed_from_u64(1);
+    (0..n).map(|_| policy.rollout(*b, &mut rng, None)).sum::<f64>() / n as f64
+}
+
+/// The return of one rollout from `b`, and the moves it played
+fn play_out(policy: &dyn RolloutPolicy<TTTBoard>, b: &TTTBoard) -> (f64, Vec<TTTAddr>) {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut played = vec![];
+    let g_return = policy.rollout(*b, &mut rng, Some(&mut played));
+    (g_return, played)
+}
+
+#[test]
+fn win_block_takes_the_win_that_uniform_misses() {
+    // O wins at 3, but a random move gives X the chance to win at 6 first
+    let b = TTTBoard::from_str("oo xx    ").unwrap();
+    assert_eq!(play_out(&WinBlockRollout, &b), (1.0, vec![TTTAddr(3)]));
+    let uniform = mean_return(&UniformRollout, &b, 500);
+    assert!(uniform < 0.5, "uniform rollouts return {uniform} on average");
+}
+
+#[test]
+fn win_block_blocks_where_uniform_loses() {
+    // X has to block at 3, or O wins with the next move
+    let b = TTTBoard::from_str("oo  x    ").unwrap();
+    assert_eq!(b.current_player(), PlayerMark::Cross);
+    let win_block = mean_return(&WinBlockRollout, &b, 500);
+    let uniform = mean_return(&UniformRollout, &b, 500);
+    assert!(win_block > uniform + 0.2, "win/block {win_block}, uniform {uniform}");
+}
+
+#[test]
+fn depth_limited_scores_the_cutoff_with_the_heuristic() {
+    // O wins with the last empty cell
+    let b = TTTBoard::from_str("oxoxoxxo ").unwrap();
+    let policy = |depth| DepthLimitedRollout {
+        heuristic: ttt_heuristic,
+        depth,
+        scale: 50.0,
+    };
+    // cut off right away, so the heuristic scores the board as it is
+    let expected = (ttt_heuristic(b.current_player(), &b) / 50.0).tanh();
+    assert_eq!(play_out(&policy(0), &b), (expected, vec![]));
+    // the game ends before the cutoff, so the return is the win
+    assert_eq!(play_out(&policy(3), &b), (1.0, vec![TTTAddr(9)]));
+}
@@ -0,0 +1,79 @@
+//! Integration test of MCTS-Solver, with both engines
+use std::str::FromStr;
+
+use xoxo::{
+    core::{Board, Outcome, Player},
+    game::{
+        connect_four::C4Board,
+        tictactoe::{TTTAddr, TTTBoard},
+    },
+    player::{mcts::Mdp, MctsAi, MctsEngine},
+};
+
+fn solver_ai<B: Mdp>(seed: u64, engine: MctsEngine, steps: usize) -> MctsAi<B> {
+    let mut ai = MctsAi::<B>::new(seed, 1.0);
+    ai.set_engine(engine);
+    ai.set_play_steps(steps);
+    ai
+}
+
+const ENGINES: [MctsEngine; 2] = [MctsEngine::QMap, MctsEngine::Tree];
+
+#[test]
+fn never_misses_a_mate_in_one() {
+    // O can win in column 3, X threatens nothing yet
+    let mut b = C4Board::default();
+    for col in [3, 0, 3, 0, 3, 6] {
+        b.place_mark(col, b.current_player());
+    }
+    for engine in ENGINES {
+        for seed in 0..10 {
+            // one step per move is enough to try them all and find the win
+            let mut ai = solver_ai::<C4Board>(seed, engine, 7);
+            assert_eq!(ai.play(&b), 3, "{engine:?} seed {seed}");
+            let info = ai.last_search().unwrap();
+            assert_eq!(info.outcome, Some(Outcome::Win));
+            assert!(info.proven_moves.contains(&(3, Outcome::Win)));
+            assert!(info.to_string().contains("proven win"));
+        }
+    }
+}
+
+#[test]
+fn proves_a_loss() {
+    // X has three ways to win, O can only block one
+    let b = TTTBoard::from_str("xooxx  o ").unwrap();
+    for engine in ENGINES {
+        let mut ai = solver_ai::<TTTBoard>(1, engine, 1000);
+        ai.play(&b);
+        let info = ai.last_search().unwrap();
+        assert_eq!(info.outcome, Some(Outcome::Loss), "{engine:?}");
+        assert!(info.proven_moves.iter().all(|&(_, o)| o == Outcome::Loss));
+        // the search stops once the root is proven
+        assert!(info.nodes < 1000, "{engine:?} took {} steps", info.nodes);
+    }
+}
+
+#[test]
+fn solves_tic_tac_toe() {
+    for engine in ENGINES {
+        let mut ai = solver_ai::<TTTBoard>(3, engine, 200_000);
+        ai.play(&TTTBoard::default());
+        let info = ai.last_search().unwrap();
+        assert_eq!(info.outcome, Some(Outcome::Draw), "{engine:?} after {} steps", info.nodes);
+    }
+}
+
+#[test]
+fn without_the_solver_nothing_is_proven() {
+    let b = TTTBoard::from_str("oo xx    ").unwrap();
+    for engine in ENGINES {
+        let mut ai = solver_ai::<TTTBoard>(1, engine, 1000);
+        ai.set_solver(false);
+        assert_eq!(ai.play(&b), TTTAddr(3));
+        let info = ai.last_search().unwrap();
+        assert_eq!(info.outcome, None);
+        assert!(info.proven_moves.is_empty());
+        assert_eq!(info.nodes, 1000);
+    }
+}
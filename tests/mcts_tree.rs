@@ -0,0 +1,50 @@
+//! Integration test of MCTS with the tree engine
+use xoxo::{
+    core::{Board, Player},
+    game::{
+        connect_four::C4Board,
+        tictactoe::{TTTAddr, TTTBoard},
+    },
+    player::{mcts::Mdp, MctsAi, MctsEngine},
+};
+
+fn tree_ai<B: Mdp>(seed: u64) -> MctsAi<B> {
+    let mut ai = MctsAi::<B>::new(seed, 1.0);
+    ai.set_engine(MctsEngine::Tree);
+    ai.set_play_steps(5000);
+    ai
+}
+
+#[test]
+fn keeps_the_tree_between_moves() {
+    let mut ai = tree_ai::<C4Board>(7);
+    let mut b = C4Board::default();
+    let m = ai.play(&b);
+    let first_tree = ai.n_tree_nodes();
+    assert!(first_tree > 1000);
+    // the opponent replies as expected, so the subtree is a big one
+    let reply = ai.last_search().unwrap().principal_variation[1];
+    b.place_mark(m, b.current_player());
+    b.place_mark(reply, b.current_player());
+    // with a single step, the tree is what was kept plus at most one node
+    ai.set_play_steps(1);
+    ai.play(&b);
+    let kept = ai.n_tree_nodes() - 1;
+    assert!(kept > 10, "only {kept} nodes kept");
+    assert!(kept < first_tree);
+}
+
+#[test]
+fn starts_over_in_another_game() {
+    let mut ai = tree_ai::<TTTBoard>(1);
+    let mut b = TTTBoard::default();
+    let m = ai.play(&b);
+    b.place_mark(m, b.current_player());
+    b.place_mark(if m == TTTAddr(1) { TTTAddr(2) } else { TTTAddr(1) }, b.current_player());
+    ai.set_play_steps(1);
+    ai.play(&b);
+    assert!(ai.n_tree_nodes() > 2);
+    // a new game can't be reached from the old root, so nothing is kept
+    ai.play(&TTTBoard::default());
+    assert!(ai.n_tree_nodes() <= 2);
+}
@@ -1,19 +1,26 @@
 //! Integration test minimax AI with TTT
 use std::str::FromStr;
 
-use xoxo::{core::{Player, PlayerMark}, game::tictactoe::{TTTAddr, TTTBoard}, player::{ttt_heuristic, MinMaxAi}};
+use xoxo::{core::Player, game::tictactoe::{TTTAddr, TTTBoard}, player::{ttt_heuristic, MinMaxAi}};
 
 #[test]
 fn can_find_winning_move() {
     let b = TTTBoard::from_str("   xx    ").unwrap();
-    let mut ai = MinMaxAi::<TTTBoard>::new(PlayerMark::Cross, ttt_heuristic, 10);
+    let mut ai = MinMaxAi::<TTTBoard>::new(ttt_heuristic, 10);
     let action: TTTAddr = ai.play(&b);
     assert_eq!(action, TTTAddr(6))
 }
 #[test]
 fn can_block_winning_move() {
     let b = TTTBoard::from_str("oo  x    ").unwrap();
-    let mut ai = MinMaxAi::<TTTBoard>::new(PlayerMark::Cross, ttt_heuristic, 10);
+    let mut ai = MinMaxAi::<TTTBoard>::new(ttt_heuristic, 10);
     let action = ai.play(&b);
     assert_eq!(action, TTTAddr(3))
-}
\ No newline at end of file
+}
+#[test]
+fn can_find_winning_move_on_budget() {
+    let b = TTTBoard::from_str("   xx    ").unwrap();
+    let mut ai = MinMaxAi::<TTTBoard>::new(ttt_heuristic, 10);
+    let action = ai.play_with_budget(&b, std::time::Duration::from_secs(1));
+    assert_eq!(action, TTTAddr(6))
+}
@@ -0,0 +1,55 @@
+//! Integration test that illegal moves are rejected with the right error on all boards
+use std::str::FromStr;
+
+use xoxo::{
+    core::{Board, MoveError, PlayerMark},
+    game::{
+        connect_four::C4Board,
+        tictactoe::{TTTAddr, TTTBoard},
+        ultimate_ttt::{Action, UTTTBoard},
+    },
+};
+
+#[test]
+fn ttt_rejects_illegal_moves() {
+    let mut b = TTTBoard::from_str("o   x    ").unwrap();
+    assert_eq!(b.try_place_mark(TTTAddr(0), PlayerMark::Naught), Err(MoveError::OutOfRange));
+    assert_eq!(b.try_place_mark(TTTAddr(10), PlayerMark::Naught), Err(MoveError::OutOfRange));
+    assert_eq!(b.try_place_mark(TTTAddr(5), PlayerMark::Naught), Err(MoveError::Occupied));
+    assert_eq!(b.try_place_mark(TTTAddr(2), PlayerMark::Cross), Err(MoveError::WrongPlayer));
+    let before = b;
+    assert_eq!(b.try_place_mark(TTTAddr(2), PlayerMark::Naught), Ok(()));
+    assert_ne!(b, before);
+
+    let mut won = TTTBoard::from_str("ooox x   ").unwrap();
+    assert_eq!(won.try_place_mark(TTTAddr(9), PlayerMark::Cross), Err(MoveError::GameOver));
+}
+
+#[test]
+fn c4_rejects_illegal_moves() {
+    let mut b = C4Board::default();
+    assert_eq!(b.try_place_mark(7, PlayerMark::Naught), Err(MoveError::OutOfRange));
+    assert_eq!(b.try_place_mark(0, PlayerMark::Cross), Err(MoveError::WrongPlayer));
+    for _ in 0..6 {
+        b.try_place_mark(0, b.current_player()).unwrap();
+    }
+    assert_eq!(b.try_place_mark(0, b.current_player()), Err(MoveError::Occupied));
+    for col in [1, 2, 1, 2, 1, 2, 1] {
+        b.try_place_mark(col, b.current_player()).unwrap();
+    }
+    assert_eq!(b.try_place_mark(3, b.current_player()), Err(MoveError::GameOver));
+}
+
+#[test]
+fn uttt_rejects_illegal_moves() {
+    let a = |i, j, k, l| Action::try_from((i, j, k, l)).unwrap();
+    let mut b = UTTTBoard::default();
+    b.try_place_mark(a(1, 1, 0, 2), PlayerMark::Naught).unwrap();
+    // must now play in the top right sub-board
+    assert_eq!(b.try_place_mark(a(1, 1, 1, 1), PlayerMark::Cross), Err(MoveError::WrongSubBoard));
+    assert_eq!(b.try_place_mark(a(0, 2, 1, 1), PlayerMark::Naught), Err(MoveError::WrongPlayer));
+    b.try_place_mark(a(0, 2, 1, 1), PlayerMark::Cross).unwrap();
+    // back to the middle, where the first marker is
+    assert_eq!(b.try_place_mark(a(1, 1, 0, 2), PlayerMark::Naught), Err(MoveError::Occupied));
+    assert!(Action::try_from((3, 0, 0, 0)).is_err());
+}
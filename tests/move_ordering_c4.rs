@@ -0,0 +1,69 @@
+//! Integration test that move ordering makes alpha-beta prune more, on fixed Connect Four positions
+use xoxo::{
+    core::{Board, Player},
+    game::connect_four::C4Board,
+    player::{c4_heuristic, c4_move_prior, ABAi},
+};
+
+fn position(moves: &[usize]) -> C4Board {
+    let mut b = C4Board::default();
+    for &col in moves {
+        b.place_mark(col, b.current_player());
+    }
+    b
+}
+
+/// Leafs evaluated by a depth 6 search, with the given ordering options
+fn leafs(b: &C4Board, prior: bool, killers: bool, history: bool) -> usize {
+    let mut ai = ABAi::<C4Board>::new(c4_heuristic, 6);
+    if prior {
+        ai.set_move_prior(Some(c4_move_prior));
+    }
+    ai.set_killer_moves(killers);
+    ai.set_history_heuristic(history);
+    ai.play(b);
+    ai.n_leafs_evaluated()
+}
+
+const POSITIONS: [&[usize]; 5] = [&[], &[3, 3, 2, 4], &[0, 6, 1, 5, 3], &[3, 2, 3, 4, 1], &[3, 3, 3, 3, 2, 2]];
+
+#[test]
+fn ordering_prunes_more() {
+    for moves in POSITIONS {
+        let b = position(moves);
+        let plain = leafs(&b, false, false, false);
+        let ordered = leafs(&b, true, true, true);
+        assert!(ordered < plain, "position {moves:?}: {ordered} leafs, {plain} without ordering");
+    }
+}
+
+/// Each technique on its own can lose on some position, but should win over all of them
+#[test]
+fn each_technique_helps() {
+    let total = |prior, killers, history| -> usize {
+        POSITIONS.iter().map(|moves| leafs(&position(moves), prior, killers, history)).sum()
+    };
+    let plain = total(false, false, false);
+    for (prior, killers, history) in [(true, false, false), (false, true, false), (false, false, true)] {
+        let ordered = total(prior, killers, history);
+        assert!(
+            ordered < plain,
+            "prior {prior}, killers {killers}, history {history}: {ordered} leafs, {plain} without ordering"
+        );
+    }
+}
+
+#[test]
+fn ordering_with_transposition_table() {
+    let b = position(&[3, 3, 2, 4]);
+    let mut tt_only = ABAi::<C4Board>::new(c4_heuristic, 6);
+    tt_only.set_transposition_table_size(1 << 16);
+    tt_only.play(&b);
+    let mut all = ABAi::<C4Board>::new(c4_heuristic, 6);
+    all.set_transposition_table_size(1 << 16);
+    all.set_move_prior(Some(c4_move_prior));
+    all.set_killer_moves(true);
+    all.set_history_heuristic(true);
+    all.play(&b);
+    assert!(all.n_leafs_evaluated() < tt_only.n_leafs_evaluated());
+}
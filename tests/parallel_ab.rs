@@ -0,0 +1,75 @@
+//! Integration test that the multi-threaded alpha-beta search agrees with the single-threaded one
+use std::time::Duration;
+
+use xoxo::{
+    core::{Board, SearchInfo},
+    game::{connect_four::C4Board, ultimate_ttt::UTTTBoard},
+    player::{c4_heuristic, c4_move_prior, uttt_heuristic, ABAi},
+};
+
+fn position<B: Board>(moves: &[B::Coordinate]) -> B {
+    let mut b = B::default();
+    for &m in moves {
+        b.place_mark(m, b.current_player());
+    }
+    b
+}
+
+fn c4_search(b: &C4Board, threads: usize, tuned: bool, exact: bool) -> SearchInfo<usize> {
+    let mut ai = ABAi::<C4Board>::new(c4_heuristic, 5);
+    ai.set_exact_root_scores(exact);
+    if tuned {
+        ai.set_transposition_table_size(1 << 14);
+        ai.set_move_prior(Some(c4_move_prior));
+        ai.set_killer_moves(true);
+        ai.set_history_heuristic(true);
+    }
+    ai.set_threads(threads);
+    ai.search(b)
+}
+
+#[test]
+fn same_result_as_sequential_c4() {
+    for moves in [&[][..], &[3, 3, 2, 4], &[0, 6, 1, 5, 3], &[3, 3, 3, 3, 2, 2]] {
+        let b = position::<C4Board>(moves);
+        for (tuned, exact) in [(false, false), (false, true), (true, false), (true, true)] {
+            let seq = c4_search(&b, 1, tuned, exact);
+            for threads in [2, 3, 4] {
+                let par = c4_search(&b, threads, tuned, exact);
+                assert_eq!(par.best_move, seq.best_move, "position {moves:?}, {threads} threads");
+                assert_eq!(par.score, seq.score);
+                // the other moves are only bounded by the best move that each thread found
+                if exact {
+                    assert_eq!(par.root_moves, seq.root_moves);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn same_result_as_sequential_uttt() {
+    let b = UTTTBoard::default();
+    let mut ai = ABAi::<UTTTBoard>::new(uttt_heuristic, 2);
+    ai.set_exact_root_scores(true);
+    let seq = ai.search(&b);
+    let mut ai = ABAi::<UTTTBoard>::new(uttt_heuristic, 2);
+    ai.set_exact_root_scores(true);
+    ai.set_threads(4);
+    let par = ai.search(&b);
+    assert_eq!(par.best_move, seq.best_move);
+    assert_eq!(par.root_moves, seq.root_moves);
+    assert_eq!(par.nodes, seq.nodes);
+}
+
+#[test]
+fn threads_stop_on_budget() {
+    let b = C4Board::default();
+    let mut ai = ABAi::<C4Board>::new(c4_heuristic, 20);
+    ai.set_threads(4);
+    let t0 = std::time::Instant::now();
+    let info = ai.search_with_budget(&b, Duration::from_millis(50));
+    assert!(t0.elapsed() < Duration::from_millis(500));
+    assert!(info.depth >= 1);
+    assert!(info.depth < 21);
+}
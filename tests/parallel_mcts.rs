@@ -0,0 +1,62 @@
+//! Integration test of MCTS searching on more than one thread
+use std::str::FromStr;
+
+use xoxo::{
+    core::{Outcome, Player},
+    game::tictactoe::{TTTAddr, TTTBoard},
+    player::{MctsAi, MctsEngine, Parallelism},
+};
+
+fn parallel_ai(seed: u64, engine: MctsEngine, parallelism: Parallelism) -> MctsAi<TTTBoard> {
+    let mut ai = MctsAi::<TTTBoard>::new(seed, 1.0);
+    ai.set_engine(engine);
+    ai.set_parallelism(parallelism);
+    ai.set_threads(4);
+    ai.set_play_steps(5000);
+    ai
+}
+
+/// A thread counts its visit and puts its virtual loss on a move on the way down,
+/// so the threads that search at the same time take different moves instead of piling onto the same one.
+#[test]
+fn threads_spread_over_the_root_moves() {
+    let b = TTTBoard::default();
+    for seed in 0..5 {
+        let mut ai = parallel_ai(seed, MctsEngine::Tree, Parallelism::SharedTree);
+        ai.set_play_steps(9);
+        ai.play(&b);
+        let searched = ai.last_search().unwrap().root_moves.len();
+        assert_eq!(searched, 9, "seed {seed}");
+    }
+}
+
+#[test]
+fn shared_tree_takes_the_steps_over_all_threads() {
+    let b = TTTBoard::default();
+    let mut ai = parallel_ai(3, MctsEngine::Tree, Parallelism::SharedTree);
+    ai.set_play_steps(1000);
+    ai.play(&b);
+    // every step adds at most one node to the root.
+    // The threads that are still busy when the budget runs out finish their steps, so there can be a few more.
+    let nodes = ai.n_tree_nodes();
+    assert!(nodes <= 1 + 1000 + 3, "{nodes} nodes");
+    assert!(nodes > 500, "{nodes} nodes");
+}
+
+/// The threads of root parallelism search trees of their own. What the solver proves in any of them is merged
+/// with their statistics, so the mate in one is reported as a proven win whichever thread found it.
+#[test]
+fn root_parallelism_keeps_the_proofs_of_every_thread() {
+    // O wins at 3
+    let b = TTTBoard::from_str("oo xx    ").unwrap();
+    for engine in [MctsEngine::QMap, MctsEngine::Tree] {
+        for seed in 0..5 {
+            let mut ai = parallel_ai(seed, engine, Parallelism::Root);
+            ai.set_play_steps(40);
+            assert_eq!(ai.play(&b), TTTAddr(3), "{engine:?} seed {seed}");
+            let info = ai.last_search().unwrap();
+            assert_eq!(info.outcome, Some(Outcome::Win), "{engine:?} seed {seed}");
+            assert!(info.proven_moves.contains(&(TTTAddr(3), Outcome::Win)));
+        }
+    }
+}
@@ -1,19 +1,37 @@
 //! Integration test pruning minimax AI with TTT
 use std::str::FromStr;
 
-use xoxo::{core::{Player, PlayerMark}, game::tictactoe::{TTTAddr, TTTBoard}, player::{ttt_heuristic, ABAi}};
+use xoxo::{core::Player, game::tictactoe::{TTTAddr, TTTBoard}, player::{ttt_heuristic, ABAi}};
 
 #[test]
 fn can_find_winning_move() {
     let b = TTTBoard::from_str("   xx    ").unwrap();
-    let mut ai = ABAi::<TTTBoard>::new(PlayerMark::Cross, ttt_heuristic, 10);
+    let mut ai = ABAi::<TTTBoard>::new(ttt_heuristic, 10);
     let action: TTTAddr = ai.play(&b);
     assert_eq!(action, TTTAddr(6))
 }
 #[test]
 fn can_block_winning_move() {
     let b = TTTBoard::from_str("oo  x    ").unwrap();
-    let mut ai = ABAi::<TTTBoard>::new(PlayerMark::Cross, ttt_heuristic, 10);
+    let mut ai = ABAi::<TTTBoard>::new(ttt_heuristic, 10);
     let action = ai.play(&b);
     assert_eq!(action, TTTAddr(3))
-}
\ No newline at end of file
+}
+#[test]
+fn can_find_winning_move_on_budget() {
+    let b = TTTBoard::from_str("   xx    ").unwrap();
+    let mut ai = ABAi::<TTTBoard>::new(ttt_heuristic, 10);
+    let action = ai.play_with_budget(&b, std::time::Duration::from_secs(1));
+    assert_eq!(action, TTTAddr(6))
+}
+#[test]
+fn transposition_table_keeps_the_right_moves() {
+    let b = TTTBoard::from_str("   xx    ").unwrap();
+    let mut ai = ABAi::<TTTBoard>::new(ttt_heuristic, 10);
+    ai.set_transposition_table_size(1 << 12);
+    assert_eq!(ai.play(&b), TTTAddr(6));
+    let b = TTTBoard::from_str("oo  x    ").unwrap();
+    assert_eq!(ai.play(&b), TTTAddr(3));
+    // the table is kept between moves, so a second search must agree
+    assert_eq!(ai.play(&b), TTTAddr(3));
+}
@@ -0,0 +1,103 @@
+//! Integration test of what the search AIs report about their searches
+use std::{str::FromStr, time::Duration};
+
+use xoxo::{
+    core::{Board, Player, RootMove, ScoreBound, SearchInfo},
+    game::{
+        connect_four::C4Board,
+        tictactoe::{TTTAddr, TTTBoard},
+    },
+    player::{c4_heuristic, ttt_heuristic, ABAi, MinMaxAi},
+};
+
+fn check_ranking<C: Copy + PartialEq + std::fmt::Debug>(info: &SearchInfo<C>, n_moves: usize) {
+    assert_eq!(info.root_moves.len(), n_moves);
+    let best = RootMove {
+        coordinate: info.best_move,
+        score: info.score,
+        bound: ScoreBound::Exact,
+    };
+    assert_eq!(info.root_moves[0], best);
+    assert!(info.root_moves.windows(2).all(|w| w[0].score >= w[1].score));
+    assert_eq!(info.principal_variation[0], info.best_move);
+    assert!(info.nodes > 0);
+}
+
+#[test]
+fn reports_the_win() {
+    let b = TTTBoard::from_str("oo xx    ").unwrap();
+    let mut ai = ABAi::<TTTBoard>::new(ttt_heuristic, 10);
+    let info = ai.search(&b);
+    assert_eq!(info.best_move, TTTAddr(3));
+    assert!(info.score > 0.0);
+    // the game is over after the winning move
+    assert_eq!(info.principal_variation, vec![TTTAddr(3)]);
+    check_ranking(&info, 5);
+    assert_eq!(ai.last_search(), Some(&info));
+}
+
+/// Play out the principal variation, and check that it leads to the reported score
+fn check_pv(b: &C4Board, info: &SearchInfo<usize>, depth: usize) {
+    assert_eq!(info.depth, depth);
+    assert_eq!(info.principal_variation.len(), depth);
+    let mut end = *b;
+    for &m in &info.principal_variation {
+        end.try_place_mark(m, end.current_player()).unwrap();
+    }
+    assert_eq!(c4_heuristic(b.current_player(), &end), info.score);
+}
+
+#[test]
+fn principal_variation_leads_to_the_score() {
+    let mut b = C4Board::default();
+    for col in [3, 3, 2, 4] {
+        b.place_mark(col, b.current_player());
+    }
+    let ab = ABAi::<C4Board>::new(c4_heuristic, 4).search(&b);
+    let mm = MinMaxAi::<C4Board>::new(c4_heuristic, 4).search(&b);
+    check_pv(&b, &ab, 5);
+    check_pv(&b, &mm, 5);
+    check_ranking(&ab, 7);
+    check_ranking(&mm, 7);
+    assert_eq!(ab.score, mm.score);
+    assert!(ab.nodes < mm.nodes);
+}
+
+#[test]
+fn root_moves_are_pruned_unless_exact_scores_are_asked_for() {
+    let mut b = C4Board::default();
+    for col in [3, 3, 2, 4] {
+        b.place_mark(col, b.current_player());
+    }
+    let pruned = ABAi::<C4Board>::new(c4_heuristic, 4).search(&b);
+    let mut ai = ABAi::<C4Board>::new(c4_heuristic, 4);
+    ai.set_exact_root_scores(true);
+    let exact = ai.search(&b);
+    assert_eq!(pruned.best_move, exact.best_move);
+    assert_eq!(pruned.score, exact.score);
+    assert!(pruned.nodes < exact.nodes);
+    // the other moves are no better than their exact scores, and say so when they are only bounds
+    for m in &pruned.root_moves[1..] {
+        let e = exact.root_moves.iter().find(|e| e.coordinate == m.coordinate).unwrap();
+        match m.bound {
+            ScoreBound::Exact => assert_eq!(e.score, m.score, "move {}", m.coordinate),
+            ScoreBound::Upper => assert!(e.score <= m.score, "move {}: {} > {}", m.coordinate, e.score, m.score),
+        }
+    }
+    assert!(pruned.root_moves.iter().any(|m| m.bound == ScoreBound::Upper));
+    assert!(exact.root_moves.iter().all(|m| m.bound == ScoreBound::Exact));
+    check_ranking(&pruned, 7);
+    check_ranking(&exact, 7);
+}
+
+#[test]
+fn budget_search_reports_the_deepest_iteration() {
+    let b = C4Board::default();
+    let mut ai = MinMaxAi::<C4Board>::new(c4_heuristic, 3);
+    let info = ai.search_with_budget(&b, Duration::from_secs(10));
+    assert_eq!(info.depth, 4);
+    check_ranking(&info, 7);
+    let mut ai = ABAi::<C4Board>::new(c4_heuristic, 3);
+    let played = ai.play_with_budget(&b, Duration::from_secs(10));
+    assert_eq!(Player::last_search(&ai).map(|i| i.best_move), Some(played));
+}
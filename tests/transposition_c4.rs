@@ -0,0 +1,44 @@
+//! Integration test of the alpha-beta transposition table with Connect Four
+use xoxo::{
+    core::{Board, Player, PlayerMark},
+    game::connect_four::C4Board,
+    player::{c4_heuristic, ABAi},
+};
+
+fn position() -> C4Board {
+    let mut b = C4Board::default();
+    for col in [3, 3, 2, 4] {
+        b.place_mark(col, b.current_player());
+    }
+    b
+}
+
+#[test]
+fn transposition_table_saves_work() {
+    let b = position();
+    let mut plain = ABAi::<C4Board>::new(c4_heuristic, 6);
+    let mut with_tt = ABAi::<C4Board>::new(c4_heuristic, 6);
+    with_tt.set_transposition_table_size(1 << 16);
+    plain.play(&b);
+    with_tt.play(&b);
+    assert!(
+        with_tt.n_leafs_evaluated() < plain.n_leafs_evaluated(),
+        "{} leafs with the table, {} without",
+        with_tt.n_leafs_evaluated(),
+        plain.n_leafs_evaluated()
+    );
+}
+
+#[test]
+fn transposition_table_finds_the_win() {
+    // naught has three in a row on the bottom, and can win in column 0 or 4
+    let mut b = C4Board::default();
+    for col in [1, 1, 2, 2, 3, 3] {
+        b.place_mark(col, b.current_player());
+    }
+    assert_eq!(b.current_player(), PlayerMark::Naught);
+    let mut ai = ABAi::<C4Board>::new(c4_heuristic, 6);
+    ai.set_transposition_table_size(1 << 16);
+    let m = ai.play(&b);
+    assert!(m == 0 || m == 4, "played {m}");
+}
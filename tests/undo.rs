@@ -0,0 +1,67 @@
+//! Integration test make/unmake of moves on all boards
+use std::str::FromStr;
+
+use xoxo::{
+    core::{Board, GameStatus},
+    game::{
+        connect_four::C4Board,
+        tictactoe::{TTTAddr, TTTBoard},
+        ultimate_ttt::{Action, UTTTBoard},
+    },
+};
+
+/// Play the moves one by one, then take them all back and check that every intermediate board is restored
+fn check_unmake<B: Board + Clone + std::fmt::Debug>(start: B, moves: &[B::Coordinate]) {
+    let mut board = start.clone();
+    let mut history = vec![];
+    for &m in moves {
+        let before = board.clone();
+        let undo = board.make_move(m, board.current_player());
+        history.push((before, undo));
+    }
+    while let Some((before, undo)) = history.pop() {
+        board.unmake_move(undo);
+        assert_eq!(board, before);
+        assert_eq!(board.game_status(), before.game_status());
+        assert_eq!(board.current_player(), before.current_player());
+    }
+    assert_eq!(board, start);
+}
+
+#[test]
+fn ttt_unmake_winning_move() {
+    let b = TTTBoard::from_str("oo xx    ").unwrap();
+    let mut b2 = b;
+    let undo = b2.make_move(TTTAddr(3), b2.current_player());
+    assert!(matches!(b2.game_status(), GameStatus::Won(_)));
+    b2.unmake_move(undo);
+    assert_eq!(b2, b);
+    assert_eq!(b2.game_status(), GameStatus::Undecided);
+    check_unmake(TTTBoard::default(), &[TTTAddr(5), TTTAddr(1), TTTAddr(9)]);
+}
+
+#[test]
+fn c4_unmake_sequence() {
+    check_unmake(C4Board::default(), &[3, 3, 4, 2, 5, 6, 6]);
+}
+
+#[test]
+fn uttt_unmake_sub_board_win() {
+    let a = |i, j, k, l| Action::try_from((i, j, k, l)).unwrap();
+    // X takes the top left sub-board along its anti-diagonal with the last move
+    let moves = [
+        a(0, 0, 0, 0),
+        a(0, 0, 1, 1),
+        a(1, 1, 0, 0),
+        a(0, 0, 1, 0),
+        a(1, 0, 0, 0),
+        a(0, 0, 2, 2),
+        a(2, 2, 0, 0),
+        a(0, 0, 0, 1),
+        a(0, 1, 0, 0),
+        a(0, 0, 2, 0),
+        a(2, 0, 0, 0),
+        a(0, 0, 0, 2),
+    ];
+    check_unmake(UTTTBoard::default(), &moves);
+}